@@ -251,7 +251,7 @@ class AlertResource(SeverityResource):
             "message": SeverityResource.ALL_FILTER_STR,
             "active": SeverityResource.ALL_FILTER_BOOL,
             "dismissed": SeverityResource.ALL_FILTER_BOOL,
-            "id": SeverityResource.ALL_FILTER_INT,
+            "id": SeverityResource.ALL_FILTER_INT + ["in"],
             "severity": SeverityResource.ALL_FILTER_ENUMERATION,
             "created_at": SeverityResource.ALL_FILTER_DATE,
             "alert_type": SeverityResource.ALL_FILTER_ENUMERATION,
@@ -458,13 +458,40 @@ class OstPoolResource(ChromaModelResource):
         excludes = ["not_deleted"]
         ordering = ["filesystem", "name"]
         list_allowed_methods = ["get", "delete", "put", "post"]
-        detail_allowed_methods = ["get", "delete", "post"]
+        detail_allowed_methods = ["get", "delete", "post", "put"]
         filtering = {"filesystem": ["exact"], "name": ["exact"], "id": ["exact"]}
 
+    def _check_osts(self, request, filesystem, names):
+        """
+        Reject OST names that are not in the filesystem, rather than silently dropping them.
+        """
+        found = set(ManagedOst.objects.filter(filesystem=filesystem, name__in=names).values_list("name", flat=True))
+        unknown = sorted(set(names) - found)
+
+        if unknown:
+            raise custom_response(
+                self,
+                request,
+                http.HttpBadRequest,
+                {"osts": ["OST {} not found in filesystem {}".format(x, filesystem.name) for x in unknown]},
+            )
+
     @validate
     def obj_create(self, bundle, **kwargs):
         request = bundle.request
 
+        try:
+            filesystem = ManagedFilesystem.objects.get(name=bundle.data["filesystem"])
+        except ManagedFilesystem.DoesNotExist:
+            raise custom_response(
+                self,
+                request,
+                http.HttpBadRequest,
+                {"filesystem": ["Filesystem {} not found".format(bundle.data["filesystem"])]},
+            )
+
+        self._check_osts(request, filesystem, bundle.data.get("osts", []))
+
         ostpool_id, command_id = JobSchedulerClient.create_ostpool(bundle.data)
         command = Command.objects.get(pk=command_id)
 
@@ -478,7 +505,13 @@ class OstPoolResource(ChromaModelResource):
         except ObjectDoesNotExist:
             raise NotFound("A model instance matching the provided arguments could not be found.")
 
-        command_id = JobSchedulerClient.update_ostpool(obj, bundle.data)
+        self._check_osts(bundle.request, obj.filesystem, bundle.data.get("osts", []))
+
+        command_id = JobSchedulerClient.update_ostpool(obj.id, bundle.data)
+
+        if command_id is None:
+            raise custom_response(self, bundle.request, http.HttpNoContent, None)
+
         command = Command.objects.get(pk=command_id)
 
         raise custom_response(self, bundle.request, http.HttpAccepted, {"command": dehydrate_command(command)})
@@ -239,7 +239,7 @@ class HostResource(MetricResource, StatefulModelResource, BulkResourceOperation)
         validation = HostValidation()
         always_return_data = True
 
-        filtering = {"id": ["exact"], "fqdn": ["exact", "startswith"], "role": ["exact"]}
+        filtering = {"id": ["exact", "in"], "fqdn": ["exact", "startswith"], "role": ["exact"]}
 
     def put_list(self, request, **kwargs):
         """
@@ -121,7 +121,7 @@ class VolumeResource(ChromaModelResource):
         detail_allowed_methods = ["get", "put"]
         always_return_data = True
 
-        filtering = {"id": ["exact"], "label": ["exact", "endswith"]}
+        filtering = {"id": ["exact", "in"], "label": ["exact", "endswith"]}
 
     def apply_filters(self, request, filters=None):
         objects = super(VolumeResource, self).apply_filters(request, filters)
@@ -82,10 +82,27 @@ def parse_user_distribution(measurement, fs_name, counters):
     )
 
 
+def parse_fid_counters(measurement, fs_name, group_name, counters):
+    return pipe(
+        counters,
+        filter_out_other_counter,
+        partial(
+            map,
+            lambda x: create_stratagem_influx_point(
+                measurement,
+                [("group_name", group_name), ("counter_name", x.get("name")), ("fs_name", fs_name)],
+                [("count", x.get("count")), ("size", x.get("size"))],
+            ),
+        ),
+    )
+
+
 def parse_stratagem_results_to_influx(measurement, fs_name, stratagem_results_json):
     parse_fns = {
         "size_distribution": partial(parse_size_distribution, measurement, fs_name, labels),
         "user_distribution": partial(parse_user_distribution, measurement, fs_name),
+        "warn_fids": partial(parse_fid_counters, measurement, fs_name, "warn_fids"),
+        "purge_fids": partial(parse_fid_counters, measurement, fs_name, "purge_fids"),
     }
 
     group_counters = stratagem_results_json.get("group_counters")
@@ -93,7 +110,6 @@ def parse_stratagem_results_to_influx(measurement, fs_name, stratagem_results_js
     return pipe(
         [],
         partial(reduce, lambda out, cur: out + [(cur.get("name"), cur.get("counters"))], group_counters),
-        partial(filter, lambda xs: xs[0] not in ["warn_fids", "purge_fids"]),
         partial(map, lambda xs, parse_fns=parse_fns: parse_fns[xs[0]](xs[1])),
         partial(flatten),
     )
@@ -41,12 +41,16 @@ class Command(BaseCommand):
                 ("IS_RELEASE", json.dumps(settings.IS_RELEASE)),
                 ("LOG_PATH", settings.LOG_PATH),
                 ("SERVER_HTTP_URL", settings.SERVER_HTTP_URL),
+                ("IML_INSECURE", json.dumps(True)),
                 ("SITE_ROOT", settings.SITE_ROOT),
                 ("VERSION", settings.VERSION),
                 ("API_USER", API_USER),
                 ("API_KEY", API_KEY),
                 ("SOURCE_MAP_PATH", SOURCE_MAP_PATH),
                 ("MAILBOX_PATH", settings.MAILBOX_PATH),
+                ("MAILBOX_ADDRESS_QUOTA", settings.MAILBOX_ADDRESS_QUOTA),
+                ("MAILBOX_TOTAL_QUOTA", settings.MAILBOX_TOTAL_QUOTA),
+                ("MAILBOX_TTL", settings.MAILBOX_TTL),
                 ("PROXY_HOST", settings.PROXY_HOST),
                 ("DB_HOST", DB.get("HOST")),
                 ("DB_NAME", DB.get("NAME")),
@@ -0,0 +1,72 @@
+# -*- coding: utf-8 -*-
+from __future__ import unicode_literals
+
+import django.contrib.postgres.fields
+from django.db import migrations, models
+import django.db.models.deletion
+from chroma_core.migrations import (
+    build_tables,
+    forward_trigger_template,
+    backward_trigger_template,
+    join,
+)
+
+
+tables = map(build_tables, ["device", "devicehost"],)
+
+forward_trigger_list = map(forward_trigger_template, tables)
+forward_trigger_str = join(forward_trigger_list)
+
+backward_trigger_list = map(backward_trigger_template, tables)
+backward_trigger_str = join(backward_trigger_list)
+
+
+class Migration(migrations.Migration):
+
+    dependencies = [("chroma_core", "0008_ostpool_json_notify")]
+
+    operations = [
+        migrations.CreateModel(
+            name="Device",
+            fields=[
+                ("id", models.TextField(primary_key=True, serialize=False)),
+                ("size", models.TextField(help_text=b"Size of the device in bytes")),
+                ("usable_for_lustre", models.BooleanField()),
+                (
+                    "device_type",
+                    models.TextField(
+                        choices=[
+                            (b"scsi", b"scsi"),
+                            (b"partition", b"partition"),
+                            (b"mdraid", b"mdraid"),
+                            (b"mpath", b"mpath"),
+                            (b"vg", b"vg"),
+                            (b"lv", b"lv"),
+                            (b"zpool", b"zpool"),
+                            (b"dataset", b"dataset"),
+                        ]
+                    ),
+                ),
+                ("parents", django.contrib.postgres.fields.ArrayField(base_field=models.TextField(), size=None)),
+                ("children", django.contrib.postgres.fields.ArrayField(base_field=models.TextField(), size=None)),
+            ],
+            options={"ordering": ["id"],},
+        ),
+        migrations.CreateModel(
+            name="DeviceHost",
+            fields=[
+                ("id", models.AutoField(auto_created=True, primary_key=True, serialize=False, verbose_name="ID")),
+                ("fqdn", models.TextField()),
+                ("local", models.BooleanField()),
+                ("paths", django.contrib.postgres.fields.ArrayField(base_field=models.TextField(), size=None)),
+                ("mount_path", models.TextField(null=True)),
+                ("fs_type", models.TextField(null=True)),
+                ("fs_label", models.TextField(null=True)),
+                ("fs_uuid", models.TextField(null=True)),
+                ("device", models.ForeignKey(on_delete=django.db.models.deletion.CASCADE, to="chroma_core.Device")),
+            ],
+            options={"ordering": ["id"],},
+        ),
+        migrations.AlterUniqueTogether(name="devicehost", unique_together=set([("device", "fqdn")]),),
+        migrations.RunSQL(sql=forward_trigger_str, reverse_sql=backward_trigger_str),
+    ]
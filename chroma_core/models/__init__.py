@@ -35,3 +35,4 @@ from client_mount import *
 from lnet_configuration import *
 from sparse_model import *
 from stratagem import *
+from device import *
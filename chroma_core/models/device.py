@@ -0,0 +1,58 @@
+# -*- coding: utf-8 -*-
+# Copyright (c) 2019 DDN. All rights reserved.
+# Use of this source code is governed by a MIT-style
+# license that can be found in the LICENSE file.
+
+from django.db import models
+from django.db.models import CASCADE
+from django.contrib.postgres.fields import ArrayField
+
+
+class Device(models.Model):
+    """
+    A device (Block or Virtual).
+    These should be unique per cluster.
+    """
+
+    DEVICE_TYPES = [
+        ("scsi", "scsi"),
+        ("partition", "partition"),
+        ("mdraid", "mdraid"),
+        ("mpath", "mpath"),
+        ("vg", "vg"),
+        ("lv", "lv"),
+        ("zpool", "zpool"),
+        ("dataset", "dataset"),
+    ]
+
+    id = models.TextField(primary_key=True)
+    size = models.TextField(help_text="Size of the device in bytes")
+    usable_for_lustre = models.BooleanField()
+    device_type = models.TextField(choices=DEVICE_TYPES)
+    parents = ArrayField(models.TextField())
+    children = ArrayField(models.TextField())
+
+    class Meta:
+        app_label = "chroma_core"
+        ordering = ["id"]
+
+
+class DeviceHost(models.Model):
+    """
+    A pointer to a `Device` present on a host.
+    Stores mount_path and paths to reach the pointed to `Device`.
+    """
+
+    device = models.ForeignKey("Device", on_delete=CASCADE)
+    fqdn = models.TextField()
+    local = models.BooleanField()
+    paths = ArrayField(models.TextField())
+    mount_path = models.TextField(null=True)
+    fs_type = models.TextField(null=True)
+    fs_label = models.TextField(null=True)
+    fs_uuid = models.TextField(null=True)
+
+    class Meta:
+        app_label = "chroma_core"
+        unique_together = ("device", "fqdn")
+        ordering = ["id"]
@@ -502,6 +502,8 @@ class AggregateStratagemResultsStep(Step):
         clear_scan_results(args["clear_measurement_query"].format(args["fs_name"]))
         aggregated = aggregate_points(args["aggregate_query"])
         influx_entries = submit_aggregated_data(args["measurement"], args["fs_name"], aggregated)
+        submit_aggregated_data(args["history_measurement"], args["fs_name"], aggregated)
+        clear_scan_results(args["prune_history_query"].format(args["fs_name"]))
         clear_scan_results(args["clear_temp_measurement_query"])
 
         self.log(u"\u2713 Aggregated Stratagem counts and submitted to time series database.")
@@ -532,6 +534,8 @@ class AggregateStratagemResultsJob(Job):
                     "clear_measurement_query": "DELETE FROM stratagem_scan WHERE fs_name='{}'",
                     "clear_temp_measurement_query": "DROP MEASUREMENT temp_stratagem_scan",
                     "measurement": "stratagem_scan",
+                    "history_measurement": "stratagem_scan_history",
+                    "prune_history_query": "DELETE FROM stratagem_scan_history WHERE fs_name='{}' AND time < now() - 90d",
                     "fs_name": self.fs_name,
                 },
             )
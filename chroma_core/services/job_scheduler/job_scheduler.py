@@ -1163,7 +1163,24 @@ class JobScheduler(object):
         log.debug("Updating ostpool {} with: {}".format(ostpool_id, ostpool_data))
         with self._lock:
             ostpool = OstPool.objects.get(pk=ostpool_id)
-        # @@
+            current = set(ostpool.osts.all())
+            wanted = set(ManagedOst.objects.filter(filesystem=ostpool.filesystem, name__in=ostpool_data["osts"]))
+
+            with transaction.atomic():
+                cmds = []
+                for ost in wanted - current:
+                    cmds.append({"class_name": "AddOstPoolJob", "args": {"pool": ostpool, "ost": ost}})
+
+                for ost in current - wanted:
+                    cmds.append({"class_name": "RemoveOstPoolJob", "args": {"pool": ostpool, "ost": ost}})
+
+                if not cmds:
+                    return None
+
+                command_id = self.CommandPlan.command_run_jobs(cmds, help_text["updating_ostpool"])
+
+        self.progress.advance()
+        return command_id
 
     def delete_ostpool(self, ostpool_id):
         log.debug("Deleting ostpool {}".format(ostpool_id))
@@ -9,7 +9,8 @@ use crate::{
         ostpool, package,
         stratagem::{action_purge, action_warning, server},
     },
-    systemd,
+    daemon_plugins::action_queue::ActionLimits,
+    env, systemd,
 };
 use iml_util::action_plugins;
 use iml_wire_types::ActionName;
@@ -56,3 +57,25 @@ pub fn create_registry() -> action_plugins::Actions {
 
     map
 }
+
+/// The concurrency limits applied to actions run by the `AgentDaemon`.
+/// Actions not listed here are only bound by the host limit.
+pub fn create_limits() -> ActionLimits {
+    let host_limit = env::get_var_else("IML_ACTION_HOST_LIMIT", "16")
+        .parse()
+        .unwrap_or(16);
+
+    ActionLimits::new(host_limit)
+        .limit_action("start_scan_stratagem", 1)
+        .limit_action("stream_fidlists_stratagem", 1)
+        .exclusive_group("package", &["package_installed", "package_version"])
+        .exclusive_group(
+            "ostpool",
+            &[
+                "ostpool_create",
+                "ostpool_destroy",
+                "ostpool_add",
+                "ostpool_remove",
+            ],
+        )
+}
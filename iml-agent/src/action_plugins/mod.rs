@@ -14,4 +14,4 @@ pub mod ntp;
 pub mod ostpool;
 pub mod package;
 pub mod stratagem;
-pub use action_plugin::create_registry;
+pub use action_plugin::{create_limits, create_registry};
@@ -7,6 +7,7 @@ use futures::{
     future::{self, TryFutureExt},
     stream::{StreamExt, TryStreamExt},
 };
+use iml_wire_types::MailboxAddress;
 use liblustreapi::LlapiFid;
 use std::convert::Into;
 use tokio::task::spawn_blocking;
@@ -36,7 +37,7 @@ async fn rm_fids(llapi: LlapiFid, fids: Vec<String>) -> Result<(), ImlAgentError
 }
 
 pub async fn read_mailbox(
-    (fsname_or_mntpath, mailbox): (String, String),
+    (fsname_or_mntpath, mailbox): (String, MailboxAddress),
 ) -> Result<(), ImlAgentError> {
     let llapi = search_rootpath(fsname_or_mntpath).await?;
 
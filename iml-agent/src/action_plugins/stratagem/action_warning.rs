@@ -8,6 +8,7 @@ use futures::{
     sink::SinkExt,
     StreamExt, TryFutureExt, TryStreamExt,
 };
+use iml_wire_types::MailboxAddress;
 use liblustreapi::LlapiFid;
 use std::{io, path::PathBuf};
 use tokio::task::spawn_blocking;
@@ -59,7 +60,7 @@ pub fn write_records(
 
 /// Read mailbox and build a file of files. return pathname of generated file
 pub async fn read_mailbox(
-    (fsname_or_mntpath, mailbox): (String, String),
+    (fsname_or_mntpath, mailbox): (String, MailboxAddress),
 ) -> Result<PathBuf, ImlAgentError> {
     let mut txt_path: PathBuf = PathBuf::from(env::get_var_else("REPORT_DIR", "/tmp"));
     txt_path.push(mailbox.to_string());
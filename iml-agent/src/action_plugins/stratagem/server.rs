@@ -5,6 +5,7 @@
 use crate::{agent_error::ImlAgentError, cmd::cmd_output_success, http_comms::mailbox_client};
 use futures::{future, stream, StreamExt, TryStreamExt};
 use iml_fs::{read_file_to_end, stream_dir_lines, write_tempfile};
+use iml_wire_types::MailboxAddress;
 use std::{convert::Into, path::PathBuf};
 use uuid::Uuid;
 
@@ -280,15 +281,17 @@ pub fn generate_cooked_config(path: String, rd: Option<u64>, pd: Option<u64>) ->
     conf
 }
 
-type MailboxFiles = Vec<(PathBuf, String)>;
+type MailboxFiles = Vec<(PathBuf, MailboxAddress)>;
 
 /// Given a results.json
 /// Returns all the directories that contain fid files.
+///
+/// Errors if a group and rule don't make a valid `MailboxAddress`.
 pub fn get_mailbox_files(
     base_dir: &str,
     stratagem_data: &StratagemConfig,
     stratagem_result: &StratagemResult,
-) -> MailboxFiles {
+) -> Result<MailboxFiles, ImlAgentError> {
     stratagem_result
         .group_counters
         .iter()
@@ -313,7 +316,10 @@ pub fn get_mailbox_files(
                         .cloned()
                         .collect::<PathBuf>();
 
-                    (p, format!("{}-{}", group.name, rule.argument))
+                    let address: MailboxAddress =
+                        format!("{}-{}", group.name, rule.argument).parse()?;
+
+                    Ok::<_, ImlAgentError>((p, address))
                 })
         })
         .flatten()
@@ -352,7 +358,7 @@ pub async fn trigger_scan(
 
     let x = serde_json::from_slice(&xs)?;
 
-    let mailbox_files = get_mailbox_files(&tmp_dir, &data, &x);
+    let mailbox_files = get_mailbox_files(&tmp_dir, &data, &x)?;
 
     Ok((tmp_dir, x, mailbox_files))
 }
@@ -360,7 +366,9 @@ pub async fn trigger_scan(
 /// Streams output for all given mailbox files
 ///
 /// This fn will stream all files in parallel and return once they have all finished.
-pub async fn stream_fidlists(mailbox_files: MailboxFiles) -> Result<(), ImlAgentError> {
+pub async fn stream_fidlists(
+    mailbox_files: Vec<(PathBuf, MailboxAddress)>,
+) -> Result<(), ImlAgentError> {
     let mailbox_files = mailbox_files.into_iter().map(|(file, address)| {
         stream_dir_lines(file)
             .err_into::<ImlAgentError>()
@@ -516,18 +524,18 @@ mod tests {
             ],
         };
 
-        let actual = get_mailbox_files("foo_bar", &stratagem_data, &stratagem_result);
+        let actual = get_mailbox_files("foo_bar", &stratagem_data, &stratagem_result).unwrap();
 
         assert_eq!(
             actual,
             vec![
                 (
                     PathBuf::from("foo_bar/warn_purge_times/shell_cmd_of_rule_0"),
-                    "warn_purge_times-fids_expiring_soon".into()
+                    "warn_purge_times-fids_expiring_soon".parse().unwrap()
                 ),
                 (
                     PathBuf::from("foo_bar/warn_purge_times/shell_cmd_of_rule_1"),
-                    "warn_purge_times-fids_expired".into()
+                    "warn_purge_times-fids_expired".parse().unwrap()
                 )
             ]
         );
@@ -3,7 +3,7 @@
 // license that can be found in the LICENSE file.
 
 use iml_fs::ImlFsError;
-use iml_wire_types::PluginName;
+use iml_wire_types::{MailboxAddressError, PluginName};
 use std::{fmt, process::Output};
 
 pub type Result<T> = std::result::Result<T, ImlAgentError>;
@@ -95,6 +95,8 @@ pub enum ImlAgentError {
     XmlError(elementtree::Error),
     CibError(CibError),
     UnexpectedStatusError,
+    MailboxQuotaExceeded(http::StatusCode),
+    MailboxAddressError(MailboxAddressError),
     MarkerNotFound,
 }
 
@@ -133,6 +135,10 @@ impl std::fmt::Display for ImlAgentError {
             ImlAgentError::XmlError(ref err) => write!(f, "{}", err),
             ImlAgentError::CibError(ref err) => write!(f, "{}", err),
             ImlAgentError::UnexpectedStatusError => write!(f, "Unexpected status code"),
+            ImlAgentError::MailboxQuotaExceeded(ref status) => {
+                write!(f, "Mailbox quota exceeded ({})", status)
+            }
+            ImlAgentError::MailboxAddressError(ref err) => write!(f, "{}", err),
             ImlAgentError::MarkerNotFound => write!(f, "Marker not found"),
         }
     }
@@ -167,6 +173,8 @@ impl std::error::Error for ImlAgentError {
             ImlAgentError::XmlError(ref err) => Some(err),
             ImlAgentError::CibError(ref err) => Some(err),
             ImlAgentError::UnexpectedStatusError => None,
+            ImlAgentError::MailboxQuotaExceeded(_) => None,
+            ImlAgentError::MailboxAddressError(ref err) => Some(err),
             ImlAgentError::MarkerNotFound => None,
         }
     }
@@ -178,6 +186,12 @@ impl From<ImlFsError> for ImlAgentError {
     }
 }
 
+impl From<MailboxAddressError> for ImlAgentError {
+    fn from(err: MailboxAddressError) -> Self {
+        ImlAgentError::MailboxAddressError(err)
+    }
+}
+
 impl From<std::io::Error> for ImlAgentError {
     fn from(err: std::io::Error) -> Self {
         ImlAgentError::Io(err)
@@ -0,0 +1,384 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! # Action Queue
+//!
+//! Declarative concurrency limits for actions run by the `ActionRunner`.
+//!
+//! Limits can be placed on the number of actions running on this host,
+//! on the number of concurrent runs of a single `ActionName`, and on named groups
+//! of actions that are mutually exclusive with each other.
+//!
+//! Actions that cannot run right away wait in a FIFO queue until a running action
+//! finishes and frees up the capacity they need.
+
+use crate::action_plugins::create_limits;
+use futures::channel::oneshot;
+use iml_wire_types::{ActionName, ActionQueueStats};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Instant,
+};
+
+/// The declared limits for running actions.
+#[derive(Debug, Clone)]
+pub struct ActionLimits {
+    host_limit: usize,
+    action_limits: HashMap<ActionName, usize>,
+    groups: HashMap<ActionName, String>,
+}
+
+impl ActionLimits {
+    /// Creates a new set of limits, allowing at most `host_limit`
+    /// actions to run concurrently on this host.
+    pub fn new(host_limit: usize) -> Self {
+        ActionLimits {
+            host_limit: std::cmp::max(host_limit, 1),
+            action_limits: HashMap::new(),
+            groups: HashMap::new(),
+        }
+    }
+    /// Allow at most `limit` concurrent runs of the given action.
+    pub fn limit_action(mut self, name: impl Into<ActionName>, limit: usize) -> Self {
+        self.action_limits
+            .insert(name.into(), std::cmp::max(limit, 1));
+
+        self
+    }
+    /// Make the given actions mutually exclusive.
+    /// Only one member of the group may be running at a time.
+    pub fn exclusive_group(mut self, group: &str, names: &[&str]) -> Self {
+        for name in names {
+            self.groups.insert((*name).into(), group.to_string());
+        }
+
+        self
+    }
+    fn action_limit(&self, name: &ActionName) -> Option<usize> {
+        self.action_limits.get(name).copied()
+    }
+    fn group(&self, name: &ActionName) -> Option<&String> {
+        self.groups.get(name)
+    }
+}
+
+struct Waiter {
+    name: ActionName,
+    queued_at: Instant,
+    tx: oneshot::Sender<Permit>,
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    running_by_action: HashMap<ActionName, usize>,
+    running_groups: HashMap<String, usize>,
+    waiters: VecDeque<Waiter>,
+}
+
+struct Inner {
+    limits: ActionLimits,
+    state: Mutex<State>,
+}
+
+impl Inner {
+    fn can_run(&self, state: &State, name: &ActionName) -> bool {
+        if state.running >= self.limits.host_limit {
+            return false;
+        }
+
+        if let Some(limit) = self.limits.action_limit(name) {
+            if state.running_by_action.get(name).copied().unwrap_or(0) >= limit {
+                return false;
+            }
+        }
+
+        if let Some(group) = self.limits.group(name) {
+            if state.running_groups.get(group).copied().unwrap_or(0) > 0 {
+                return false;
+            }
+        }
+
+        true
+    }
+    fn start(&self, state: &mut State, name: &ActionName) {
+        state.running += 1;
+
+        *state.running_by_action.entry(name.clone()).or_insert(0) += 1;
+
+        if let Some(group) = self.limits.group(name) {
+            *state.running_groups.entry(group.clone()).or_insert(0) += 1;
+        }
+    }
+    fn finish(&self, state: &mut State, name: &ActionName) {
+        state.running = state.running.saturating_sub(1);
+
+        if let Some(x) = state.running_by_action.get_mut(name) {
+            *x = x.saturating_sub(1);
+
+            if *x == 0 {
+                state.running_by_action.remove(name);
+            }
+        }
+
+        if let Some(group) = self.limits.group(name) {
+            if let Some(x) = state.running_groups.get_mut(group) {
+                *x = x.saturating_sub(1);
+
+                if *x == 0 {
+                    state.running_groups.remove(group);
+                }
+            }
+        }
+    }
+    /// Walks the queue in FIFO order, pulling out every waiter that can now run.
+    ///
+    /// The returned pairs must be sent *after* the state lock has been released,
+    /// as a failed send drops the `Permit`, which takes the lock again.
+    fn dispatch(self: &Arc<Self>, state: &mut State) -> Vec<(oneshot::Sender<Permit>, Permit)> {
+        let mut ready = vec![];
+        let mut remaining = VecDeque::with_capacity(state.waiters.len());
+
+        while let Some(w) = state.waiters.pop_front() {
+            if w.tx.is_canceled() {
+                continue;
+            }
+
+            if self.can_run(state, &w.name) {
+                self.start(state, &w.name);
+
+                let permit = Permit {
+                    name: w.name,
+                    inner: Arc::clone(self),
+                };
+
+                ready.push((w.tx, permit));
+            } else {
+                remaining.push_back(w);
+            }
+        }
+
+        state.waiters = remaining;
+
+        ready
+    }
+}
+
+fn send_all(ready: Vec<(oneshot::Sender<Permit>, Permit)>) {
+    for (tx, permit) in ready {
+        // If the receiver went away, the returned permit is dropped here
+        // and its capacity is handed to the next waiter.
+        let _ = tx.send(permit);
+    }
+}
+
+/// Held by a running action. Capacity is released when this is dropped.
+pub struct Permit {
+    name: ActionName,
+    inner: Arc<Inner>,
+}
+
+impl std::fmt::Debug for Permit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Permit {{ name: {:?} }}", self.name)
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let ready = {
+            let mut state = self.inner.state.lock();
+
+            self.inner.finish(&mut state, &self.name);
+
+            self.inner.dispatch(&mut state)
+        };
+
+        send_all(ready);
+    }
+}
+
+/// Gates running actions behind the declared `ActionLimits`.
+#[derive(Clone)]
+pub struct ActionQueue(Arc<Inner>);
+
+lazy_static! {
+    /// The queue for this host, shared by the `action_runner` and `action_queue` plugins.
+    ///
+    /// It outlives any one session, so actions still running from an old session
+    /// keep counting against the limits.
+    pub static ref ACTION_QUEUE: ActionQueue = ActionQueue::new(create_limits());
+}
+
+impl std::fmt::Debug for ActionQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "ActionQueue {{ limits: {:?}, stats: {:?} }}",
+            self.0.limits,
+            self.stats()
+        )
+    }
+}
+
+impl ActionQueue {
+    pub fn new(limits: ActionLimits) -> Self {
+        ActionQueue(Arc::new(Inner {
+            limits,
+            state: Mutex::new(State::default()),
+        }))
+    }
+    /// Waits until the given action is allowed to run.
+    ///
+    /// Dropping the returned future before it resolves removes
+    /// the action from the queue.
+    pub async fn acquire(&self, name: ActionName) -> Permit {
+        let rx = {
+            let mut state = self.0.state.lock();
+
+            if state.waiters.is_empty() && self.0.can_run(&state, &name) {
+                self.0.start(&mut state, &name);
+
+                return Permit {
+                    name,
+                    inner: Arc::clone(&self.0),
+                };
+            }
+
+            let (tx, rx) = oneshot::channel();
+
+            state.waiters.push_back(Waiter {
+                name,
+                queued_at: Instant::now(),
+                tx,
+            });
+
+            let ready = self.0.dispatch(&mut state);
+
+            drop(state);
+
+            send_all(ready);
+
+            rx
+        };
+
+        rx.await
+            .expect("ActionQueue dropped a waiter without sending a permit")
+    }
+    pub fn stats(&self) -> ActionQueueStats {
+        let state = self.0.state.lock();
+
+        let mut queued_by_action = HashMap::new();
+        let mut oldest_queued_at = None;
+
+        for w in state.waiters.iter().filter(|w| !w.tx.is_canceled()) {
+            *queued_by_action.entry(w.name.0.clone()).or_insert(0) += 1;
+
+            oldest_queued_at = oldest_queued_at.or(Some(w.queued_at));
+        }
+
+        ActionQueueStats {
+            running: state.running,
+            queued: queued_by_action.values().sum(),
+            queued_by_action,
+            oldest_queued_secs: oldest_queued_at.map(|x| x.elapsed().as_secs()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ActionLimits, ActionQueue};
+    use futures::{future::FutureExt, poll};
+
+    #[tokio::test]
+    async fn test_host_limit_queues_in_order() {
+        let q = ActionQueue::new(ActionLimits::new(1));
+
+        let p1 = q.acquire("a".into()).await;
+
+        let mut f2 = Box::pin(q.acquire("b".into()));
+        let mut f3 = Box::pin(q.acquire("c".into()));
+
+        assert!(poll!(&mut f2).is_pending());
+        assert!(poll!(&mut f3).is_pending());
+        assert_eq!(q.stats().queued, 2);
+        assert_eq!(q.stats().oldest_queued_secs, Some(0));
+
+        drop(p1);
+
+        let p2 = f2.await;
+
+        assert!(poll!(&mut f3).is_pending());
+
+        drop(p2);
+
+        let _p3 = f3.await;
+
+        assert_eq!(q.stats().running, 1);
+        assert_eq!(q.stats().queued, 0);
+        assert_eq!(q.stats().oldest_queued_secs, None);
+    }
+
+    #[tokio::test]
+    async fn test_action_limit() {
+        let q = ActionQueue::new(ActionLimits::new(10).limit_action("scan", 1));
+
+        let p1 = q.acquire("scan".into()).await;
+        let _other = q.acquire("other".into()).await;
+
+        let mut f2 = Box::pin(q.acquire("scan".into()));
+
+        assert!(poll!(&mut f2).is_pending());
+        assert_eq!(q.stats().queued_by_action.get("scan"), Some(&1));
+
+        drop(p1);
+
+        let _p2 = f2.await;
+
+        assert_eq!(q.stats().running, 2);
+    }
+
+    #[tokio::test]
+    async fn test_exclusive_group() {
+        let q = ActionQueue::new(
+            ActionLimits::new(10).exclusive_group("ostpool", &["ostpool_create", "ostpool_add"]),
+        );
+
+        let p1 = q.acquire("ostpool_create".into()).await;
+
+        let mut f2 = Box::pin(q.acquire("ostpool_add".into()));
+
+        assert!(poll!(&mut f2).is_pending());
+
+        drop(p1);
+
+        let _p2 = f2.await;
+    }
+
+    #[tokio::test]
+    async fn test_dropped_waiter_is_removed() {
+        let q = ActionQueue::new(ActionLimits::new(1));
+
+        let p1 = q.acquire("a".into()).await;
+
+        let mut f2 = Box::pin(q.acquire("b".into()));
+
+        assert!(poll!(&mut f2).is_pending());
+
+        drop(f2);
+
+        assert_eq!(q.stats().queued, 0);
+
+        drop(p1);
+
+        let p3 = q.acquire("c".into()).now_or_never();
+
+        assert!(p3.is_some());
+        assert_eq!(q.stats().running, 1);
+    }
+}
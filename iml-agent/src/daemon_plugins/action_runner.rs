@@ -5,7 +5,10 @@
 use crate::{
     action_plugins::create_registry,
     agent_error::{ImlAgentError, RequiredError, Result},
-    daemon_plugins::DaemonPlugin,
+    daemon_plugins::{
+        action_queue::{ActionQueue, ACTION_QUEUE},
+        DaemonPlugin,
+    },
 };
 use futures::{
     channel::oneshot,
@@ -20,14 +23,15 @@ use std::{collections::HashMap, pin::Pin, sync::Arc};
 pub struct ActionRunner {
     ids: Arc<Mutex<HashMap<ActionId, oneshot::Sender<()>>>>,
     registry: Actions,
+    queue: ActionQueue,
 }
 
 impl std::fmt::Debug for ActionRunner {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
-            "ActionRunner {{ ids: {:?}, registry: RegistryFn }}",
-            self.ids
+            "ActionRunner {{ ids: {:?}, registry: RegistryFn, queue: {:?} }}",
+            self.ids, self.queue
         )
     }
 }
@@ -36,6 +40,7 @@ pub fn create() -> impl DaemonPlugin {
     ActionRunner {
         ids: Arc::new(Mutex::new(HashMap::new())),
         registry: create_registry(),
+        queue: ACTION_QUEUE.clone(),
     }
 }
 
@@ -72,10 +77,20 @@ impl DaemonPlugin for ActionRunner {
 
                 let fut = action_plugin_fn(args);
 
+                let queue = self.queue.clone();
+
+                // Wait in the queue until the limits allow this action to run.
+                // Cancelling while queued drops the wait and removes it from the queue.
+                let fut = async move {
+                    let _permit = queue.acquire(action).await;
+
+                    fut.await
+                };
+
                 let ids = self.ids.clone();
 
                 Box::pin(
-                    future::select(fut, rx)
+                    future::select(fut.boxed(), rx)
                         .map(move |r| match r {
                             Either::Left((result, _)) => {
                                 ids.lock().remove(&id);
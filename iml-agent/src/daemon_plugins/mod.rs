@@ -9,8 +9,10 @@
 //! `DaemonPlugin` is a trait that can be implemented by stateful plugins.
 //! Each plugin is wrapped in a session which provides a connection guarantee with the IML manager.
 
+pub mod action_queue;
 pub mod action_runner;
 pub mod daemon_plugin;
+pub mod queue_stats;
 pub mod stratagem;
 
 pub use daemon_plugin::{
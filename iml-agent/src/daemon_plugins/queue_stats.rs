@@ -0,0 +1,59 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    agent_error::{ImlAgentError, Result},
+    daemon_plugins::{action_queue::ACTION_QUEUE, DaemonPlugin, Output},
+};
+use futures::{future, Future};
+use iml_wire_types::ActionQueueStats;
+use parking_lot::Mutex;
+use std::pin::Pin;
+
+pub fn create() -> impl DaemonPlugin {
+    QueueStats {
+        last_stats: Mutex::new(None),
+    }
+}
+
+/// Reports the depth of the `ActionQueue` to the manager.
+///
+/// This has its own session, so the `action_runner` session
+/// only ever carries action results.
+#[derive(Debug)]
+pub struct QueueStats {
+    last_stats: Mutex<Option<ActionQueueStats>>,
+}
+
+fn to_output(stats: ActionQueueStats) -> Pin<Box<dyn Future<Output = Result<Output>> + Send>> {
+    Box::pin(future::ready(
+        serde_json::to_value(stats)
+            .map(Some)
+            .map_err(ImlAgentError::Serde),
+    ))
+}
+
+impl DaemonPlugin for QueueStats {
+    fn start_session(&mut self) -> Pin<Box<dyn Future<Output = Result<Output>> + Send>> {
+        let stats = ACTION_QUEUE.stats();
+
+        self.last_stats.lock().replace(stats.clone());
+
+        to_output(stats)
+    }
+    /// Only reports when the queue has changed since the last report.
+    fn update_session(&self) -> Pin<Box<dyn Future<Output = Result<Output>> + Send>> {
+        let stats = ACTION_QUEUE.stats();
+
+        let mut last_stats = self.last_stats.lock();
+
+        if last_stats.as_ref() == Some(&stats) {
+            return Box::pin(future::ok(None));
+        }
+
+        last_stats.replace(stats.clone());
+
+        to_output(stats)
+    }
+}
@@ -5,11 +5,12 @@
 use crate::{agent_error::ImlAgentError, env, http_comms::crypto_client};
 use futures::{future, Stream, TryFutureExt, TryStreamExt};
 use iml_fs::read_lines;
+use iml_wire_types::MailboxAddress;
 use reqwest::{Body, StatusCode};
 
 /// Streams the given data to the manager mailbox.
 pub async fn send(
-    message_name: String,
+    message_name: MailboxAddress,
     stream: impl Stream<Item = Result<bytes::Bytes, ImlAgentError>> + Send + Sync + 'static,
 ) -> Result<(), ImlAgentError> {
     tracing::debug!("Sending mailbox message to {}", message_name);
@@ -21,22 +22,27 @@ pub async fn send(
 
     let resp = client
         .post(env::MANAGER_URL.join("/mailbox/")?)
-        .header("mailbox-message-name", &message_name)
+        .header("mailbox-message-name", message_name.as_str())
         .body(body)
         .send()
         .await?;
 
-    if resp.status() != StatusCode::CREATED {
-        Err(ImlAgentError::UnexpectedStatusError)
-    } else {
-        tracing::debug!("Mailbox message sent");
-        Ok(())
+    match resp.status() {
+        StatusCode::CREATED => {
+            tracing::debug!("Mailbox message sent");
+            Ok(())
+        }
+        status @ StatusCode::PAYLOAD_TOO_LARGE | status @ StatusCode::INSUFFICIENT_STORAGE => {
+            tracing::warn!("Mailbox quota exceeded for {}: {}", message_name, status);
+            Err(ImlAgentError::MailboxQuotaExceeded(status))
+        }
+        _ => Err(ImlAgentError::UnexpectedStatusError),
     }
 }
 
 /// Retrieves the given data from the manager mailbox as a `Stream`
 /// of line-delimited `String`
-pub fn get(message_name: String) -> impl Stream<Item = Result<String, ImlAgentError>> {
+pub fn get(message_name: MailboxAddress) -> impl Stream<Item = Result<String, ImlAgentError>> {
     let q: Vec<(String, String)> = vec![];
 
     future::ready(crypto_client::get_id(&env::PFX))
@@ -44,7 +50,9 @@ pub fn get(message_name: String) -> impl Stream<Item = Result<String, ImlAgentEr
         .and_then(|id| async { crypto_client::create_client(id) })
         .and_then(move |client| {
             async move {
-                let message_endpoint = env::MANAGER_URL.join("/mailbox/")?.join(&message_name)?;
+                let message_endpoint = env::MANAGER_URL
+                    .join("/mailbox/")?
+                    .join(message_name.as_str())?;
 
                 Ok((client, message_endpoint))
             }
@@ -15,7 +15,7 @@ use components::{
     breadcrumbs::BreadCrumbs, update_activity_health, ActivityHealth,
 };
 use generated::css_classes::C;
-use iml_wire_types::warp_drive;
+use iml_wire_types::{warp_drive, LockAction, LockChange, ToCompositeId};
 use js_sys::Function;
 use route::Route;
 use seed::{events::Listener, prelude::*, *};
@@ -227,7 +227,9 @@ pub enum Msg {
     EventSourceError(JsValue),
     Records(warp_drive::Cache),
     RecordChange(warp_drive::RecordChange),
+    RecordChanges(Vec<warp_drive::RecordChange>),
     Locks(warp_drive::Locks),
+    LockChange(LockChange),
     WindowClick,
 }
 
@@ -257,10 +259,16 @@ pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
 
             let msg = match msg {
                 warp_drive::Message::Locks(locks) => Msg::Locks(locks),
+                warp_drive::Message::LockChange(lock_change) => {
+                    Msg::LockChange(lock_change)
+                }
                 warp_drive::Message::Records(records) => Msg::Records(records),
                 warp_drive::Message::RecordChange(record_change) => {
                     Msg::RecordChange(record_change)
                 }
+                warp_drive::Message::RecordChanges(record_changes) => {
+                    Msg::RecordChanges(record_changes)
+                }
             };
 
             orders.send_msg(msg);
@@ -274,6 +282,11 @@ pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             model.activity_health =
                 update_activity_health(&model.records.active_alert);
         }
+        Msg::RecordChanges(record_changes) => {
+            for x in record_changes {
+                update(Msg::RecordChange(x), model, orders);
+            }
+        }
         Msg::RecordChange(record_change) => match record_change {
             warp_drive::RecordChange::Update(record) => match record {
                 warp_drive::Record::ActiveAlert(x) => {
@@ -312,6 +325,17 @@ pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 warp_drive::Record::LnetConfiguration(x) => {
                     model.records.lnet_configuration.insert(x.id, x);
                 }
+                warp_drive::Record::Device(x) => {
+                    model.records.device.insert(x.id.clone(), x);
+                }
+                warp_drive::Record::DeviceHost(x) => {
+                    model
+                        .records
+                        .device_host
+                        .entry(x.fqdn.clone())
+                        .or_default()
+                        .insert(x.device_id.clone(), x);
+                }
             },
             warp_drive::RecordChange::Delete(record_id) => match record_id {
                 warp_drive::RecordId::ActiveAlert(x) => {
@@ -347,11 +371,41 @@ pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 warp_drive::RecordId::LnetConfiguration(x) => {
                     model.records.lnet_configuration.remove(&x);
                 }
+                warp_drive::RecordId::Device(x) => {
+                    model.records.device.remove(&x);
+                }
+                warp_drive::RecordId::DeviceHost(id, fqdn) => {
+                    if let Some(xs) = model.records.device_host.get_mut(&fqdn) {
+                        xs.remove(&id);
+
+                        if xs.is_empty() {
+                            model.records.device_host.remove(&fqdn);
+                        }
+                    }
+                }
             },
         },
         Msg::Locks(locks) => {
             model.locks = locks;
         }
+        Msg::LockChange(lock_change) => {
+            let key = lock_change.composite_id().to_string();
+
+            match lock_change.action {
+                LockAction::Add => {
+                    model.locks.entry(key).or_default().insert(lock_change);
+                }
+                LockAction::Remove => {
+                    if let Some(xs) = model.locks.get_mut(&key) {
+                        xs.retain(|x| x.uuid != lock_change.uuid);
+
+                        if xs.is_empty() {
+                            model.locks.remove(&key);
+                        }
+                    }
+                }
+            }
+        }
         Msg::ToggleMenu => model.menu_visibility.toggle(),
         Msg::ManageMenuState => {
             model.manage_menu_state.update();
@@ -3,19 +3,149 @@
 // license that can be found in the LICENSE file.
 
 use bytes::Buf;
-use futures::{channel::mpsc, stream::BoxStream, Future, Stream, StreamExt, TryStreamExt};
-use std::{collections::HashMap, path::PathBuf};
+use futures::{
+    channel::mpsc, lock::Mutex, stream::BoxStream, Future, SinkExt, Stream, StreamExt, TryStreamExt,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tokio::{fs::OpenOptions, io::AsyncWriteExt};
-use warp::{filters::BoxedFilter, reject, Filter};
+use warp::{filters::BoxedFilter, http::StatusCode, reject, Filter};
+
+/// The number of lines that may be buffered for an address
+/// before writers are made to wait on the file.
+pub const LINE_BUFFER_SIZE: usize = 100;
 
 #[derive(Debug)]
 pub enum Errors {
     IoError(std::io::Error),
-    TrySendError(mpsc::TrySendError<String>),
+    SendError(mpsc::SendError),
+    AddressQuotaExceeded(PathBuf),
+    TotalQuotaExceeded,
+    AddressNotFound(PathBuf),
+    AddressInUse(PathBuf),
+    TokioJoinError(tokio::task::JoinError),
 }
 
 impl reject::Reject for Errors {}
 
+/// Converts mailbox rejections into their matching status codes.
+///
+/// Exceeding the quota of a single address returns a `413 Payload Too Large`,
+/// while exceeding the quota of the whole mailbox returns a `507 Insufficient Storage`.
+///
+/// A write that exceeds a quota part way through is truncated:
+/// the lines sent before the one that did not fit are kept, and count against the quota.
+///
+/// Deleting an unknown address returns a `404 Not Found`, and deleting an address
+/// that is still being written to returns a `409 Conflict`.
+pub async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    let status = match err.find::<Errors>() {
+        Some(Errors::AddressQuotaExceeded(_)) => StatusCode::PAYLOAD_TOO_LARGE,
+        Some(Errors::TotalQuotaExceeded) => StatusCode::INSUFFICIENT_STORAGE,
+        Some(Errors::AddressNotFound(_)) => StatusCode::NOT_FOUND,
+        Some(Errors::AddressInUse(_)) => StatusCode::CONFLICT,
+        _ => return Err(err),
+    };
+
+    Ok(warp::reply::with_status(warp::reply(), status))
+}
+
+/// The number of bytes a line takes up once written to an address.
+pub fn line_size(line: &str) -> u64 {
+    if line.ends_with('\n') {
+        line.len() as u64
+    } else {
+        line.len() as u64 + 1
+    }
+}
+
+/// Tracks the bytes written to each address, and to the mailbox as a whole.
+#[derive(Debug)]
+pub struct MailboxQuotas {
+    address_limit: u64,
+    total_limit: u64,
+    total: u64,
+    usage: HashMap<PathBuf, u64>,
+}
+
+impl MailboxQuotas {
+    pub fn new(address_limit: u64, total_limit: u64) -> Self {
+        MailboxQuotas {
+            address_limit,
+            total_limit,
+            total: 0,
+            usage: HashMap::new(),
+        }
+    }
+    /// Seeds usage with any files already present in the mailbox directory.
+    pub fn with_existing(mut self, dir: &Path) -> Result<Self, std::io::Error> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+
+            if meta.is_file() {
+                self.usage.insert(entry.path(), meta.len());
+                self.total += meta.len();
+            }
+        }
+
+        Ok(self)
+    }
+    /// Reserves `bytes` for the given address.
+    ///
+    /// Errors without reserving anything if either quota would be exceeded.
+    pub fn reserve(&mut self, address: &PathBuf, bytes: u64) -> Result<(), Errors> {
+        let used = self.usage.get(address).copied().unwrap_or(0);
+
+        if used + bytes > self.address_limit {
+            return Err(Errors::AddressQuotaExceeded(address.clone()));
+        }
+
+        if self.total + bytes > self.total_limit {
+            return Err(Errors::TotalQuotaExceeded);
+        }
+
+        self.usage.insert(address.clone(), used + bytes);
+        self.total += bytes;
+
+        Ok(())
+    }
+    /// Gives back `bytes` reserved for an address that were never written.
+    pub fn unreserve(&mut self, address: &PathBuf, bytes: u64) {
+        if let Some(x) = self.usage.get_mut(address) {
+            let bytes = bytes.min(*x);
+
+            *x -= bytes;
+            self.total = self.total.saturating_sub(bytes);
+
+            if *x == 0 {
+                self.usage.remove(address);
+            }
+        }
+    }
+    /// Releases all bytes held by an address.
+    ///
+    /// Usually called once the address has been removed from disk.
+    pub fn release(&mut self, address: &PathBuf) {
+        if let Some(x) = self.usage.remove(address) {
+            self.total = self.total.saturating_sub(x);
+        }
+    }
+    /// Returns the number of bytes currently held by an address.
+    pub fn usage(&self, address: &PathBuf) -> u64 {
+        self.usage.get(address).copied().unwrap_or(0)
+    }
+    /// Returns the number of bytes currently held by the mailbox.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
 pub trait LineStream: Stream<Item = Result<String, warp::Rejection>> {}
 impl<T: Stream<Item = Result<String, warp::Rejection>>> LineStream for T {}
 
@@ -36,7 +166,7 @@ pub fn line_stream<'a>() -> BoxedFilter<(BoxStream<'a, Result<String, warp::Reje
 }
 
 /// Holds all active streams that are currently writing to an address.
-pub struct MailboxSenders(HashMap<PathBuf, mpsc::UnboundedSender<String>>);
+pub struct MailboxSenders(HashMap<PathBuf, mpsc::Sender<String>>);
 
 impl Default for MailboxSenders {
     fn default() -> Self {
@@ -46,7 +176,7 @@ impl Default for MailboxSenders {
 
 impl MailboxSenders {
     /// Adds a new address and tx handle to write lines with
-    pub fn insert(&mut self, address: PathBuf, tx: mpsc::UnboundedSender<String>) {
+    pub fn insert(&mut self, address: PathBuf, tx: mpsc::Sender<String>) {
         self.0.insert(address, tx);
     }
     /// Removes an address.
@@ -55,8 +185,12 @@ impl MailboxSenders {
     pub fn remove(&mut self, address: &PathBuf) {
         self.0.remove(address);
     }
+    /// Returns whether the address is currently being written to.
+    pub fn contains(&self, address: &PathBuf) -> bool {
+        self.0.contains_key(address)
+    }
     /// Returns a cloned reference to a tx handle matching the provided address, if one exists.
-    pub fn get(&mut self, address: &PathBuf) -> Option<mpsc::UnboundedSender<String>> {
+    pub fn get(&mut self, address: &PathBuf) -> Option<mpsc::Sender<String>> {
         self.0.get(address).cloned()
     }
     /// Creates a new sender entry.
@@ -68,10 +202,10 @@ impl MailboxSenders {
         &mut self,
         address: PathBuf,
     ) -> (
-        mpsc::UnboundedSender<String>,
+        mpsc::Sender<String>,
         impl Future<Output = Result<(), std::io::Error>>,
     ) {
-        let (tx, rx) = mpsc::unbounded();
+        let (tx, rx) = mpsc::channel(LINE_BUFFER_SIZE);
 
         self.insert(address.clone(), tx.clone());
 
@@ -79,14 +213,152 @@ impl MailboxSenders {
     }
 }
 
-/// Given an address and `mpsc::UnboundedReceiver` handle,
+/// Reserves quota for `line`, then sends it to be written to `address`.
+///
+/// Nothing is sent if a quota would be exceeded.
+/// If the line can't be sent, its reservation is released again.
+pub async fn send_line(
+    address: &PathBuf,
+    line: String,
+    tx: &mut mpsc::Sender<String>,
+    quotas: &Mutex<MailboxQuotas>,
+) -> Result<(), Errors> {
+    let size = line_size(&line);
+
+    quotas.lock().await.reserve(address, size)?;
+
+    // Waits when the writer falls behind, which stops reading the body
+    // and pushes back on the sender.
+    if let Err(e) = tx.send(line).await {
+        quotas.lock().await.unreserve(address, size);
+
+        return Err(Errors::SendError(e));
+    }
+
+    Ok(())
+}
+
+/// Information about a single address held in the mailbox.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct AddressInfo {
+    pub address: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Number of lines written
+    pub lines: u64,
+    /// Last write time, in seconds since the unix epoch
+    pub modified: u64,
+}
+
+fn count_lines(path: &Path) -> Result<u64, std::io::Error> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut count = 0;
+
+    loop {
+        let buf = reader.fill_buf()?;
+
+        if buf.is_empty() {
+            break;
+        }
+
+        count += buf.iter().filter(|b| **b == b'\n').count() as u64;
+
+        let len = buf.len();
+
+        reader.consume(len);
+    }
+
+    Ok(count)
+}
+
+fn address_info(path: &Path, meta: &fs::Metadata) -> Result<AddressInfo, std::io::Error> {
+    let modified = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    Ok(AddressInfo {
+        address: path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        size: meta.len(),
+        lines: count_lines(path)?,
+        modified,
+    })
+}
+
+/// Lists every address currently held in the mailbox directory.
+pub async fn list_addresses(dir: PathBuf) -> Result<Vec<AddressInfo>, Errors> {
+    tokio::task::spawn_blocking(move || {
+        let mut xs = vec![];
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+
+            if meta.is_file() {
+                xs.push(address_info(&entry.path(), &meta)?);
+            }
+        }
+
+        xs.sort_by(|a: &AddressInfo, b| a.address.cmp(&b.address));
+
+        Ok(xs)
+    })
+    .await
+    .map_err(Errors::TokioJoinError)?
+    .map_err(Errors::IoError)
+}
+
+/// Returns whether an address last written at `modified` has outlived `ttl`.
+pub fn is_expired(modified: SystemTime, ttl: Duration, now: SystemTime) -> bool {
+    now.duration_since(modified)
+        .map(|age| age > ttl)
+        .unwrap_or(false)
+}
+
+/// Lists addresses in the mailbox directory that have not been written to within `ttl`.
+pub async fn expired_addresses(dir: PathBuf, ttl: Duration) -> Result<Vec<PathBuf>, Errors> {
+    tokio::task::spawn_blocking(move || {
+        let now = SystemTime::now();
+        let mut xs = vec![];
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+
+            if meta.is_file() && is_expired(meta.modified()?, ttl, now) {
+                xs.push(entry.path());
+            }
+        }
+
+        Ok(xs)
+    })
+    .await
+    .map_err(Errors::TokioJoinError)?
+    .map_err(Errors::IoError)
+}
+
+/// Removes an address from disk.
+pub async fn remove_address(address: PathBuf) -> Result<(), Errors> {
+    tokio::fs::remove_file(&address)
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Errors::AddressNotFound(address.clone()),
+            _ => Errors::IoError(e),
+        })
+}
+
+/// Given an address and `mpsc::Receiver` handle,
 /// this fn will create or open an existing file in append mode.
 ///
-/// It will then write any incoming lines from the passed `mpsc::UnboundedReceiver`
+/// It will then write any incoming lines from the passed `mpsc::Receiver`
 /// to that file.
 pub async fn ingest_data(
     address: PathBuf,
-    mut rx: mpsc::UnboundedReceiver<String>,
+    mut rx: mpsc::Receiver<String>,
 ) -> Result<(), std::io::Error> {
     tracing::debug!("Starting ingest for {:?}", address);
 
@@ -124,22 +396,25 @@ mod tests {
 
         let mut mailbox_sender = MailboxSenders::default();
 
-        let (tx, fut) = mailbox_sender.create(address.clone());
+        let (mut tx, fut) = mailbox_sender.create(address.clone());
 
-        tx.unbounded_send("foo\n".into())?;
+        let fut = tokio::spawn(fut);
+
+        tx.send("foo\n".into()).await?;
 
         mailbox_sender
             .get(&address)
             .unwrap()
-            .unbounded_send("bar".into())?;
+            .send("bar".into())
+            .await?;
 
-        tx.unbounded_send("baz\n".into())?;
+        tx.send("baz\n".into()).await?;
 
         mailbox_sender.remove(&address);
 
         drop(tx);
 
-        fut.await?;
+        fut.await??;
 
         let contents = fs::read_to_string(&address).unwrap();
 
@@ -147,4 +422,190 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_line_size() {
+        assert_eq!(line_size("foo"), 4);
+        assert_eq!(line_size("foo\n"), 4);
+    }
+
+    #[test]
+    fn test_address_quota() {
+        let address = PathBuf::from("/tmp/mailbox/foo");
+        let mut quotas = MailboxQuotas::new(10, 100);
+
+        quotas.reserve(&address, 8).unwrap();
+
+        match quotas.reserve(&address, 3) {
+            Err(Errors::AddressQuotaExceeded(x)) => assert_eq!(x, address),
+            x => panic!("Expected AddressQuotaExceeded, got {:?}", x),
+        }
+
+        assert_eq!(quotas.usage(&address), 8);
+        assert_eq!(quotas.total(), 8);
+    }
+
+    #[test]
+    fn test_total_quota() {
+        let mut quotas = MailboxQuotas::new(10, 15);
+
+        quotas
+            .reserve(&PathBuf::from("/tmp/mailbox/foo"), 10)
+            .unwrap();
+
+        match quotas.reserve(&PathBuf::from("/tmp/mailbox/bar"), 10) {
+            Err(Errors::TotalQuotaExceeded) => {}
+            x => panic!("Expected TotalQuotaExceeded, got {:?}", x),
+        }
+
+        assert_eq!(quotas.total(), 10);
+    }
+
+    #[test]
+    fn test_quotas_with_existing() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = TempDir::new("test_mailbox")?;
+
+        fs::write(tmp_dir.path().join("foo"), "foo\nbar\n")?;
+
+        let quotas = MailboxQuotas::new(100, 100).with_existing(tmp_dir.path())?;
+
+        assert_eq!(quotas.usage(&tmp_dir.path().join("foo")), 8);
+        assert_eq!(quotas.total(), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_release_quota() {
+        let address = PathBuf::from("/tmp/mailbox/foo");
+        let mut quotas = MailboxQuotas::new(10, 100);
+
+        quotas.reserve(&address, 8).unwrap();
+        quotas.release(&address);
+
+        assert_eq!(quotas.usage(&address), 0);
+        assert_eq!(quotas.total(), 0);
+    }
+
+    #[test]
+    fn test_unreserve_quota() {
+        let address = PathBuf::from("/tmp/mailbox/foo");
+        let mut quotas = MailboxQuotas::new(10, 100);
+
+        quotas.reserve(&address, 8).unwrap();
+        quotas.unreserve(&address, 4);
+
+        assert_eq!(quotas.usage(&address), 4);
+        assert_eq!(quotas.total(), 4);
+
+        quotas.unreserve(&address, 8);
+
+        assert_eq!(quotas.usage(&address), 0);
+        assert_eq!(quotas.total(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_send_line_truncates_on_quota() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = TempDir::new("test_mailbox")?;
+        let address = tmp_dir.path().join("foo");
+        let quotas = Mutex::new(MailboxQuotas::new(10, 100));
+
+        let (mut tx, fut) = MailboxSenders::default().create(address.clone());
+
+        let fut = tokio::spawn(fut);
+
+        let mut result = Ok(());
+
+        for l in &["foo", "bar", "baz", "qux"] {
+            result = send_line(&address, l.to_string(), &mut tx, &quotas).await;
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        match result {
+            Err(Errors::AddressQuotaExceeded(x)) => assert_eq!(x, address),
+            x => panic!("Expected AddressQuotaExceeded, got {:?}", x),
+        }
+
+        drop(tx);
+
+        fut.await??;
+
+        assert_eq!(fs::read_to_string(&address)?, "foo\nbar\n");
+        assert_eq!(quotas.lock().await.usage(&address), 8);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_line_releases_on_send_error() {
+        let address = PathBuf::from("/tmp/mailbox/foo");
+        let quotas = Mutex::new(MailboxQuotas::new(10, 100));
+
+        let (mut tx, rx) = mpsc::channel(LINE_BUFFER_SIZE);
+
+        drop(rx);
+
+        match send_line(&address, "foo".into(), &mut tx, &quotas).await {
+            Err(Errors::SendError(_)) => {}
+            x => panic!("Expected SendError, got {:?}", x),
+        }
+
+        assert_eq!(quotas.lock().await.usage(&address), 0);
+        assert_eq!(quotas.lock().await.total(), 0);
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let now = SystemTime::now();
+        let ttl = Duration::from_secs(60);
+
+        assert!(is_expired(now - Duration::from_secs(61), ttl, now));
+        assert!(!is_expired(now - Duration::from_secs(59), ttl, now));
+        assert!(!is_expired(now + Duration::from_secs(10), ttl, now));
+    }
+
+    #[tokio::test]
+    async fn test_list_addresses() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = TempDir::new("test_mailbox")?;
+
+        fs::write(tmp_dir.path().join("foo"), "foo\nbar\n")?;
+        fs::write(tmp_dir.path().join("bar"), "baz\n")?;
+
+        let xs = list_addresses(tmp_dir.path().to_path_buf())
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        let xs: Vec<_> = xs
+            .into_iter()
+            .map(|x| (x.address, x.size, x.lines))
+            .collect();
+
+        assert_eq!(xs, vec![("bar".into(), 4, 1), ("foo".into(), 8, 2)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_address() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = TempDir::new("test_mailbox")?;
+        let address = tmp_dir.path().join("foo");
+
+        fs::write(&address, "foo\n")?;
+
+        remove_address(address.clone())
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        assert!(!address.exists());
+
+        match remove_address(address.clone()).await {
+            Err(Errors::AddressNotFound(x)) => assert_eq!(x, address),
+            x => panic!("Expected AddressNotFound, got {:?}", x),
+        }
+
+        Ok(())
+    }
 }
@@ -11,11 +11,72 @@
 //! concurrently
 
 use futures::{lock::Mutex, Stream, TryStreamExt};
-use iml_mailbox::{Errors, MailboxSenders};
-use std::{fs, path::PathBuf, pin::Pin, sync::Arc};
+use iml_mailbox::{
+    expired_addresses, handle_rejection, list_addresses, remove_address, send_line, Errors,
+    MailboxQuotas, MailboxSenders,
+};
+use iml_wire_types::MailboxAddress;
+use std::{fs, path::PathBuf, pin::Pin, sync::Arc, time::Duration};
 use tracing_subscriber::{fmt::Subscriber, EnvFilter};
 use warp::Filter as _;
 
+type SharedMailboxSenders = Arc<Mutex<MailboxSenders>>;
+type SharedMailboxQuotas = Arc<Mutex<MailboxQuotas>>;
+
+/// Removes an address, unless it is being written to.
+///
+/// The senders lock is held throughout,
+/// so no write can start on the address while it is being removed.
+async fn remove_unused_address(
+    address: PathBuf,
+    mailbox_senders: &SharedMailboxSenders,
+    mailbox_quotas: &SharedMailboxQuotas,
+) -> Result<(), Errors> {
+    let senders = mailbox_senders.lock().await;
+
+    if senders.contains(&address) {
+        return Err(Errors::AddressInUse(address));
+    }
+
+    remove_address(address.clone()).await?;
+
+    mailbox_quotas.lock().await.release(&address);
+
+    Ok(())
+}
+
+/// Periodically removes addresses that have not been written to within `ttl`.
+///
+/// Addresses that are still being written to are left alone.
+async fn expire_addresses(
+    mailbox_path: PathBuf,
+    ttl: Duration,
+    mailbox_senders: SharedMailboxSenders,
+    mailbox_quotas: SharedMailboxQuotas,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+
+    loop {
+        interval.tick().await;
+
+        let xs = match expired_addresses(mailbox_path.clone(), ttl).await {
+            Ok(xs) => xs,
+            Err(e) => {
+                tracing::error!("Could not list expired mailbox addresses: {:?}", e);
+                continue;
+            }
+        };
+
+        for address in xs {
+            match remove_unused_address(address.clone(), &mailbox_senders, &mailbox_quotas).await {
+                Ok(()) => tracing::info!("Expired mailbox address {:?}", address),
+                Err(Errors::AddressInUse(_)) => {}
+                Err(e) => tracing::error!("Could not expire {:?}: {:?}", address, e),
+            }
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let subscriber = Subscriber::builder()
@@ -27,25 +88,89 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = iml_manager_env::get_mailbox_addr();
     let mailbox_path = iml_manager_env::get_mailbox_path();
 
-    type SharedMailboxSenders = Arc<Mutex<MailboxSenders>>;
-
-    let shared_senders = Arc::new(Mutex::new(MailboxSenders::default()));
+    let shared_senders: SharedMailboxSenders = Arc::new(Mutex::new(MailboxSenders::default()));
+    let shared_senders2 = Arc::clone(&shared_senders);
+    let shared_senders3 = Arc::clone(&shared_senders);
     let shared_senders_filter = warp::any().map(move || Arc::clone(&shared_senders));
 
     fs::create_dir_all(&mailbox_path).expect("could not create mailbox path");
 
-    let mailbox = warp::path("mailbox");
+    let quotas = MailboxQuotas::new(
+        iml_manager_env::get_mailbox_address_quota(),
+        iml_manager_env::get_mailbox_total_quota(),
+    )
+    .with_existing(&mailbox_path)?;
+
+    tracing::info!("Mailbox is currently holding {} bytes", quotas.total());
+
+    let shared_quotas: SharedMailboxQuotas = Arc::new(Mutex::new(quotas));
+    let shared_quotas2 = Arc::clone(&shared_quotas);
+    let shared_quotas3 = Arc::clone(&shared_quotas);
+    let shared_quotas_filter = warp::any().map(move || Arc::clone(&shared_quotas));
+
+    let ttl = Duration::from_secs(iml_manager_env::get_mailbox_ttl());
+
+    tokio::spawn(expire_addresses(
+        mailbox_path.clone(),
+        ttl,
+        shared_senders3,
+        shared_quotas3,
+    ));
+
+    let mailbox_path2 = mailbox_path.clone();
+    let mailbox_path3 = mailbox_path.clone();
+    let mailbox_path4 = mailbox_path.clone();
+
+    // Only validated `MailboxAddress`es are accepted,
+    // so the joined path can never escape the mailbox path.
+    let address_filter = warp::header::<MailboxAddress>("mailbox-message-name")
+        .map(move |x: MailboxAddress| mailbox_path.join(x.as_str()));
+
+    let address_param = warp::path::param::<MailboxAddress>()
+        .map(move |x: MailboxAddress| mailbox_path4.join(x.as_str()));
+
+    let get = warp::get()
+        .and(warp::path("mailbox"))
+        .and(warp::path::end())
+        .and(warp::any().map(move || mailbox_path2.clone()))
+        .and_then(|mailbox_path: PathBuf| async move {
+            list_addresses(mailbox_path)
+                .await
+                .map(|xs| warp::reply::json(&xs))
+                .map_err(warp::reject::custom)
+        });
+
+    let delete = warp::delete()
+        .and(warp::path("mailbox"))
+        .and(address_param)
+        .and(warp::path::end())
+        .and(warp::any().map(move || Arc::clone(&shared_senders2)))
+        .and(warp::any().map(move || Arc::clone(&shared_quotas2)))
+        .and_then(
+            |address: PathBuf,
+             mailbox_senders: SharedMailboxSenders,
+             mailbox_quotas: SharedMailboxQuotas| {
+                async move {
+                    remove_unused_address(address.clone(), &mailbox_senders, &mailbox_quotas)
+                        .await
+                        .map_err(warp::reject::custom)?;
+
+                    tracing::info!("Removed mailbox address {:?}", address);
+
+                    Ok::<_, warp::reject::Rejection>(warp::http::StatusCode::NO_CONTENT)
+                }
+            },
+        );
 
     let post = warp::post()
-        .and(mailbox)
+        .and(warp::path("mailbox"))
         .and(shared_senders_filter)
-        .and(
-            warp::header::<PathBuf>("mailbox-message-name")
-                .map(move |x| [&mailbox_path.clone(), &x].iter().collect()),
-        )
+        .and(shared_quotas_filter)
+        .and(address_filter)
         .and(iml_mailbox::line_stream())
         .and_then(
             |mailbox_senders: SharedMailboxSenders,
+             mailbox_quotas: SharedMailboxQuotas,
              address: PathBuf,
              mut s: Pin<Box<dyn Stream<Item = Result<String, warp::Rejection>> + Send>>| {
                 async move {
@@ -55,7 +180,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         lock.get(&address)
                     };
 
-                    let tx = match tx {
+                    let mut tx = match tx {
                         Some(tx) => tx,
                         None => {
                             let (tx, fut) = {
@@ -84,11 +209,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         }
                     };
 
-
+                    // Stops at the first line over quota,
+                    // keeping the lines already sent.
                     while let Some(l) = s.try_next().await? {
                         tracing::debug!("Sending line {:?}", l);
 
-                        tx.unbounded_send(l).map_err(Errors::TrySendError).map_err(warp::reject::custom)?
+                        send_line(&address, l, &mut tx, &mailbox_quotas)
+                            .await
+                            .map_err(warp::reject::custom)?;
                     }
 
                     Ok::<_, warp::reject::Rejection>(())
@@ -97,9 +225,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         )
         .map(|_| warp::reply::with_status(warp::reply(), warp::http::StatusCode::CREATED));
 
-    let route = post.with(warp::log("mailbox"));
-
-    tracing::info!("Starting on {:?}", addr);
+    let route = get
+        .or(post)
+        .or(delete)
+        .recover(handle_rejection)
+        .with(warp::log("mailbox"));
+
+    tracing::info!(
+        "Starting on {:?}, expiring addresses in {:?} after {:?}",
+        addr,
+        mailbox_path3,
+        ttl
+    );
 
     warp::serve(route).run(addr).await;
 
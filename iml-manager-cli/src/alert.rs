@@ -0,0 +1,380 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    api_utils::{get, get_one, patch, put},
+    display_utils::{display_output, display_success, generate_table, wrap_fut, DisplayType},
+    error::ImlManagerCliError,
+    messaging::messages,
+};
+use console::style;
+use futures::{future::try_join_all, StreamExt};
+use iml_wire_types::{
+    warp_drive::{Message, Record, RecordChange, RecordId},
+    Alert, AlertSeverity, ApiList, EndpointName, FlatQuery, Host,
+};
+use std::collections::HashMap;
+use structopt::StructOpt;
+
+/// Narrows down which alerts are shown.
+#[derive(Debug, StructOpt)]
+pub struct AlertFilter {
+    /// Only show alerts at least this severe
+    #[structopt(short = "s", long = "severity")]
+    severity: Option<AlertSeverity>,
+    /// Only show alerts of this type, i.e. HostOfflineAlert
+    #[structopt(short = "t", long = "type")]
+    alert_type: Option<String>,
+    /// Only show alerts affecting this host
+    #[structopt(long = "host")]
+    host: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum AlertCommand {
+    /// List alerts
+    #[structopt(name = "list")]
+    List {
+        #[structopt(flatten)]
+        filter: AlertFilter,
+        /// Include alerts that are no longer active
+        #[structopt(short = "a", long = "all")]
+        all: bool,
+    },
+    /// Print active alerts as they are raised and cleared
+    #[structopt(name = "watch")]
+    Watch {
+        #[structopt(flatten)]
+        filter: AlertFilter,
+    },
+    /// Dismiss alerts
+    #[structopt(name = "dismiss")]
+    Dismiss {
+        /// The ids of the alerts to dismiss
+        #[structopt(name = "ID", raw(required_unless = "\"all\""))]
+        ids: Vec<u32>,
+        /// Dismiss every dismissable alert
+        #[structopt(long = "all")]
+        all: bool,
+    },
+}
+
+/// An `AlertFilter` with the host resolved.
+struct Filter {
+    severity: Option<AlertSeverity>,
+    alert_type: Option<String>,
+    host_uri: Option<String>,
+}
+
+impl Filter {
+    async fn new(x: AlertFilter) -> Result<Self, ImlManagerCliError> {
+        let host_uri = match x.host {
+            Some(fqdn) => {
+                let host: Host =
+                    wrap_fut("Fetching host...", get_one(vec![("fqdn", &fqdn)])).await?;
+
+                Some(host.resource_uri)
+            }
+            None => None,
+        };
+
+        Ok(Filter {
+            severity: x.severity,
+            alert_type: x.alert_type,
+            host_uri,
+        })
+    }
+    fn matches(&self, x: &Alert) -> bool {
+        self.severity.map(|s| x.severity >= s).unwrap_or(true)
+            && self
+                .alert_type
+                .as_ref()
+                .map(|t| &format!("{:?}", x.record_type) == t)
+                .unwrap_or(true)
+            && self
+                .host_uri
+                .as_ref()
+                .map(|uri| {
+                    &x.alert_item == uri
+                        || x.affected.iter().flatten().any(|affected| affected == uri)
+                })
+                .unwrap_or(true)
+    }
+}
+
+/// Active warnings and errors can't be dismissed until they clear.
+///
+/// This mirrors what the API's `dismiss_all` will dismiss.
+fn is_dismissable(x: &Alert) -> bool {
+    !x.dismissed
+        && !(x.active == Some(true)
+            && (x.severity == AlertSeverity::WARNING || x.severity == AlertSeverity::ERROR))
+}
+
+fn option_str(x: &Option<String>) -> String {
+    x.clone().unwrap_or_else(|| "---".into())
+}
+
+fn format_severity(x: AlertSeverity) -> String {
+    let s = style(x.to_string());
+
+    let s = match x {
+        AlertSeverity::DEBUG | AlertSeverity::INFO => s.dim(),
+        AlertSeverity::WARNING => s.yellow(),
+        AlertSeverity::ERROR | AlertSeverity::CRITICAL => s.red(),
+    };
+
+    s.to_string()
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "action", content = "alert", rename_all = "lowercase")]
+enum AlertEvent<'a> {
+    Raised(&'a Alert),
+    Cleared(&'a Alert),
+}
+
+fn display_event(event: AlertEvent, display_type: DisplayType) -> Result<(), ImlManagerCliError> {
+    let s = match display_type {
+        DisplayType::Json => serde_json::to_string(&event)?,
+        DisplayType::Yaml => serde_yaml::to_string(&event)?,
+        DisplayType::Csv | DisplayType::Table => match event {
+            AlertEvent::Raised(x) => format!(
+                "{} {} {} {}",
+                style("+").green(),
+                format_severity(x.severity),
+                x.alert_item_str,
+                x.message
+            ),
+            AlertEvent::Cleared(x) => format!(
+                "{} {} {} {}",
+                style("-").dim(),
+                format_severity(x.severity),
+                x.alert_item_str,
+                x.message
+            ),
+        },
+    };
+
+    println!("{}", s);
+
+    Ok(())
+}
+
+/// Tracks the active alerts a watch has seen,
+/// printing the ones raised and cleared.
+struct Watcher {
+    filter: Filter,
+    display_type: DisplayType,
+    alerts: HashMap<u32, Alert>,
+}
+
+impl Watcher {
+    fn raise(&mut self, x: Alert) -> Result<(), ImlManagerCliError> {
+        if !self.filter.matches(&x) {
+            return Ok(());
+        }
+
+        if !self.alerts.contains_key(&x.id) {
+            display_event(AlertEvent::Raised(&x), self.display_type)?;
+        }
+
+        self.alerts.insert(x.id, x);
+
+        Ok(())
+    }
+    fn clear(&mut self, id: u32) -> Result<(), ImlManagerCliError> {
+        if let Some(x) = self.alerts.remove(&id) {
+            display_event(AlertEvent::Cleared(&x), self.display_type)?;
+        }
+
+        Ok(())
+    }
+    fn change(&mut self, x: RecordChange) -> Result<(), ImlManagerCliError> {
+        match x {
+            RecordChange::Update(Record::ActiveAlert(x)) => self.raise(x),
+            RecordChange::Delete(RecordId::ActiveAlert(id)) => self.clear(id),
+            _ => Ok(()),
+        }
+    }
+    fn on_message(&mut self, msg: Message) -> Result<(), ImlManagerCliError> {
+        match msg {
+            Message::Records(cache) => {
+                let cleared: Vec<_> = self
+                    .alerts
+                    .keys()
+                    .filter(|id| !cache.active_alert.contains_key(id))
+                    .copied()
+                    .collect();
+
+                for id in cleared {
+                    self.clear(id)?;
+                }
+
+                let mut xs: Vec<_> = cache.active_alert.values().cloned().collect();
+                xs.sort_by_key(|x| x.id);
+
+                for x in xs {
+                    self.raise(x)?;
+                }
+
+                Ok(())
+            }
+            Message::RecordChange(x) => self.change(x),
+            Message::RecordChanges(xs) => xs.into_iter().try_for_each(|x| self.change(x)),
+            Message::Locks(_) | Message::LockChange(_) => Ok(()),
+        }
+    }
+}
+
+pub async fn alert_cli(
+    command: AlertCommand,
+    display_type: DisplayType,
+) -> Result<(), ImlManagerCliError> {
+    match command {
+        AlertCommand::List { filter, all } => {
+            let filter = Filter::new(filter).await?;
+
+            let mut query: Vec<(&str, &str)> = Alert::query();
+
+            if all {
+                query.retain(|(k, _)| k != &"active");
+            }
+
+            if let Some(t) = filter.alert_type.as_ref() {
+                query.push(("record_type", t));
+            }
+
+            let alerts: ApiList<Alert> =
+                wrap_fut("Fetching alerts...", get(Alert::endpoint_name(), query)).await?;
+
+            let alerts: Vec<_> = alerts
+                .objects
+                .into_iter()
+                .filter(|x| filter.matches(x))
+                .collect();
+
+            tracing::debug!("Alerts: {:?}", alerts);
+
+            display_output(&alerts, display_type, || {
+                generate_table(
+                    &[
+                        "Id",
+                        "Severity",
+                        "Type",
+                        "Item",
+                        "Message",
+                        "Begin",
+                        "End",
+                        "Dismissed",
+                    ],
+                    alerts.iter().map(|x| {
+                        vec![
+                            x.id.to_string(),
+                            x.severity.to_string(),
+                            format!("{:?}", x.record_type),
+                            x.alert_item_str.clone(),
+                            x.message.clone(),
+                            x.begin.clone(),
+                            option_str(&x.end),
+                            x.dismissed.to_string(),
+                        ]
+                    }),
+                )
+            })?;
+        }
+        AlertCommand::Watch { filter } => {
+            let mut watcher = Watcher {
+                filter: Filter::new(filter).await?,
+                display_type,
+                alerts: HashMap::new(),
+            };
+
+            let mut xs = Box::pin(messages(vec![("kinds".into(), "active_alert".into())]));
+
+            while let Some(msg) = xs.next().await {
+                watcher.on_message(msg?)?;
+            }
+        }
+        AlertCommand::Dismiss { all: true, .. } => {
+            put(&format!("{}/dismiss_all", Alert::endpoint_name()), ()).await?;
+
+            display_success("Dismissed all dismissable alerts");
+        }
+        AlertCommand::Dismiss { ids, .. } => {
+            let alerts: Vec<Alert> = wrap_fut(
+                "Fetching alerts...",
+                try_join_all(ids.iter().map(|id| {
+                    async move {
+                        get(
+                            &format!("{}/{}", Alert::endpoint_name(), id),
+                            Vec::<(String, String)>::new(),
+                        )
+                        .await
+                    }
+                })),
+            )
+            .await?;
+
+            if let Some(x) = alerts.iter().find(|x| !is_dismissable(x)) {
+                return Err(ImlManagerCliError::ApiError(format!(
+                    "Alert {} can not be dismissed while it is active",
+                    x.id
+                )));
+            }
+
+            try_join_all(
+                alerts
+                    .iter()
+                    .map(|x| patch(&x.resource_uri, serde_json::json!({ "dismissed": true }))),
+            )
+            .await?;
+
+            display_success(format!("Dismissed {} alert(s)", alerts.len()));
+        }
+    };
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_dismissable;
+    use iml_wire_types::{Alert, AlertSeverity, AlertType};
+
+    fn alert(active: bool, severity: AlertSeverity) -> Alert {
+        Alert {
+            _message: None,
+            active: if active { Some(true) } else { None },
+            affected: None,
+            alert_item: "/api/host/1/".into(),
+            alert_item_id: Some(1),
+            alert_item_str: "mds1".into(),
+            alert_type: "HostOfflineAlert".into(),
+            begin: "2019-11-01T00:00:00".into(),
+            dismissed: false,
+            end: None,
+            id: 1,
+            lustre_pid: None,
+            message: "Host is offline mds1".into(),
+            record_type: AlertType::HostOfflineAlert,
+            resource_uri: "/api/alert/1/".into(),
+            severity,
+            variant: "{}".into(),
+        }
+    }
+
+    #[test]
+    fn test_is_dismissable() {
+        assert!(!is_dismissable(&alert(true, AlertSeverity::ERROR)));
+        assert!(!is_dismissable(&alert(true, AlertSeverity::WARNING)));
+        assert!(is_dismissable(&alert(true, AlertSeverity::INFO)));
+        assert!(is_dismissable(&alert(false, AlertSeverity::ERROR)));
+
+        let mut x = alert(false, AlertSeverity::ERROR);
+        x.dismissed = true;
+
+        assert!(!is_dismissable(&x));
+    }
+}
@@ -2,13 +2,22 @@
 // Use of this source code is governed by a MIT-style
 // license that can be found in the LICENSE file.
 
-use crate::{display_utils, error::ImlManagerCliError};
+use crate::{
+    display_utils,
+    error::ImlManagerCliError,
+    progress::{format_locks, Watch},
+};
 use futures::{future, FutureExt, TryFutureExt};
-use iml_wire_types::{ApiList, AvailableAction, Command, EndpointName, FlatQuery, Host};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use iml_wire_types::{
+    ActionArgs, ApiList, AvailableAction, Command, EndpointName, FlatQuery, Host,
+};
 use regex::Regex;
-use std::{collections::HashMap, fmt::Debug, iter, time::Duration};
-use tokio::{task::spawn_blocking, time::delay_for};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    iter,
+};
+use tokio::task::spawn_blocking;
 
 #[derive(serde::Deserialize, Debug)]
 pub struct CmdWrapper {
@@ -46,12 +55,14 @@ fn cmd_finished(cmd: &Command) -> bool {
 }
 
 pub async fn wait_for_cmd(cmd: Command) -> Result<Command, ImlManagerCliError> {
+    let mut watch = Watch::new(vec![("kinds".into(), "host".into())]);
+
     loop {
         if cmd_finished(&cmd) {
             return Ok(cmd);
         }
 
-        delay_for(Duration::from_millis(1000)).await;
+        watch.changed().await;
 
         let client = iml_manager_client::get_client()?;
 
@@ -68,36 +79,48 @@ pub async fn wait_for_cmd(cmd: Command) -> Result<Command, ImlManagerCliError> {
     }
 }
 
-pub async fn wait_for_cmds(cmds: Vec<Command>) -> Result<Vec<Command>, ImlManagerCliError> {
-    let m = MultiProgress::new();
+/// The ids of the jobs in `cmd`.
+fn job_ids(cmd: &Command) -> HashSet<u64> {
+    cmd.jobs
+        .iter()
+        .filter_map(|x| extract_api_id(x))
+        .filter_map(|x| x.parse().ok())
+        .collect()
+}
 
-    let num_cmds = cmds.len() as u64;
+/// Waits for `cmds` to complete, returning them in their final state.
+///
+/// Each command is shown with how long it has been running,
+/// and the jobs currently holding locks for it.
+pub async fn wait_for_cmds(cmds: Vec<Command>) -> Result<Vec<Command>, ImlManagerCliError> {
+    let m = display_utils::multi_progress();
 
-    let spinner_style = ProgressStyle::default_spinner()
-        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
-        .template("{prefix:.bold.dim} {spinner} {wide_msg}");
+    let num_cmds = cmds.len();
 
     let mut cmd_spinners = HashMap::new();
 
     for (idx, cmd) in cmds.iter().enumerate() {
-        let pb = m.add(ProgressBar::new(100_000));
-        pb.set_style(spinner_style.clone());
-        pb.set_prefix(&format!("[{}/{}]", idx + 1, num_cmds));
-        pb.set_message(&cmd.message);
-        cmd_spinners.insert(cmd.id, pb);
+        let (pb, detail) =
+            display_utils::add_progress(&m, &format!("[{}/{}]", idx + 1, num_cmds), &cmd.message);
+
+        cmd_spinners.insert(cmd.id, (pb, detail, job_ids(cmd)));
     }
 
     let fut = spawn_blocking(move || m.join())
         .map(|x| x.map_err(|e| e.into()).and_then(std::convert::identity));
 
     let fut2 = async {
+        let mut done = HashMap::new();
+
+        let mut watch = Watch::new(vec![("kinds".into(), "host".into())]);
+
         loop {
             if cmd_spinners.is_empty() {
                 tracing::debug!("All commands complete. Returning");
-                return Ok::<_, ImlManagerCliError>(());
+                return Ok::<_, ImlManagerCliError>(done);
             }
 
-            delay_for(Duration::from_millis(1000)).await;
+            watch.changed().await;
 
             let query: Vec<_> = cmd_spinners
                 .keys()
@@ -109,19 +132,46 @@ pub async fn wait_for_cmds(cmds: Vec<Command>) -> Result<Vec<Command>, ImlManage
 
             for cmd in cmds.objects {
                 if cmd_finished(&cmd) {
-                    let pb = cmd_spinners.remove(&cmd.id).unwrap();
+                    let (pb, detail, _) = cmd_spinners.remove(&cmd.id).unwrap();
+                    detail.finish_and_clear();
                     pb.finish_with_message(&display_utils::format_cmd_state(&cmd));
+                    done.insert(cmd.id, cmd);
                 } else {
-                    let pb = cmd_spinners.get(&cmd.id).unwrap();
-                    pb.inc(1);
+                    let (_, detail, jobs) = cmd_spinners.get(&cmd.id).unwrap();
+                    detail.set_message(&format_locks("running", watch.held_by(jobs)));
                 }
             }
         }
     };
 
-    future::try_join(fut.err_into(), fut2).await?;
+    let (_, mut done) = future::try_join(fut.err_into(), fut2).await?;
 
-    Ok(cmds)
+    Ok(cmds
+        .into_iter()
+        .map(|cmd| done.remove(&cmd.id).unwrap_or(cmd))
+        .collect())
+}
+
+/// Returns an `ApiError` if any of `cmds` errored or was cancelled.
+pub fn check_cmds(cmds: &[Command]) -> Result<(), ImlManagerCliError> {
+    let failed: Vec<_> = cmds
+        .iter()
+        .filter_map(|x| {
+            if x.errored {
+                Some(format!("{} errored", x.message))
+            } else if x.cancelled {
+                Some(format!("{} cancelled", x.message))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(ImlManagerCliError::ApiError(failed.join("\n")))
+    }
 }
 
 pub async fn get_available_actions(
@@ -141,6 +191,64 @@ pub async fn get_available_actions(
     .await
 }
 
+/// `ActionArgs` without the unset fields, as jobs reject args they don't take.
+fn job_args(x: &Option<ActionArgs>) -> serde_json::Map<String, serde_json::Value> {
+    let mut args = serde_json::Map::new();
+
+    if let Some(x) = x {
+        if let Some(id) = x.host_id {
+            args.insert("host_id".into(), id.into());
+        }
+
+        if let Some(id) = x.target_id {
+            args.insert("target_id".into(), id.into());
+        }
+    }
+
+    args
+}
+
+/// Runs an `AvailableAction` on the object at `resource_uri`.
+///
+/// Actions with a `state` are state changes, made with a `PUT` to the object.
+/// Actions with a `class_name` are jobs, run in a new `Command`.
+///
+/// Returns `None` if the object was already in the requested state.
+pub async fn run_available_action(
+    action: &AvailableAction,
+    resource_uri: &str,
+) -> Result<Option<Command>, ImlManagerCliError> {
+    match (&action.state, &action.class_name) {
+        (Some(state), _) => {
+            let resp = put(resource_uri, serde_json::json!({ "state": state })).await?;
+
+            if resp.status() == iml_manager_client::StatusCode::NO_CONTENT {
+                return Ok(None);
+            }
+
+            let CmdWrapper { command } = resp.json().await?;
+
+            Ok(Some(command))
+        }
+        (None, Some(class_name)) => {
+            let cmd = create_command(SendCmd {
+                jobs: vec![SendJob {
+                    class_name: class_name.to_string(),
+                    args: job_args(&action.args),
+                }],
+                message: action.long_description.to_string(),
+            })
+            .await?;
+
+            Ok(Some(cmd))
+        }
+        (None, None) => Err(ImlManagerCliError::ApiError(format!(
+            "{} can not be run from the CLI",
+            action.verb
+        ))),
+    }
+}
+
 /// Given an `ApiList`, this fn returns the first item or errors.
 pub fn first<T: EndpointName>(x: ApiList<T>) -> Result<T, ImlManagerCliError> {
     x.objects
@@ -184,17 +292,40 @@ pub async fn put(
         .map_err(|e| e.into())
 }
 
+/// Wrapper for a `PATCH` to the Api.
+pub async fn patch(
+    endpoint: &str,
+    body: impl serde::Serialize,
+) -> Result<iml_manager_client::Response, ImlManagerCliError> {
+    let client = iml_manager_client::get_client()?;
+    iml_manager_client::patch(client, endpoint, body)
+        .await
+        .map_err(|e| e.into())
+}
+
 /// Wrapper for a `DELETE` to the Api.
 pub async fn delete(
     endpoint: &str,
     query: impl serde::Serialize,
 ) -> Result<iml_manager_client::Response, ImlManagerCliError> {
-    let client = iml_manager_client::get_client().expect("Could not create API client");
+    let client = iml_manager_client::get_client()?;
     iml_manager_client::delete(client, endpoint, query)
         .await
         .map_err(|e| e.into())
 }
 
+/// Wrapper for an InfluxQL query through the `/influx` proxy.
+pub async fn get_influx<T: serde::de::DeserializeOwned + std::fmt::Debug>(
+    db: &str,
+    q: &str,
+) -> Result<T, ImlManagerCliError> {
+    let client = iml_manager_client::get_client()?;
+
+    iml_manager_client::get_influx(client, db, q)
+        .await
+        .map_err(|e| e.into())
+}
+
 pub async fn get_hosts() -> Result<ApiList<Host>, ImlManagerCliError> {
     get(Host::endpoint_name(), Host::query()).await
 }
@@ -219,3 +350,39 @@ pub fn extract_api_id(s: &str) -> Option<&str> {
 
     x.get(1).map(|x| x.as_str())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::check_cmds;
+    use iml_wire_types::Command;
+
+    fn command(id: u32, errored: bool, cancelled: bool) -> Command {
+        Command {
+            cancelled,
+            complete: true,
+            created_at: "2019-11-01T00:00:00".into(),
+            errored,
+            id,
+            jobs: vec![],
+            logs: "".into(),
+            message: format!("Command {}", id),
+            resource_uri: format!("/api/command/{}/", id),
+        }
+    }
+
+    #[test]
+    fn test_check_cmds() {
+        assert!(check_cmds(&[]).is_ok());
+        assert!(check_cmds(&[command(1, false, false)]).is_ok());
+
+        let err = check_cmds(&[
+            command(1, false, false),
+            command(2, true, false),
+            command(3, false, true),
+        ])
+        .unwrap_err();
+
+        assert_eq!(err.exit_code(), crate::error::EXIT_API_ERROR);
+        assert_eq!(err.to_string(), "Command 2 errored\nCommand 3 cancelled");
+    }
+}
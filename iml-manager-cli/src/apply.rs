@@ -0,0 +1,807 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! # Apply
+//!
+//! Brings the cluster in line with a declarative description,
+//! by diffing it against what the API reports and running only the
+//! `server`, `ostpool` and `stratagem` commands needed to get there.
+
+use crate::{
+    api_utils::{get_all, get_hosts},
+    display_utils::{display_cancelled, display_output, display_success, wrap_fut, DisplayType},
+    error::ImlManagerCliError,
+    ostpool::{ostpool_cli, OstPoolCommand},
+    server::{server_cli, AddHosts, ServerCommand},
+    stratagem::{
+        parse_duration, stratagem_cli, StratagemCommand, StratagemInterval,
+        StratagemIntervalConfig, StratagemRemoveData,
+    },
+};
+use console::style;
+use futures::future::{try_join, try_join5};
+use iml_wire_types::{
+    Filesystem, OstPool, ServerProfile, StratagemConfiguration, Target, TargetConfParam,
+};
+use prettytable::{Row, Table};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::PathBuf,
+};
+use structopt::StructOpt;
+
+type AnyTarget = Target<TargetConfParam>;
+
+#[derive(Debug, StructOpt)]
+pub struct ApplyConfig {
+    /// The cluster description, as YAML or JSON
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    path: PathBuf,
+    /// Print the plan without running it
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+}
+
+/// Servers to deploy with a profile.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct HostsDescription {
+    /// A hostlist expression, i.e. oss[1-4].local
+    pub hosts: String,
+    pub profile: String,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct PoolDescription {
+    pub name: String,
+    pub osts: BTreeSet<String>,
+}
+
+/// Stratagem durations, written as they are on the command line, i.e. `6h`.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct IntervalDescription {
+    pub interval: String,
+    pub report: Option<String>,
+    pub purge: Option<String>,
+}
+
+/// The pools and Stratagem interval of an existing filesystem.
+///
+/// Pools that are not listed are left alone, as is the Stratagem interval
+/// when none is given. An explicit `stratagem: null` removes the interval.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct FilesystemDescription {
+    pub name: String,
+    #[serde(default)]
+    pub pools: Vec<PoolDescription>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub stratagem: Option<Option<IntervalDescription>>,
+}
+
+/// Deserializes a field that is present as `Some`,
+/// so an explicit `null` can be told apart from a missing field.
+fn deserialize_some<'de, T, D>(d: D) -> Result<Option<T>, D::Error>
+where
+    T: serde::Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    serde::Deserialize::deserialize(d).map(Some)
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct ClusterDescription {
+    #[serde(default)]
+    pub hosts: Vec<HostsDescription>,
+    #[serde(default)]
+    pub filesystems: Vec<FilesystemDescription>,
+}
+
+/// Stratagem durations, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct Interval {
+    pub interval: u64,
+    pub report_duration: Option<u64>,
+    pub purge_duration: Option<u64>,
+}
+
+impl From<&StratagemConfiguration> for Interval {
+    fn from(x: &StratagemConfiguration) -> Self {
+        Interval {
+            interval: x.interval,
+            report_duration: x.report_duration,
+            purge_duration: x.purge_duration,
+        }
+    }
+}
+
+impl Interval {
+    fn parse(x: &IntervalDescription) -> Result<Self, ImlManagerCliError> {
+        Ok(Interval {
+            interval: parse_duration(&x.interval)?,
+            report_duration: x.report.as_deref().map(parse_duration).transpose()?,
+            purge_duration: x.purge.as_deref().map(parse_duration).transpose()?,
+        })
+    }
+}
+
+/// What the API reports, narrowed down to what a `ClusterDescription` can hold.
+#[derive(Debug, Clone, Default)]
+pub struct ClusterState {
+    /// Profile names, keyed by both fqdn and nodename
+    pub hosts: BTreeMap<String, String>,
+    /// Names of the profiles hosts can be added with
+    pub profiles: BTreeSet<String>,
+    /// OST names, keyed by filesystem name
+    pub osts: BTreeMap<String, BTreeSet<String>>,
+    /// OST names, keyed by filesystem and pool name
+    pub pools: BTreeMap<(String, String), BTreeSet<String>>,
+    /// Stratagem intervals, keyed by filesystem name
+    pub intervals: BTreeMap<String, Interval>,
+}
+
+/// A single change needed to bring the cluster in line.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Op {
+    AddHosts {
+        hosts: BTreeSet<String>,
+        profile: String,
+    },
+    CreatePool {
+        filesystem: String,
+        pool: String,
+        osts: BTreeSet<String>,
+    },
+    GrowPool {
+        filesystem: String,
+        pool: String,
+        osts: BTreeSet<String>,
+    },
+    ShrinkPool {
+        filesystem: String,
+        pool: String,
+        osts: BTreeSet<String>,
+    },
+    AddInterval {
+        filesystem: String,
+        interval: Interval,
+    },
+    UpdateInterval {
+        filesystem: String,
+        interval: Interval,
+    },
+    RemoveInterval {
+        filesystem: String,
+    },
+}
+
+/// Formats milliseconds in the largest unit `parse_duration` accepts that divides them.
+fn format_duration(ms: u64) -> String {
+    match ms {
+        x if x % 86_400_000 == 0 => format!("{}d", x / 86_400_000),
+        x if x % 3_600_000 == 0 => format!("{}h", x / 3_600_000),
+        x if x % 60_000 == 0 => format!("{}m", x / 60_000),
+        x => format!("{}s", x / 1_000),
+    }
+}
+
+fn join(xs: &BTreeSet<String>) -> String {
+    xs.iter().cloned().collect::<Vec<_>>().join(" ")
+}
+
+fn interval_args(x: &Interval) -> String {
+    let mut s = format!("--interval {}", format_duration(x.interval));
+
+    if let Some(r) = x.report_duration {
+        s.push_str(&format!(" --report {}", format_duration(r)));
+    }
+
+    if let Some(p) = x.purge_duration {
+        s.push_str(&format!(" --purge {}", format_duration(p)));
+    }
+
+    s
+}
+
+/// Each `Op` is shown as the `iml` command that carries it out.
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::AddHosts { hosts, profile } => write!(
+                f,
+                "iml server add --hosts {} --profile {}",
+                hosts.iter().cloned().collect::<Vec<_>>().join(","),
+                profile
+            ),
+            Op::CreatePool {
+                filesystem,
+                pool,
+                osts,
+            } => write!(
+                f,
+                "iml filesystem pool create {} {} {}",
+                filesystem,
+                pool,
+                join(osts)
+            ),
+            Op::GrowPool {
+                filesystem,
+                pool,
+                osts,
+            } => write!(
+                f,
+                "iml filesystem pool grow {} {} {}",
+                filesystem,
+                pool,
+                join(osts)
+            ),
+            Op::ShrinkPool {
+                filesystem,
+                pool,
+                osts,
+            } => write!(
+                f,
+                "iml filesystem pool shrink {} {} {}",
+                filesystem,
+                pool,
+                join(osts)
+            ),
+            Op::AddInterval {
+                filesystem,
+                interval,
+            } => write!(
+                f,
+                "iml stratagem interval add --filesystem {} {}",
+                filesystem,
+                interval_args(interval)
+            ),
+            Op::UpdateInterval {
+                filesystem,
+                interval,
+            } => write!(
+                f,
+                "iml stratagem interval update --filesystem {} {}",
+                filesystem,
+                interval_args(interval)
+            ),
+            Op::RemoveInterval { filesystem } => {
+                write!(
+                    f,
+                    "iml stratagem interval remove --filesystem {}",
+                    filesystem
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Plan {
+    pub ops: Vec<Op>,
+    /// Differences `apply` can't fix, i.e. a deployed host with another profile
+    pub warnings: Vec<String>,
+}
+
+/// Diffs `desc` against `state`, returning the `Op`s needed, in the order they should run.
+pub fn plan(desc: &ClusterDescription, state: &ClusterState) -> Result<Plan, ImlManagerCliError> {
+    let mut plan = Plan::default();
+
+    let mut new_hosts: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+
+    for x in &desc.hosts {
+        for host in hostlist_parser::parse(&x.hosts)? {
+            match state.hosts.get(&host) {
+                Some(profile) if profile != &x.profile => plan.warnings.push(format!(
+                    "Host {} is deployed with profile {}, not {}. Remove it first to change it.",
+                    host, profile, x.profile
+                )),
+                Some(_) => {}
+                None if !state.profiles.contains(&x.profile) => {
+                    return Err(ImlManagerCliError::ApiError(format!(
+                        "Profile {} not found",
+                        x.profile
+                    )))
+                }
+                None => {
+                    new_hosts.entry(&x.profile).or_default().insert(host);
+                }
+            }
+        }
+    }
+
+    plan.ops
+        .extend(new_hosts.into_iter().map(|(profile, hosts)| Op::AddHosts {
+            hosts,
+            profile: profile.to_string(),
+        }));
+
+    for fs in &desc.filesystems {
+        let osts = state.osts.get(&fs.name).ok_or_else(|| {
+            ImlManagerCliError::ApiError(format!("Filesystem {} not found", fs.name))
+        })?;
+
+        for pool in &fs.pools {
+            if let Some(x) = pool.osts.difference(osts).next() {
+                return Err(ImlManagerCliError::ApiError(format!(
+                    "OST {} not found in filesystem {}",
+                    x, fs.name
+                )));
+            }
+
+            let current = match state.pools.get(&(fs.name.clone(), pool.name.clone())) {
+                Some(x) => x,
+                None => {
+                    plan.ops.push(Op::CreatePool {
+                        filesystem: fs.name.clone(),
+                        pool: pool.name.clone(),
+                        osts: pool.osts.clone(),
+                    });
+
+                    continue;
+                }
+            };
+
+            let grow: BTreeSet<_> = pool.osts.difference(current).cloned().collect();
+            let shrink: BTreeSet<_> = current.difference(&pool.osts).cloned().collect();
+
+            if !grow.is_empty() {
+                plan.ops.push(Op::GrowPool {
+                    filesystem: fs.name.clone(),
+                    pool: pool.name.clone(),
+                    osts: grow,
+                });
+            }
+
+            if !shrink.is_empty() {
+                plan.ops.push(Op::ShrinkPool {
+                    filesystem: fs.name.clone(),
+                    pool: pool.name.clone(),
+                    osts: shrink,
+                });
+            }
+        }
+
+        let wanted = match &fs.stratagem {
+            Some(x) => x.as_ref().map(Interval::parse).transpose()?,
+            None => continue,
+        };
+
+        match (state.intervals.get(&fs.name), wanted) {
+            (None, Some(interval)) => plan.ops.push(Op::AddInterval {
+                filesystem: fs.name.clone(),
+                interval,
+            }),
+            (Some(current), Some(interval)) if current != &interval => {
+                plan.ops.push(Op::UpdateInterval {
+                    filesystem: fs.name.clone(),
+                    interval,
+                })
+            }
+            (Some(_), None) => plan.ops.push(Op::RemoveInterval {
+                filesystem: fs.name.clone(),
+            }),
+            _ => {}
+        }
+    }
+
+    Ok(plan)
+}
+
+async fn get_state() -> Result<ClusterState, ImlManagerCliError> {
+    let ((hosts, filesystems, targets, pools, configs), profiles) = try_join(
+        try_join5(
+            get_hosts(),
+            get_all::<Filesystem>(),
+            get_all::<AnyTarget>(),
+            get_all::<OstPool>(),
+            get_all::<StratagemConfiguration>(),
+        ),
+        get_all::<ServerProfile>(),
+    )
+    .await?;
+
+    let fs_names: BTreeMap<_, _> = filesystems
+        .objects
+        .iter()
+        .map(|x| (x.resource_uri.as_str(), x.name.clone()))
+        .collect();
+
+    let mut state = ClusterState {
+        // The same profiles `server add` accepts.
+        profiles: profiles
+            .objects
+            .into_iter()
+            .filter(|x| x.user_selectable)
+            .map(|x| x.name)
+            .collect(),
+        ..Default::default()
+    };
+
+    for x in hosts.objects {
+        state
+            .hosts
+            .insert(x.nodename.clone(), x.server_profile.name.clone());
+        state.hosts.insert(x.fqdn, x.server_profile.name);
+    }
+
+    for x in filesystems.objects.iter() {
+        state.osts.insert(x.name.clone(), BTreeSet::new());
+    }
+
+    let mut ost_names = BTreeMap::new();
+
+    for x in targets.objects.into_iter().filter(|x| x.kind == "OST") {
+        if let Some(fsname) = x.filesystem_name {
+            state.osts.entry(fsname).or_default().insert(x.name.clone());
+        }
+
+        ost_names.insert(x.resource_uri, x.name);
+    }
+
+    for x in pools.objects {
+        let fsname = fs_names.get(x.filesystem.as_str()).ok_or_else(|| {
+            ImlManagerCliError::ApiError(format!("Filesystem {} not found", x.filesystem))
+        })?;
+
+        let osts = x
+            .osts
+            .iter()
+            .filter_map(|uri| ost_names.get(uri).cloned())
+            .collect();
+
+        state.pools.insert((fsname.clone(), x.name), osts);
+    }
+
+    for x in configs.objects {
+        if let Some(fsname) = fs_names.get(x.filesystem.as_str()) {
+            state.intervals.insert(fsname.clone(), Interval::from(&x));
+        }
+    }
+
+    Ok(state)
+}
+
+async fn run_op(op: Op, display_type: DisplayType) -> Result<(), ImlManagerCliError> {
+    match op {
+        Op::AddHosts { hosts, profile } => {
+            server_cli(
+                ServerCommand::Add(AddHosts {
+                    hosts: hosts.into_iter().collect::<Vec<_>>().join(","),
+                    profile,
+                }),
+                display_type,
+            )
+            .await
+        }
+        Op::CreatePool {
+            filesystem,
+            pool,
+            osts,
+        } => {
+            ostpool_cli(
+                OstPoolCommand::Create {
+                    fsname: filesystem,
+                    poolname: pool,
+                    osts: osts.into_iter().collect(),
+                },
+                display_type,
+            )
+            .await
+        }
+        Op::GrowPool {
+            filesystem,
+            pool,
+            osts,
+        } => {
+            ostpool_cli(
+                OstPoolCommand::Grow {
+                    fsname: filesystem,
+                    poolname: pool,
+                    osts: osts.into_iter().collect(),
+                },
+                display_type,
+            )
+            .await
+        }
+        Op::ShrinkPool {
+            filesystem,
+            pool,
+            osts,
+        } => {
+            ostpool_cli(
+                OstPoolCommand::Shrink {
+                    fsname: filesystem,
+                    poolname: pool,
+                    osts: osts.into_iter().collect(),
+                },
+                display_type,
+            )
+            .await
+        }
+        Op::AddInterval {
+            filesystem,
+            interval,
+        } => {
+            stratagem_cli(
+                StratagemCommand::StratagemInterval(StratagemInterval::Add(
+                    StratagemIntervalConfig {
+                        filesystem,
+                        interval: interval.interval,
+                        report_duration: interval.report_duration,
+                        purge_duration: interval.purge_duration,
+                    },
+                )),
+                display_type,
+            )
+            .await
+        }
+        Op::UpdateInterval {
+            filesystem,
+            interval,
+        } => {
+            stratagem_cli(
+                StratagemCommand::StratagemInterval(StratagemInterval::Update(
+                    StratagemIntervalConfig {
+                        filesystem,
+                        interval: interval.interval,
+                        report_duration: interval.report_duration,
+                        purge_duration: interval.purge_duration,
+                    },
+                )),
+                display_type,
+            )
+            .await
+        }
+        Op::RemoveInterval { filesystem } => {
+            stratagem_cli(
+                StratagemCommand::StratagemInterval(StratagemInterval::Remove(
+                    StratagemRemoveData { name: filesystem },
+                )),
+                display_type,
+            )
+            .await
+        }
+    }
+}
+
+pub async fn apply_cli(
+    config: ApplyConfig,
+    display_type: DisplayType,
+) -> Result<(), ImlManagerCliError> {
+    let desc: ClusterDescription = serde_yaml::from_str(&std::fs::read_to_string(config.path)?)?;
+
+    let state = wrap_fut("Fetching cluster state...", get_state()).await?;
+
+    tracing::debug!("Cluster state: {:?}", state);
+
+    let plan = plan(&desc, &state)?;
+
+    display_output(&plan, display_type, || {
+        let mut table = Table::new();
+
+        for op in &plan.ops {
+            table.add_row(Row::from(&[
+                format!("{}", style("~").yellow()),
+                op.to_string(),
+            ]));
+        }
+
+        for x in &plan.warnings {
+            table.add_row(Row::from(&[format!("{}", style("!").red()), x.clone()]));
+        }
+
+        table
+    })?;
+
+    if plan.ops.is_empty() {
+        display_cancelled("Nothing to do");
+
+        return Ok(());
+    }
+
+    if config.dry_run {
+        return Ok(());
+    }
+
+    let n = plan.ops.len();
+
+    for op in plan.ops {
+        tracing::debug!("Running {}", op);
+
+        run_op(op, display_type).await?;
+    }
+
+    display_success(format!("Applied {} change(s)", n));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, plan, ClusterDescription, ClusterState, Interval, Op};
+    use std::collections::BTreeSet;
+
+    fn set(xs: &[&str]) -> BTreeSet<String> {
+        xs.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn state() -> ClusterState {
+        let mut state = ClusterState::default();
+
+        state
+            .hosts
+            .insert("oss1.local".into(), "base_managed_rh7".into());
+        state.profiles = set(&["base_managed_rh7", "base_monitored"]);
+        state.osts.insert(
+            "fs".into(),
+            set(&["fs-OST0000", "fs-OST0001", "fs-OST0002"]),
+        );
+        state.pools.insert(
+            ("fs".into(), "fast".into()),
+            set(&["fs-OST0000", "fs-OST0001"]),
+        );
+        state.intervals.insert(
+            "fs".into(),
+            Interval {
+                interval: 86_400_000,
+                report_duration: None,
+                purge_duration: None,
+            },
+        );
+
+        state
+    }
+
+    #[test]
+    fn test_plan() {
+        let desc: ClusterDescription = serde_yaml::from_str(
+            r#"
+hosts:
+  - hosts: oss1.local,oss2.local
+    profile: base_managed_rh7
+filesystems:
+  - name: fs
+    pools:
+      - name: fast
+        osts: [fs-OST0001, fs-OST0002]
+      - name: slow
+        osts: [fs-OST0000]
+    stratagem:
+      interval: 1d
+      report: 6h
+"#,
+        )
+        .unwrap();
+
+        let plan = plan(&desc, &state()).unwrap();
+
+        assert_eq!(
+            plan.ops,
+            vec![
+                Op::AddHosts {
+                    hosts: set(&["oss2.local"]),
+                    profile: "base_managed_rh7".into(),
+                },
+                Op::GrowPool {
+                    filesystem: "fs".into(),
+                    pool: "fast".into(),
+                    osts: set(&["fs-OST0002"]),
+                },
+                Op::ShrinkPool {
+                    filesystem: "fs".into(),
+                    pool: "fast".into(),
+                    osts: set(&["fs-OST0000"]),
+                },
+                Op::CreatePool {
+                    filesystem: "fs".into(),
+                    pool: "slow".into(),
+                    osts: set(&["fs-OST0000"]),
+                },
+                Op::UpdateInterval {
+                    filesystem: "fs".into(),
+                    interval: Interval {
+                        interval: 86_400_000,
+                        report_duration: Some(21_600_000),
+                        purge_duration: None,
+                    },
+                },
+            ]
+        );
+        assert!(plan.warnings.is_empty());
+        assert_eq!(
+            plan.ops[4].to_string(),
+            "iml stratagem interval update --filesystem fs --interval 1d --report 6h"
+        );
+        assert_eq!(
+            plan.ops[1].to_string(),
+            "iml filesystem pool grow fs fast fs-OST0002"
+        );
+    }
+
+    #[test]
+    fn test_plan_remove_interval() {
+        let desc: ClusterDescription = serde_yaml::from_str(
+            r#"
+hosts:
+  - hosts: oss1.local
+    profile: base_monitored
+filesystems:
+  - name: fs
+    pools:
+      - name: fast
+        osts: [fs-OST0000, fs-OST0001]
+    stratagem: null
+"#,
+        )
+        .unwrap();
+
+        let plan = plan(&desc, &state()).unwrap();
+
+        assert_eq!(
+            plan.ops,
+            vec![Op::RemoveInterval {
+                filesystem: "fs".into()
+            }]
+        );
+        assert_eq!(plan.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_plan_keep_interval() {
+        let desc: ClusterDescription = serde_yaml::from_str(
+            r#"
+filesystems:
+  - name: fs
+    pools:
+      - name: fast
+        osts: [fs-OST0000, fs-OST0001]
+"#,
+        )
+        .unwrap();
+
+        let plan = plan(&desc, &state()).unwrap();
+
+        assert!(plan.ops.is_empty());
+    }
+
+    #[test]
+    fn test_plan_unknown_profile() {
+        let desc: ClusterDescription = serde_yaml::from_str(
+            r#"
+hosts:
+  - hosts: oss2.local
+    profile: base_unknown
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan(&desc, &state()).unwrap_err().to_string(),
+            "Profile base_unknown not found"
+        );
+    }
+
+    #[test]
+    fn test_plan_unknown_ost() {
+        let desc: ClusterDescription = serde_yaml::from_str(
+            r#"
+filesystems:
+  - name: fs
+    pools:
+      - name: fast
+        osts: [fs-OST0009]
+"#,
+        )
+        .unwrap();
+
+        assert!(plan(&desc, &state()).is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(172_800_000), "2d");
+        assert_eq!(format_duration(5_400_000), "90m");
+        assert_eq!(format_duration(45_000), "45s");
+    }
+}
@@ -0,0 +1,451 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    api_utils::{check_cmds, extract_api_id, get, put},
+    display_utils::{
+        display_cancelled, display_cmd_state, display_output, display_success, format_error,
+        generate_table, wrap_fut, DisplayType,
+    },
+    error::ImlManagerCliError,
+};
+use futures::future::try_join_all;
+use iml_wire_types::{ApiList, Command, EndpointName, Job, Step};
+use prettytable::{Row, Table};
+use std::{collections::HashMap, time::Duration};
+use structopt::StructOpt;
+use tokio::time::delay_for;
+
+type AnyJob = Job<serde_json::Value>;
+
+#[derive(Debug, StructOpt)]
+pub enum CmdCommand {
+    /// List commands
+    #[structopt(name = "list")]
+    List {
+        /// Include completed commands
+        #[structopt(short = "a", long = "all")]
+        all: bool,
+        /// The number of commands to list, newest first
+        #[structopt(short = "l", long = "limit", default_value = "20")]
+        limit: u32,
+    },
+    /// Show a command, with its jobs and their steps
+    #[structopt(name = "show")]
+    Show {
+        #[structopt(name = "ID")]
+        id: u32,
+    },
+    /// Follow a running command until it completes
+    #[structopt(name = "watch")]
+    Watch {
+        #[structopt(name = "ID")]
+        id: u32,
+    },
+    /// Cancel a running command
+    #[structopt(name = "cancel")]
+    Cancel {
+        #[structopt(name = "ID")]
+        id: u32,
+    },
+}
+
+/// A `Job` and the steps it has run so far.
+#[derive(Debug, serde::Serialize)]
+pub struct JobDetail {
+    pub job: AnyJob,
+    #[serde(rename = "steps")]
+    pub step_records: Vec<Step>,
+}
+
+/// A `Command` and its jobs.
+#[derive(Debug, serde::Serialize)]
+pub struct CommandDetail {
+    pub command: Command,
+    #[serde(rename = "jobs")]
+    pub job_details: Vec<JobDetail>,
+}
+
+fn cmd_state(x: &Command) -> &'static str {
+    if !x.complete {
+        "running"
+    } else if x.errored {
+        "errored"
+    } else if x.cancelled {
+        "cancelled"
+    } else {
+        "successful"
+    }
+}
+
+async fn get_command(id: u32) -> Result<Command, ImlManagerCliError> {
+    get(
+        &format!("{}/{}", Command::endpoint_name(), id),
+        Vec::<(String, String)>::new(),
+    )
+    .await
+}
+
+/// The ids of the steps `job` has run.
+fn step_ids(job: &AnyJob) -> impl Iterator<Item = u32> + '_ {
+    job.steps
+        .iter()
+        .filter_map(|x| extract_api_id(x))
+        .filter_map(|x| x.parse().ok())
+}
+
+/// Splits `steps` up by the job in `jobs` they belong to.
+fn group_steps(jobs: &[AnyJob], steps: Vec<Step>) -> Vec<Vec<Step>> {
+    let owners: HashMap<u32, usize> = jobs
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, job)| step_ids(job).map(move |id| (id, idx)))
+        .collect();
+
+    let mut xs: Vec<Vec<Step>> = jobs.iter().map(|_| vec![]).collect();
+
+    for step in steps {
+        if let Some(idx) = owners.get(&step.id) {
+            xs[*idx].push(step);
+        }
+    }
+
+    xs
+}
+
+/// Fetches the steps of `jobs` with a single query, grouped by job.
+async fn get_steps(jobs: &[AnyJob]) -> Result<Vec<Vec<Step>>, ImlManagerCliError> {
+    let ids: Vec<_> = jobs
+        .iter()
+        .flat_map(step_ids)
+        .map(|x| ["id__in".to_string(), x.to_string()])
+        .collect();
+
+    if ids.is_empty() {
+        return Ok(group_steps(jobs, vec![]));
+    }
+
+    let query: Vec<_> = ids
+        .into_iter()
+        .chain(vec![
+            ["order_by".into(), "created_at".into()],
+            ["limit".into(), "0".into()],
+        ])
+        .collect();
+
+    let steps: ApiList<Step> = get(Step::endpoint_name(), query).await?;
+
+    Ok(group_steps(jobs, steps.objects))
+}
+
+async fn get_detail(id: u32) -> Result<CommandDetail, ImlManagerCliError> {
+    let command = get_command(id).await?;
+
+    if command.jobs.is_empty() {
+        return Ok(CommandDetail {
+            command,
+            job_details: vec![],
+        });
+    }
+
+    let job_ids: Vec<_> = command
+        .jobs
+        .iter()
+        .filter_map(|x| extract_api_id(x))
+        .map(|x| ["id__in".to_string(), x.to_string()])
+        .chain(std::iter::once(["limit".into(), "0".into()]))
+        .collect();
+
+    let jobs: ApiList<AnyJob> = get(AnyJob::endpoint_name(), job_ids).await?;
+
+    let steps = get_steps(&jobs.objects).await?;
+
+    let mut job_details: Vec<_> = jobs
+        .objects
+        .into_iter()
+        .zip(steps)
+        .map(|(job, step_records)| JobDetail { job, step_records })
+        .collect();
+
+    job_details.sort_by_key(|x| x.job.id);
+
+    Ok(CommandDetail {
+        command,
+        job_details,
+    })
+}
+
+fn format_step(x: &Step) -> String {
+    format!(
+        "[{}/{}] {}: {}",
+        x.step_index + 1,
+        x.step_count,
+        x.description,
+        x.state
+    )
+}
+
+fn format_job(x: &JobDetail) -> String {
+    let mut lines = vec![format!("{} ({})", x.job.description, x.job.state)];
+
+    for step in &x.step_records {
+        lines.push(format!("  {}", format_step(step)));
+
+        if !step.backtrace.is_empty() {
+            lines.extend(step.backtrace.lines().map(|l| format!("    {}", l)));
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Prints what changed in a command since it was last polled.
+#[derive(Default)]
+struct Watcher {
+    jobs: HashMap<u32, String>,
+    steps: HashMap<u32, String>,
+}
+
+impl Watcher {
+    fn update(&mut self, x: &CommandDetail) {
+        for job in &x.job_details {
+            if self.jobs.get(&job.job.id) != Some(&job.job.state) {
+                println!("{} ({})", job.job.description, job.job.state);
+
+                self.jobs.insert(job.job.id, job.job.state.clone());
+            }
+
+            for step in &job.step_records {
+                if self.steps.get(&step.id) == Some(&step.state) {
+                    continue;
+                }
+
+                println!("  {}", format_step(step));
+
+                if step.state == "failed" && !step.backtrace.is_empty() {
+                    println!("{}", format_error(&step.backtrace));
+                }
+
+                self.steps.insert(step.id, step.state.clone());
+            }
+        }
+    }
+}
+
+pub async fn cmd_cli(
+    command: CmdCommand,
+    display_type: DisplayType,
+) -> Result<(), ImlManagerCliError> {
+    match command {
+        CmdCommand::List { all, limit } => {
+            let limit = limit.to_string();
+
+            let mut query = vec![("order_by", "-created_at"), ("limit", limit.as_str())];
+
+            if !all {
+                query.push(("complete", "false"));
+            }
+
+            let cmds: ApiList<Command> =
+                wrap_fut("Fetching commands...", get(Command::endpoint_name(), query)).await?;
+
+            display_output(&cmds.objects, display_type, || {
+                generate_table(
+                    &["Id", "Message", "State", "Created", "Jobs"],
+                    cmds.objects.iter().map(|x| {
+                        vec![
+                            x.id.to_string(),
+                            x.message.clone(),
+                            cmd_state(x).to_string(),
+                            x.created_at.clone(),
+                            x.jobs.len().to_string(),
+                        ]
+                    }),
+                )
+            })?;
+        }
+        CmdCommand::Show { id } => {
+            let x = wrap_fut("Fetching command...", get_detail(id)).await?;
+
+            display_output(&x, display_type, || {
+                let mut table = Table::new();
+
+                table.add_row(Row::from(&["Id".to_string(), x.command.id.to_string()]));
+                table.add_row(Row::from(&[
+                    "Message".to_string(),
+                    x.command.message.clone(),
+                ]));
+                table.add_row(Row::from(&[
+                    "State".to_string(),
+                    cmd_state(&x.command).to_string(),
+                ]));
+                table.add_row(Row::from(&[
+                    "Created".to_string(),
+                    x.command.created_at.clone(),
+                ]));
+
+                for job in &x.job_details {
+                    table.add_row(Row::from(&[format!("Job {}", job.job.id), format_job(job)]));
+                }
+
+                table
+            })?;
+        }
+        CmdCommand::Watch { id } => {
+            let mut watcher = Watcher::default();
+
+            loop {
+                let x = get_detail(id).await?;
+
+                if let DisplayType::Table | DisplayType::Csv = display_type {
+                    watcher.update(&x);
+                }
+
+                if x.command.complete {
+                    match display_type {
+                        DisplayType::Table | DisplayType::Csv => display_cmd_state(&x.command),
+                        _ => display_output(&x, display_type, Table::new)?,
+                    };
+
+                    check_cmds(&[x.command])?;
+
+                    break;
+                }
+
+                delay_for(Duration::from_secs(1)).await;
+            }
+        }
+        CmdCommand::Cancel { id } => {
+            let x = wrap_fut("Fetching command...", get_detail(id)).await?;
+
+            if x.command.complete {
+                display_cancelled(format!(
+                    "Command {} is already {}",
+                    id,
+                    cmd_state(&x.command)
+                ));
+
+                return Ok(());
+            }
+
+            let jobs: Vec<_> = x
+                .job_details
+                .iter()
+                .filter(|x| {
+                    x.job
+                        .available_transitions
+                        .iter()
+                        .any(|t| t.state == "cancelled")
+                })
+                .collect();
+
+            if jobs.is_empty() {
+                return Err(ImlManagerCliError::ApiError(format!(
+                    "Command {} has no jobs that can be cancelled",
+                    id
+                )));
+            }
+
+            wrap_fut(
+                "Cancelling jobs...",
+                try_join_all(jobs.iter().map(|x| {
+                    put(
+                        &x.job.resource_uri,
+                        serde_json::json!({ "state": "cancelled" }),
+                    )
+                })),
+            )
+            .await?;
+
+            display_success(format!("Cancelled {} job(s) of command {}", jobs.len(), id));
+        }
+    };
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cmd_state, group_steps, AnyJob};
+    use iml_wire_types::{Command, Step};
+    use serde_json::json;
+
+    fn command(complete: bool, errored: bool, cancelled: bool) -> Command {
+        Command {
+            cancelled,
+            complete,
+            created_at: "2019-11-01T00:00:00".into(),
+            errored,
+            id: 1,
+            jobs: vec!["/api/job/1/".into()],
+            logs: "".into(),
+            message: "Starting filesystem fs".into(),
+            resource_uri: "/api/command/1/".into(),
+        }
+    }
+
+    #[test]
+    fn test_cmd_state() {
+        assert_eq!(cmd_state(&command(false, false, false)), "running");
+        assert_eq!(cmd_state(&command(true, true, true)), "errored");
+        assert_eq!(cmd_state(&command(true, false, true)), "cancelled");
+        assert_eq!(cmd_state(&command(true, false, false)), "successful");
+    }
+
+    fn job(id: u32, steps: &[u32]) -> AnyJob {
+        serde_json::from_value(json!({
+            "available_transitions": [],
+            "cancelled": false,
+            "class_name": "StartTargetJob",
+            "commands": ["/api/command/1/"],
+            "created_at": "2019-11-01T00:00:00",
+            "description": format!("Job {}", id),
+            "errored": false,
+            "id": id,
+            "modified_at": "2019-11-01T00:00:00",
+            "read_locks": [],
+            "resource_uri": format!("/api/job/{}/", id),
+            "state": "complete",
+            "step_results": {},
+            "steps": steps.iter().map(|x| format!("/api/step/{}/", x)).collect::<Vec<_>>(),
+            "wait_for": [],
+            "write_locks": []
+        }))
+        .unwrap()
+    }
+
+    fn step(id: u32) -> Step {
+        serde_json::from_value(json!({
+            "args": {},
+            "backtrace": "",
+            "class_name": "MountStep",
+            "console": "",
+            "created_at": "2019-11-01T00:00:00",
+            "description": format!("Step {}", id),
+            "id": id,
+            "log": "",
+            "modified_at": "2019-11-01T00:00:00",
+            "resource_uri": format!("/api/step/{}/", id),
+            "result": null,
+            "state": "success",
+            "step_count": 1,
+            "step_index": 0
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_group_steps() {
+        let jobs = vec![job(1, &[10, 12]), job(2, &[11]), job(3, &[])];
+
+        let xs = group_steps(&jobs, vec![step(10), step(11), step(12), step(99)]);
+
+        let ids: Vec<Vec<u32>> = xs
+            .iter()
+            .map(|xs| xs.iter().map(|x| x.id).collect())
+            .collect();
+
+        assert_eq!(ids, vec![vec![10, 12], vec![11], vec![]]);
+    }
+}
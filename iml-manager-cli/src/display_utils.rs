@@ -2,21 +2,87 @@
 // Use of this source code is governed by a MIT-style
 // license that can be found in the LICENSE file.
 
+use crate::error::ImlManagerCliError;
 use futures::{Future, FutureExt};
 use iml_wire_types::Command;
-use indicatif::ProgressBar;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use prettytable::{Row, Table};
 use spinners::{Spinner, Spinners};
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
+
+/// The formats command output can be printed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayType {
+    Json,
+    Yaml,
+    Csv,
+    Table,
+}
+
+impl DisplayType {
+    pub const VARIANTS: &'static [&'static str] = &["json", "yaml", "csv", "table"];
+}
+
+impl FromStr for DisplayType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(DisplayType::Json),
+            "yaml" => Ok(DisplayType::Yaml),
+            "csv" => Ok(DisplayType::Csv),
+            "table" => Ok(DisplayType::Table),
+            _ => Err(format!("Unknown output format {}", s)),
+        }
+    }
+}
+
+/// Spinners are only shown when a user is watching stdout.
+fn show_spinners() -> bool {
+    console::user_attended()
+}
 
 pub fn wrap_fut<T>(msg: &str, fut: impl Future<Output = T>) -> impl Future<Output = T> {
-    let pb = ProgressBar::new_spinner();
+    let pb = if show_spinners() {
+        ProgressBar::new_spinner()
+    } else {
+        ProgressBar::hidden()
+    };
+
     pb.enable_steady_tick(100);
     pb.set_message(msg);
 
     fut.inspect(move |_| pb.finish_and_clear())
 }
 
+/// A `MultiProgress` that is only drawn when a user is watching stdout.
+pub fn multi_progress() -> MultiProgress {
+    if show_spinners() {
+        MultiProgress::new()
+    } else {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    }
+}
+
+/// Adds a spinner showing how long it has been running to `m`,
+/// with a dimmed line under it for details.
+pub fn add_progress(m: &MultiProgress, prefix: &str, msg: &str) -> (ProgressBar, ProgressBar) {
+    let pb = m.add(ProgressBar::new_spinner());
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
+            .template("{prefix:.bold.dim} {spinner} [{elapsed}] {wide_msg}"),
+    );
+    pb.set_prefix(prefix);
+    pb.set_message(msg);
+    pb.enable_steady_tick(100);
+
+    let detail = m.add(ProgressBar::new_spinner());
+    detail.set_style(ProgressStyle::default_spinner().template("        {wide_msg:.dim}"));
+
+    (pb, detail)
+}
+
 pub fn start_spinner(msg: &str) -> impl FnOnce(Option<String>) -> () {
     let grey = termion::color::Fg(termion::color::LightBlack);
     let reset = termion::color::Fg(termion::color::Reset);
@@ -24,17 +90,22 @@ pub fn start_spinner(msg: &str) -> impl FnOnce(Option<String>) -> () {
     let s = format!("{}{}{}", grey, reset, msg);
     let s_len = s.len();
 
-    let sp = Spinner::new(Spinners::Dots9, s);
+    let sp = if show_spinners() {
+        Some(Spinner::new(Spinners::Dots9, s))
+    } else {
+        None
+    };
 
-    move |msg_opt| match msg_opt {
-        Some(msg) => {
+    move |msg_opt| match (sp, msg_opt) {
+        (Some(sp), Some(msg)) => {
             sp.message(msg);
         }
-        None => {
+        (Some(sp), None) => {
             sp.stop();
             print!("{}", termion::clear::CurrentLine);
             print!("{}", termion::cursor::Left(s_len as u16));
         }
+        (None, _) => {}
     }
 }
 
@@ -48,8 +119,10 @@ pub fn format_cmd_state(cmd: &Command) -> String {
     }
 }
 
+/// Status messages are written to stderr,
+/// so stdout only ever holds the output of a command.
 pub fn display_cmd_state(cmd: &Command) {
-    println!("{}", format_cmd_state(&cmd));
+    eprintln!("{}", format_cmd_state(&cmd));
 }
 
 pub fn format_cancelled(message: impl Display) -> String {
@@ -57,7 +130,7 @@ pub fn format_cancelled(message: impl Display) -> String {
 }
 
 pub fn display_cancelled(message: impl Display) {
-    println!("{}", format_cancelled(&message));
+    eprintln!("{}", format_cancelled(&message));
 }
 
 pub fn format_success(message: impl Display) -> String {
@@ -68,7 +141,7 @@ pub fn format_success(message: impl Display) -> String {
 }
 
 pub fn display_success(message: impl Display) {
-    println!("{}", format_success(message))
+    eprintln!("{}", format_success(message))
 }
 
 pub fn format_error(message: impl Display) -> String {
@@ -79,7 +152,7 @@ pub fn format_error(message: impl Display) -> String {
 }
 
 pub fn display_error(message: impl Display) {
-    println!("{}", format_error(message))
+    eprintln!("{}", format_error(message))
 }
 
 pub fn generate_table<Rows, R>(columns: &[&str], rows: Rows) -> Table
@@ -98,3 +171,75 @@ where
 
     table
 }
+
+/// Formats `x` as `display_type`.
+///
+/// `table` builds the tabular form, which is also used for CSV.
+pub fn format_output<T: serde::Serialize>(
+    x: &T,
+    display_type: DisplayType,
+    table: impl FnOnce() -> Table,
+) -> Result<String, ImlManagerCliError> {
+    let s = match display_type {
+        DisplayType::Json => serde_json::to_string_pretty(x)?,
+        DisplayType::Yaml => serde_yaml::to_string(x)?,
+        DisplayType::Csv => {
+            let mut buf = vec![];
+
+            table().to_csv(&mut buf)?.flush()?;
+
+            String::from_utf8_lossy(&buf).into_owned()
+        }
+        DisplayType::Table => table().to_string(),
+    };
+
+    Ok(s)
+}
+
+pub fn display_output<T: serde::Serialize>(
+    x: &T,
+    display_type: DisplayType,
+    table: impl FnOnce() -> Table,
+) -> Result<(), ImlManagerCliError> {
+    println!("{}", format_output(x, display_type, table)?.trim_end());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_output, generate_table, DisplayType};
+
+    fn output(display_type: DisplayType) -> String {
+        let xs = vec![("fs1", 2), ("fs2", 0)];
+
+        format_output(&xs, display_type, || {
+            generate_table(
+                &["Name", "OSTs"],
+                xs.iter().map(|(a, b)| vec![a.to_string(), b.to_string()]),
+            )
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_format_json() {
+        let x: serde_json::Value = serde_json::from_str(&output(DisplayType::Json)).unwrap();
+
+        assert_eq!(x, serde_json::json!([["fs1", 2], ["fs2", 0]]));
+    }
+
+    #[test]
+    fn test_format_csv() {
+        assert_eq!(output(DisplayType::Csv), "Name,OSTs\nfs1,2\nfs2,0\n");
+    }
+
+    #[test]
+    fn test_parse_display_type() {
+        for x in DisplayType::VARIANTS {
+            assert!(x.parse::<DisplayType>().is_ok());
+        }
+
+        assert!("xml".parse::<DisplayType>().is_err());
+    }
+}
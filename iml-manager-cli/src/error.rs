@@ -66,6 +66,9 @@ pub enum ImlManagerCliError {
     IoError(std::io::Error),
     CombineEasyError(combine::stream::easy::Errors<char, &'static str, usize>),
     ReqwestError(reqwest::Error),
+    SerdeYamlError(serde_yaml::Error),
+    CsvError(csv::Error),
+    ConfigError(String),
 }
 
 impl std::fmt::Display for ImlManagerCliError {
@@ -83,6 +86,46 @@ impl std::fmt::Display for ImlManagerCliError {
             ImlManagerCliError::IoError(ref err) => write!(f, "{}", err),
             ImlManagerCliError::CombineEasyError(ref err) => write!(f, "{}", err),
             ImlManagerCliError::ReqwestError(ref err) => write!(f, "{}", err),
+            ImlManagerCliError::SerdeYamlError(ref err) => write!(f, "{}", err),
+            ImlManagerCliError::CsvError(ref err) => write!(f, "{}", err),
+            ImlManagerCliError::ConfigError(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Exit codes returned by the CLI.
+///
+/// These are stable, so scripts can tell failures apart.
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NOT_FOUND: i32 = 3;
+pub const EXIT_API_ERROR: i32 = 4;
+pub const EXIT_UNREACHABLE: i32 = 5;
+
+fn reqwest_exit_code(err: &reqwest::Error) -> i32 {
+    if err.is_status() {
+        EXIT_API_ERROR
+    } else {
+        EXIT_UNREACHABLE
+    }
+}
+
+impl ImlManagerCliError {
+    /// The code to exit the process with for this error.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            ImlManagerCliError::IntParseError(_)
+            | ImlManagerCliError::ParseDurationError(_)
+            | ImlManagerCliError::CombineEasyError(_)
+            | ImlManagerCliError::ConfigError(_) => EXIT_USAGE,
+            ImlManagerCliError::DoesNotExist(_) => EXIT_NOT_FOUND,
+            ImlManagerCliError::ApiError(_)
+            | ImlManagerCliError::RunStratagemValidationError(_) => EXIT_API_ERROR,
+            ImlManagerCliError::ClientRequestError(
+                iml_manager_client::ImlManagerClientError::Reqwest(ref err),
+            )
+            | ImlManagerCliError::ReqwestError(ref err) => reqwest_exit_code(err),
+            _ => EXIT_ERROR,
         }
     }
 }
@@ -108,6 +151,9 @@ impl std::error::Error for ImlManagerCliError {
             ImlManagerCliError::IoError(ref err) => Some(err),
             ImlManagerCliError::CombineEasyError(ref err) => Some(err),
             ImlManagerCliError::ReqwestError(ref err) => Some(err),
+            ImlManagerCliError::SerdeYamlError(ref err) => Some(err),
+            ImlManagerCliError::CsvError(ref err) => Some(err),
+            ImlManagerCliError::ConfigError(_) => None,
         }
     }
 }
@@ -177,3 +223,15 @@ impl From<reqwest::Error> for ImlManagerCliError {
         ImlManagerCliError::ReqwestError(err)
     }
 }
+
+impl From<serde_yaml::Error> for ImlManagerCliError {
+    fn from(err: serde_yaml::Error) -> Self {
+        ImlManagerCliError::SerdeYamlError(err)
+    }
+}
+
+impl From<csv::Error> for ImlManagerCliError {
+    fn from(err: csv::Error) -> Self {
+        ImlManagerCliError::CsvError(err)
+    }
+}
@@ -3,15 +3,21 @@
 // license that can be found in the LICENSE file.
 
 use crate::{
-    api_utils::{get, get_all, get_one},
-    display_utils::{generate_table, wrap_fut},
+    api_utils::{
+        check_cmds, get, get_all, get_available_actions, get_hosts, get_one, post,
+        run_available_action, wait_for_cmds, CmdWrapper,
+    },
+    display_utils::{display_cancelled, display_output, generate_table, wrap_fut, DisplayType},
     error::ImlManagerCliError,
     ostpool::{ostpool_cli, OstPoolCommand},
 };
 use futures::future::{try_join, try_join_all};
-use iml_wire_types::{ApiList, Filesystem, FlatQuery, Mgt, Ost};
+use iml_wire_types::{
+    ApiList, EndpointName, Filesystem, FlatQuery, Host, Mgt, Ost, Volume, VolumeNode,
+};
 use number_formatter::{format_bytes, format_number};
 use prettytable::{Row, Table};
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -25,6 +31,34 @@ pub enum FilesystemCommand {
         #[structopt(name = "FSNAME")]
         fsname: String,
     },
+    /// Start a filesystem
+    #[structopt(name = "start")]
+    Start {
+        #[structopt(name = "FSNAME")]
+        fsname: String,
+    },
+    /// Stop a filesystem
+    #[structopt(name = "stop")]
+    Stop {
+        #[structopt(name = "FSNAME")]
+        fsname: String,
+    },
+    /// Remove a filesystem from IML
+    #[structopt(name = "remove")]
+    Remove {
+        #[structopt(name = "FSNAME")]
+        fsname: String,
+    },
+    /// Create a filesystem from a YAML or JSON description
+    #[structopt(name = "create")]
+    Create {
+        /// The filesystem description
+        #[structopt(name = "FILE", parse(from_os_str))]
+        path: PathBuf,
+        /// Reformat volumes that already contain a filesystem
+        #[structopt(long = "reformat")]
+        reformat: bool,
+    },
     /// Ost Pools
     #[structopt(name = "pool")]
     Pool {
@@ -33,6 +67,47 @@ pub enum FilesystemCommand {
     },
 }
 
+/// A target of a new filesystem, on the volume at `path` on `host`.
+#[derive(Debug, PartialEq, serde::Deserialize)]
+pub struct TargetDescription {
+    pub host: String,
+    pub path: String,
+}
+
+/// The MGT of a new filesystem.
+///
+/// Without a `path`, the existing MGT on `host` is shared.
+#[derive(Debug, PartialEq, serde::Deserialize)]
+pub struct MgtDescription {
+    pub host: String,
+    pub path: Option<String>,
+}
+
+/// A new filesystem, i.e.
+///
+/// ```yaml
+/// name: fs1
+/// mgt:
+///   host: mds1.local
+///   path: /dev/mapper/mpatha
+/// mdts:
+///   - host: mds1.local
+///     path: /dev/mapper/mpathb
+/// osts:
+///   - host: oss1.local
+///     path: /dev/mapper/mpathc
+/// ```
+#[derive(Debug, PartialEq, serde::Deserialize)]
+pub struct FilesystemDescription {
+    pub name: String,
+    pub mgt: MgtDescription,
+    pub mdts: Vec<TargetDescription>,
+    pub osts: Vec<TargetDescription>,
+    /// Reformat volumes that already contain a filesystem
+    #[serde(default)]
+    pub reformat: bool,
+}
+
 fn usage(
     free: Option<f64>,
     total: Option<f64>,
@@ -49,7 +124,168 @@ fn usage(
     }
 }
 
-pub async fn filesystem_cli(command: FilesystemCommand) -> Result<(), ImlManagerCliError> {
+fn find_host<'a>(hosts: &'a [Host], name: &str) -> Result<&'a Host, ImlManagerCliError> {
+    hosts
+        .iter()
+        .find(|x| x.fqdn == name || x.nodename == name)
+        .ok_or_else(|| ImlManagerCliError::ApiError(format!("Host {} not found", name)))
+}
+
+/// Resolves the volume at `path` on `host`.
+async fn get_volume(host: &Host, path: &str) -> Result<Volume, ImlManagerCliError> {
+    let host_id = host.id.to_string();
+
+    let mut xs: ApiList<VolumeNode> = get(
+        VolumeNode::endpoint_name(),
+        vec![("host", host_id.as_str()), ("path", path), ("limit", "0")],
+    )
+    .await?;
+
+    let node = match xs.objects.len() {
+        1 => xs.objects.remove(0),
+        0 => {
+            return Err(ImlManagerCliError::ApiError(format!(
+                "No volume found at {}:{}",
+                host.fqdn, path
+            )))
+        }
+        _ => {
+            return Err(ImlManagerCliError::ApiError(format!(
+                "{}:{} matches more than one volume",
+                host.fqdn, path
+            )))
+        }
+    };
+
+    get(
+        &format!("{}/{}", Volume::endpoint_name(), node.volume_id),
+        Volume::query(),
+    )
+    .await
+}
+
+async fn get_target_volumes(
+    hosts: &[Host],
+    xs: &[TargetDescription],
+) -> Result<Vec<Volume>, ImlManagerCliError> {
+    try_join_all(xs.iter().map(|x| {
+        async move { get_volume(find_host(hosts, &x.host)?, &x.path).await }
+    }))
+    .await
+}
+
+/// Resolves each target in `desc` to a volume,
+/// and builds the body of a filesystem `POST`.
+async fn filesystem_body(
+    desc: &FilesystemDescription,
+    reformat: bool,
+) -> Result<serde_json::Value, ImlManagerCliError> {
+    let hosts = get_hosts().await?.objects;
+
+    let mdts = get_target_volumes(&hosts, &desc.mdts).await?;
+    let osts = get_target_volumes(&hosts, &desc.osts).await?;
+
+    let mgs = find_host(&hosts, &desc.mgt.host)?;
+
+    let mgt_volume = match desc.mgt.path.as_ref() {
+        Some(path) => Some(get_volume(mgs, path).await?),
+        None => None,
+    };
+
+    let formatted: Vec<_> = mgt_volume
+        .iter()
+        .chain(mdts.iter())
+        .chain(osts.iter())
+        .filter(|x| x.filesystem_type.is_some())
+        .map(|x| x.label.as_str())
+        .collect();
+
+    if !reformat && !formatted.is_empty() {
+        return Err(ImlManagerCliError::ApiError(format!(
+            "Volumes {} already contain a filesystem, pass --reformat to overwrite them",
+            formatted.join(", ")
+        )));
+    }
+
+    let target = |x: &Volume| {
+        serde_json::json!({
+            "volume_id": x.id,
+            "conf_params": {},
+            "reformat": reformat
+        })
+    };
+
+    let mgt = match mgt_volume {
+        Some(x) => target(&x),
+        None => {
+            let mgt: Mgt = get_one(vec![("kind", "MGT"), ("host_id", &mgs.id.to_string())])
+                .await
+                .map_err(|e| match e {
+                    ImlManagerCliError::DoesNotExist(_) => {
+                        ImlManagerCliError::ApiError(format!("No MGT found on {}", desc.mgt.host))
+                    }
+                    e => e,
+                })?;
+
+            serde_json::json!({ "id": mgt.id })
+        }
+    };
+
+    Ok(serde_json::json!({
+        "name": desc.name,
+        "conf_params": {},
+        "mgt": mgt,
+        "mdts": mdts.iter().map(target).collect::<Vec<_>>(),
+        "osts": osts.iter().map(target).collect::<Vec<_>>(),
+    }))
+}
+
+/// Moves the filesystem `fsname` to `state`, through its available actions.
+async fn change_state(fsname: &str, state: &str) -> Result<(), ImlManagerCliError> {
+    let fs: Filesystem =
+        wrap_fut("Fetching filesystem...", get_one(vec![("name", fsname)])).await?;
+
+    if fs.state == state {
+        display_cancelled(format!("Filesystem {} is already {}", fsname, state));
+
+        return Ok(());
+    }
+
+    let actions = wrap_fut(
+        "Fetching available actions...",
+        get_available_actions(fs.id, fs.content_type_id),
+    )
+    .await?;
+
+    let action = actions
+        .objects
+        .into_iter()
+        .find(|x| x.state.as_deref() == Some(state))
+        .ok_or_else(|| {
+            ImlManagerCliError::ApiError(format!(
+                "Filesystem {} can not be moved from {} to {}",
+                fsname, fs.state, state
+            ))
+        })?;
+
+    match run_available_action(&action, &fs.resource_uri).await? {
+        Some(cmd) => {
+            let cmds = wait_for_cmds(vec![cmd]).await?;
+
+            check_cmds(&cmds)
+        }
+        None => {
+            display_cancelled(format!("Filesystem {} is already {}", fsname, state));
+
+            Ok(())
+        }
+    }
+}
+
+pub async fn filesystem_cli(
+    command: FilesystemCommand,
+    display_type: DisplayType,
+) -> Result<(), ImlManagerCliError> {
     match command {
         FilesystemCommand::List => {
             let filesystems: ApiList<Filesystem> =
@@ -57,24 +293,24 @@ pub async fn filesystem_cli(command: FilesystemCommand) -> Result<(), ImlManager
 
             tracing::debug!("FSs: {:?}", filesystems);
 
-            let table = generate_table(
-                &[
-                    "Name", "State", "Space", "Inodes", "Clients", "MDTs", "OSTs",
-                ],
-                filesystems.objects.into_iter().map(|f| {
-                    vec![
-                        f.label,
-                        f.state,
-                        usage(f.bytes_free, f.bytes_total, format_bytes),
-                        usage(f.files_free, f.files_total, format_number),
-                        format_number(f.client_count.unwrap_or(0.0), Some(0)),
-                        f.mdts.len().to_string(),
-                        f.osts.len().to_string(),
-                    ]
-                }),
-            );
-
-            table.printstd();
+            display_output(&filesystems.objects, display_type, || {
+                generate_table(
+                    &[
+                        "Name", "State", "Space", "Inodes", "Clients", "MDTs", "OSTs",
+                    ],
+                    filesystems.objects.iter().map(|f| {
+                        vec![
+                            f.label.clone(),
+                            f.state.clone(),
+                            usage(f.bytes_free, f.bytes_total, format_bytes),
+                            usage(f.files_free, f.files_total, format_number),
+                            format_number(f.client_count.unwrap_or(0.0), Some(0)),
+                            f.mdts.len().to_string(),
+                            f.osts.len().to_string(),
+                        ]
+                    }),
+                )
+            })?;
         }
         FilesystemCommand::Show { fsname } => {
             let fs: Filesystem =
@@ -84,43 +320,117 @@ pub async fn filesystem_cli(command: FilesystemCommand) -> Result<(), ImlManager
 
             let (mgt, osts): (Mgt, Vec<Ost>) = try_join(
                 wrap_fut("Fetching MGT...", get(&fs.mgt, Mgt::query())),
-                try_join_all(fs.osts.into_iter().map(|o| {
-                    async move { wrap_fut("Fetching OST...", get(&o, Ost::query())).await }
+                try_join_all(fs.osts.iter().map(|o| {
+                    async move { wrap_fut("Fetching OST...", get(o, Ost::query())).await }
                 })),
             )
             .await?;
 
-            let mut table = Table::new();
-            table.add_row(Row::from(&["Name".to_string(), fs.label]));
-            table.add_row(Row::from(&[
-                "Space".to_string(),
-                usage(fs.bytes_free, fs.bytes_total, format_bytes),
-            ]));
-            table.add_row(Row::from(&[
-                "Inodes".to_string(),
-                usage(fs.files_free, fs.files_total, format_number),
-            ]));
-            table.add_row(Row::from(&["State".to_string(), fs.state]));
-            table.add_row(Row::from(&[
-                "Management Server".to_string(),
-                mgt.active_host_name,
-            ]));
-
-            let mdtnames: Vec<String> = fs.mdts.into_iter().map(|m| m.name).collect();
-            table.add_row(Row::from(&["MDTs".to_string(), mdtnames.join("\n")]));
-
-            let ostnames: Vec<String> = osts.into_iter().map(|m| m.name).collect();
-            table.add_row(Row::from(&["OSTs".to_string(), ostnames.join("\n")]));
-
-            table.add_row(Row::from(&[
-                "Clients".to_string(),
-                format!("{:0}", fs.client_count.unwrap_or(0.0)),
-            ]));
-            table.add_row(Row::from(&["Mount Path".to_string(), fs.mount_path]));
-            table.printstd();
+            display_output(&fs, display_type, || {
+                let mut table = Table::new();
+                table.add_row(Row::from(&["Name".to_string(), fs.label.clone()]));
+                table.add_row(Row::from(&[
+                    "Space".to_string(),
+                    usage(fs.bytes_free, fs.bytes_total, format_bytes),
+                ]));
+                table.add_row(Row::from(&[
+                    "Inodes".to_string(),
+                    usage(fs.files_free, fs.files_total, format_number),
+                ]));
+                table.add_row(Row::from(&["State".to_string(), fs.state.clone()]));
+                table.add_row(Row::from(&[
+                    "Management Server".to_string(),
+                    mgt.active_host_name,
+                ]));
+
+                let mdtnames: Vec<&str> = fs.mdts.iter().map(|m| m.name.as_str()).collect();
+                table.add_row(Row::from(&["MDTs".to_string(), mdtnames.join("\n")]));
+
+                let ostnames: Vec<String> = osts.into_iter().map(|m| m.name).collect();
+                table.add_row(Row::from(&["OSTs".to_string(), ostnames.join("\n")]));
+
+                table.add_row(Row::from(&[
+                    "Clients".to_string(),
+                    format!("{:0}", fs.client_count.unwrap_or(0.0)),
+                ]));
+                table.add_row(Row::from(&[
+                    "Mount Path".to_string(),
+                    fs.mount_path.clone(),
+                ]));
+
+                table
+            })?;
         }
-        FilesystemCommand::Pool { command } => ostpool_cli(command).await?,
+        FilesystemCommand::Start { fsname } => change_state(&fsname, "available").await?,
+        FilesystemCommand::Stop { fsname } => change_state(&fsname, "stopped").await?,
+        FilesystemCommand::Remove { fsname } => change_state(&fsname, "removed").await?,
+        FilesystemCommand::Create { path, reformat } => {
+            let desc: FilesystemDescription =
+                serde_yaml::from_str(&std::fs::read_to_string(path)?)?;
+
+            let body = wrap_fut(
+                "Resolving volumes...",
+                filesystem_body(&desc, reformat || desc.reformat),
+            )
+            .await?;
+
+            tracing::debug!("Filesystem: {}", body);
+
+            let CmdWrapper { command } = post(Filesystem::endpoint_name(), body)
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            let cmds = wait_for_cmds(vec![command]).await?;
+
+            check_cmds(&cmds)?;
+        }
+        FilesystemCommand::Pool { command } => ostpool_cli(command, display_type).await?,
     };
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{FilesystemDescription, MgtDescription, TargetDescription};
+
+    #[test]
+    fn test_parse_filesystem_description() {
+        let desc: FilesystemDescription = serde_yaml::from_str(
+            r#"
+name: fs1
+mgt:
+  host: mds1.local
+mdts:
+  - host: mds1.local
+    path: /dev/sdb
+osts:
+  - host: oss1.local
+    path: /dev/sdc
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            desc,
+            FilesystemDescription {
+                name: "fs1".into(),
+                mgt: MgtDescription {
+                    host: "mds1.local".into(),
+                    path: None,
+                },
+                mdts: vec![TargetDescription {
+                    host: "mds1.local".into(),
+                    path: "/dev/sdb".into(),
+                }],
+                osts: vec![TargetDescription {
+                    host: "oss1.local".into(),
+                    path: "/dev/sdc".into(),
+                }],
+                reformat: false,
+            }
+        );
+    }
+}
@@ -2,11 +2,18 @@
 // Use of this source code is governed by a MIT-style
 // license that can be found in the LICENSE file.
 
+pub mod alert;
 pub mod api_utils;
+pub mod apply;
+pub mod command;
 pub mod display_utils;
 pub mod error;
 pub mod filesystem;
+pub mod messaging;
 pub mod ostpool;
+pub mod profile;
+pub mod progress;
 pub mod server;
 pub mod stratagem;
+pub mod target;
 pub mod update_repo_file;
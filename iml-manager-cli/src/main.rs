@@ -3,10 +3,16 @@
 // license that can be found in the LICENSE file.
 
 use iml_manager_cli::{
-    display_utils::display_error,
+    alert::{self, alert_cli},
+    apply::{self, apply_cli},
+    command::{self, cmd_cli},
+    display_utils::{format_error, DisplayType},
+    error::EXIT_USAGE,
     filesystem::{self, filesystem_cli},
+    profile,
     server::{self, server_cli},
     stratagem::{self, stratagem_cli},
+    target::{self, target_cli},
     update_repo_file::{self, update_repo_file_cli},
 };
 use std::process::exit;
@@ -14,10 +20,7 @@ use structopt::StructOpt;
 use tracing_subscriber::{fmt::Subscriber, EnvFilter};
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "iml")]
-#[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
-/// The Integrated Manager for Lustre CLI
-pub enum App {
+pub enum AppCommand {
     #[structopt(name = "stratagem")]
     /// Work with Stratagem server
     Stratagem {
@@ -36,11 +39,52 @@ pub enum App {
         #[structopt(subcommand)]
         command: filesystem::FilesystemCommand,
     },
+    #[structopt(name = "target")]
+    /// Work with Lustre targets
+    Target {
+        #[structopt(subcommand)]
+        command: target::TargetCommand,
+    },
+    #[structopt(name = "alert")]
+    /// Work with alerts
+    Alert {
+        #[structopt(subcommand)]
+        command: alert::AlertCommand,
+    },
+    #[structopt(name = "command")]
+    /// Work with commands and their jobs
+    Command {
+        #[structopt(subcommand)]
+        command: command::CmdCommand,
+    },
+    #[structopt(name = "apply")]
+    /// Bring the cluster in line with a declarative description
+    Apply(apply::ApplyConfig),
     #[structopt(name = "update_repo")]
     ///  Update Agent repo files
     UpdateRepoFile(update_repo_file::UpdateRepoFileHosts),
 }
 
+#[derive(Debug, StructOpt)]
+#[structopt(name = "iml")]
+#[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
+/// The Integrated Manager for Lustre CLI
+pub struct App {
+    /// The format to print output in
+    #[structopt(
+        short = "o",
+        long = "output",
+        default_value = "table",
+        raw(global = "true", possible_values = "DisplayType::VARIANTS")
+    )]
+    output: DisplayType,
+    /// The profile to connect with, from the CLI config file
+    #[structopt(long = "profile", raw(global = "true"))]
+    profile: Option<String>,
+    #[structopt(subcommand)]
+    command: AppCommand,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let subscriber = Subscriber::builder()
@@ -49,22 +93,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
-    dotenv::from_path("/var/lib/chroma/iml-settings.conf").expect("Could not load cli env");
-
-    let matches = App::from_args();
+    let matches = match App::from_iter_safe(std::env::args_os()) {
+        Ok(x) => x,
+        Err(e) if e.use_stderr() => {
+            eprintln!("{}", e.message);
+            exit(EXIT_USAGE);
+        }
+        Err(e) => e.exit(),
+    };
 
     tracing::debug!("Matching args {:?}", matches);
 
-    let r = match matches {
-        App::Stratagem { command } => stratagem_cli(command).await,
-        App::Server { command } => server_cli(command).await,
-        App::UpdateRepoFile(config) => update_repo_file_cli(config).await,
-        App::Filesystem { command } => filesystem_cli(command).await,
+    if let Err(e) = profile::load_env(matches.profile) {
+        eprintln!("{}", format_error(&e));
+        exit(e.exit_code());
+    }
+
+    let display_type = matches.output;
+
+    let r = match matches.command {
+        AppCommand::Stratagem { command } => stratagem_cli(command, display_type).await,
+        AppCommand::Server { command } => server_cli(command, display_type).await,
+        AppCommand::UpdateRepoFile(config) => update_repo_file_cli(config).await,
+        AppCommand::Filesystem { command } => filesystem_cli(command, display_type).await,
+        AppCommand::Target { command } => target_cli(command, display_type).await,
+        AppCommand::Alert { command } => alert_cli(command, display_type).await,
+        AppCommand::Command { command } => cmd_cli(command, display_type).await,
+        AppCommand::Apply(config) => apply_cli(config, display_type).await,
     };
 
     if let Err(e) = r {
-        display_error(e);
-        exit(1);
+        eprintln!("{}", format_error(&e));
+        exit(e.exit_code());
     }
 
     Ok(())
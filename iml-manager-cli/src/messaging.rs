@@ -0,0 +1,189 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! Follows the warp-drive SSE stream at `/messaging`.
+
+use crate::error::ImlManagerCliError;
+use futures::{stream, Stream};
+use iml_manager_client::Response;
+use iml_wire_types::warp_drive::Message;
+use std::{collections::VecDeque, time::Duration};
+use tokio::time::delay_for;
+
+/// A single server-sent event.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Event {
+    pub id: Option<String>,
+    pub data: String,
+}
+
+/// Splits a byte stream into server-sent events.
+///
+/// Chunks may end anywhere, so incomplete lines are held until the rest arrives.
+#[derive(Debug, Default)]
+pub struct EventParser {
+    buf: Vec<u8>,
+    event: Event,
+    has_data: bool,
+}
+
+impl EventParser {
+    /// Feeds a chunk in, returning the events it completed.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Event> {
+        self.buf.extend_from_slice(chunk);
+
+        let mut xs = vec![];
+
+        while let Some(idx) = self.buf.iter().position(|&x| x == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=idx).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(&['\n', '\r'][..]);
+
+            if line.is_empty() {
+                if self.has_data {
+                    xs.push(std::mem::take(&mut self.event));
+                    self.has_data = false;
+                }
+
+                continue;
+            }
+
+            // Lines starting with `:` are comments, i.e. keep-alives.
+            if line.starts_with(':') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, ':');
+            let field = parts.next().unwrap_or_default();
+            let value = parts.next().unwrap_or_default().trim_start_matches(' ');
+
+            match field {
+                "data" => {
+                    if self.has_data {
+                        self.event.data.push('\n');
+                    }
+
+                    self.event.data.push_str(value);
+                    self.has_data = true;
+                }
+                "id" => self.event.id = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        xs
+    }
+}
+
+struct State {
+    query: Vec<(String, String)>,
+    resp: Option<Response>,
+    parser: EventParser,
+    last_event_id: Option<String>,
+    pending: VecDeque<Message>,
+}
+
+async fn connect(
+    query: &[(String, String)],
+    last_event_id: Option<&str>,
+) -> Result<Response, ImlManagerCliError> {
+    let client = iml_manager_client::get_stream_client()?;
+
+    iml_manager_client::get_messaging(client, query, last_event_id)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Streams `Message`s from warp-drive, narrowed down by the subscription in `query`.
+///
+/// Dropped connections are reopened from the last event seen.
+/// Only a failure to make the first connection ends the stream.
+pub fn messages(
+    query: Vec<(String, String)>,
+) -> impl Stream<Item = Result<Message, ImlManagerCliError>> {
+    let state = State {
+        query,
+        resp: None,
+        parser: EventParser::default(),
+        last_event_id: None,
+        pending: VecDeque::new(),
+    };
+
+    stream::unfold(Some(state), |state| {
+        async move {
+            let mut state = state?;
+
+            loop {
+                if let Some(x) = state.pending.pop_front() {
+                    return Some((Ok(x), Some(state)));
+                }
+
+                let resp = match state.resp.as_mut() {
+                    Some(resp) => resp,
+                    None => {
+                        match connect(&state.query, state.last_event_id.as_deref()).await {
+                            Ok(resp) => state.resp = Some(resp),
+                            Err(e) if state.last_event_id.is_none() => return Some((Err(e), None)),
+                            Err(e) => {
+                                tracing::info!("Could not reconnect to warp-drive: {}", e);
+
+                                delay_for(Duration::from_secs(5)).await;
+                            }
+                        };
+
+                        continue;
+                    }
+                };
+
+                match resp.chunk().await {
+                    Ok(Some(chunk)) => {
+                        for event in state.parser.push(&chunk) {
+                            if event.id.is_some() {
+                                state.last_event_id = event.id;
+                            }
+
+                            match serde_json::from_str(&event.data) {
+                                Ok(x) => state.pending.push_back(x),
+                                Err(e) => tracing::warn!("Skipping unknown message: {}", e),
+                            }
+                        }
+                    }
+                    Ok(None) | Err(_) => {
+                        tracing::info!("Lost connection to warp-drive, reconnecting");
+
+                        state.resp = None;
+                        state.parser = EventParser::default();
+
+                        delay_for(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, EventParser};
+
+    #[test]
+    fn test_parse_events() {
+        let mut parser = EventParser::default();
+
+        assert_eq!(parser.push(b": keep-alive\n\nid: 1\nda"), vec![]);
+        assert_eq!(
+            parser.push(b"ta: {\"a\":1}\r\n\nid:2\ndata:x\ndata:y\n\n"),
+            vec![
+                Event {
+                    id: Some("1".into()),
+                    data: "{\"a\":1}".into(),
+                },
+                Event {
+                    id: Some("2".into()),
+                    data: "x\ny".into(),
+                },
+            ]
+        );
+    }
+}
@@ -3,14 +3,16 @@
 // license that can be found in the LICENSE file.
 
 use crate::{
-    api_utils::{delete, get, get_all, get_one, post, wait_for_cmd, wait_for_cmds},
-    display_utils::{generate_table, wrap_fut},
+    api_utils::{delete, get, get_all, get_one, post, put, wait_for_cmd, wait_for_cmds},
+    display_utils::{display_cancelled, display_output, generate_table, wrap_fut, DisplayType},
     error::ImlManagerCliError,
 };
 use console::{style, Term};
 use futures::future::try_join_all;
+use iml_manager_client::StatusCode;
 use iml_wire_types::{ApiList, Command, EndpointName, Filesystem, FlatQuery, Ost, OstPool};
 use prettytable::{Row, Table};
+use std::collections::BTreeSet;
 use structopt::StructOpt;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -82,11 +84,44 @@ async fn pool_lookup(fsname: &String, pool: &String) -> Result<OstPool, ImlManag
     .await
 }
 
-pub async fn ostpool_cli(command: OstPoolCommand) -> Result<(), ImlManagerCliError> {
-    let term = Term::stdout();
+/// Fetches the names of the OSTs in `pool`.
+async fn pool_ost_names(pool: &OstPool) -> Result<BTreeSet<String>, ImlManagerCliError> {
+    let osts: Vec<Ost> = try_join_all(pool.osts.iter().map(|o| get(o, Ost::query()))).await?;
+
+    Ok(osts.into_iter().map(|o| o.name).collect())
+}
+
+/// Sets the OSTs of `pool` to `osts`, returning the resulting command.
+///
+/// `None` is returned if the pool already held exactly `osts`.
+async fn update_pool(
+    pool: &OstPool,
+    osts: BTreeSet<String>,
+) -> Result<Option<Command>, ImlManagerCliError> {
+    let resp = put(
+        &format!("{}/{}", OstPool::endpoint_name(), pool.id),
+        serde_json::json!({ "osts": osts }),
+    )
+    .await?
+    .error_for_status()?;
+
+    if resp.status() == StatusCode::NO_CONTENT {
+        return Ok(None);
+    }
+
+    let objs: ObjCommand = resp.json().await?;
+
+    Ok(Some(objs.command))
+}
+
+pub async fn ostpool_cli(
+    command: OstPoolCommand,
+    display_type: DisplayType,
+) -> Result<(), ImlManagerCliError> {
+    let term = Term::stderr();
     match command {
         OstPoolCommand::List { fsname } => {
-            let xs = match fsname {
+            let xs: Vec<(String, OstPool)> = match fsname {
                 Some(fsname) => {
                     let fs: Filesystem =
                         wrap_fut("Fetching Filesystem ...", get_one(vec![("name", &fsname)]))
@@ -102,7 +137,7 @@ pub async fn ostpool_cli(command: OstPoolCommand) -> Result<(), ImlManagerCliErr
                     pools
                         .objects
                         .into_iter()
-                        .map(|p| vec![fsname.clone(), p.name, p.osts.len().to_string()])
+                        .map(|p| (fsname.clone(), p))
                         .collect()
                 }
                 None => {
@@ -111,35 +146,45 @@ pub async fn ostpool_cli(command: OstPoolCommand) -> Result<(), ImlManagerCliErr
                     // @@ "cache" this
                     try_join_all(pools.objects.into_iter().map(|p| {
                         async move {
-                            get(&p.filesystem, Filesystem::query()).await.map(
-                                move |fs: Filesystem| {
-                                    vec![fs.name, p.name, p.osts.len().to_string()]
-                                },
-                            )
+                            get(&p.filesystem, Filesystem::query())
+                                .await
+                                .map(move |fs: Filesystem| (fs.name, p))
                         }
                     }))
                     .await?
                 }
             };
 
-            let table = generate_table(&["Filesystem", "Pool Name", "OST Count"], xs);
-            table.printstd();
+            let (fsnames, pools): (Vec<_>, Vec<_>) = xs.into_iter().unzip();
+
+            display_output(&pools, display_type, || {
+                generate_table(
+                    &["Filesystem", "Pool Name", "OST Count"],
+                    fsnames
+                        .into_iter()
+                        .zip(pools.iter())
+                        .map(|(fsname, p)| vec![fsname, p.name.clone(), p.osts.len().to_string()]),
+                )
+            })?;
         }
         OstPoolCommand::Show { fsname, poolname } => {
             let pool = pool_lookup(&fsname, &poolname).await?;
 
             let osts: Vec<Ost> =
-                try_join_all(pool.osts.into_iter().map(|o| {
-                    async move { wrap_fut("Fetching OST...", get(&o, Ost::query())).await }
+                try_join_all(pool.osts.iter().map(|o| {
+                    async move { wrap_fut("Fetching OST...", get(o, Ost::query())).await }
                 }))
                 .await?;
 
-            let mut table = Table::new();
-            table.add_row(Row::from(&["Filesystem".to_string(), fsname]));
-            table.add_row(Row::from(&["Name".to_string(), poolname]));
-            let ostnames: Vec<String> = osts.into_iter().map(|m| m.name).collect();
-            table.add_row(Row::from(&["OSTs".to_string(), ostnames.join("\n")]));
-            table.printstd();
+            display_output(&pool, display_type, || {
+                let mut table = Table::new();
+                table.add_row(Row::from(&["Filesystem".to_string(), fsname]));
+                table.add_row(Row::from(&["Name".to_string(), poolname]));
+                let ostnames: Vec<String> = osts.into_iter().map(|m| m.name).collect();
+                table.add_row(Row::from(&["OSTs".to_string(), ostnames.join("\n")]));
+
+                table
+            })?;
         }
         OstPoolCommand::Create {
             fsname,
@@ -173,7 +218,44 @@ pub async fn ostpool_cli(command: OstPoolCommand) -> Result<(), ImlManagerCliErr
             let objs: ObjCommands = resp.json().await?;
             wait_for_cmds(objs.commands).await?;
         }
-        _ => println!("NYI"),
+        OstPoolCommand::Grow {
+            fsname,
+            poolname,
+            osts,
+        } => {
+            let pool = pool_lookup(&fsname, &poolname).await?;
+
+            let mut xs = wrap_fut("Fetching OSTs...", pool_ost_names(&pool)).await?;
+            xs.extend(osts);
+
+            term.write_line(&format!("{} ost pool...", style("Growing").green()))?;
+            match update_pool(&pool, xs).await? {
+                Some(cmd) => {
+                    wait_for_cmd(cmd).await?;
+                }
+                None => display_cancelled("Nothing to do"),
+            }
+        }
+        OstPoolCommand::Shrink {
+            fsname,
+            poolname,
+            osts,
+        } => {
+            let pool = pool_lookup(&fsname, &poolname).await?;
+
+            let mut xs = wrap_fut("Fetching OSTs...", pool_ost_names(&pool)).await?;
+            for o in &osts {
+                xs.remove(o);
+            }
+
+            term.write_line(&format!("{} ost pool...", style("Shrinking").green()))?;
+            match update_pool(&pool, xs).await? {
+                Some(cmd) => {
+                    wait_for_cmd(cmd).await?;
+                }
+                None => display_cancelled("Nothing to do"),
+            }
+        }
     };
     Ok(())
 }
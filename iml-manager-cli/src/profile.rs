@@ -0,0 +1,272 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! Profiles name the managers the CLI can talk to, so it can be run from anywhere.
+//!
+//! They are read from a YAML file (see `config_path`):
+//!
+//! ```yaml
+//! default: staging
+//! profiles:
+//!   staging:
+//!     url: https://staging-manager.example.com
+//!     api_user: admin
+//!     api_key: 0123456789abcdef
+//!     ca_cert: /etc/pki/iml/staging-ca.pem
+//!   production:
+//!     url: https://manager.example.com
+//!     api_user: admin
+//!   lab:
+//!     url: https://lab-manager.example.com
+//!     insecure: true
+//! ```
+//!
+//! Managers are verified against `ca_cert` if it is set, and the system roots otherwise.
+//! `insecure: true` accepts any certificate, for managers that are self-signed.
+//!
+//! A profile is loaded into the environment for `iml_manager_client` to pick up,
+//! but never replaces what is already there. So `SERVER_HTTP_URL`, `API_USER`,
+//! `API_KEY`, `IML_CA_CERT` and `IML_INSECURE` override any profile setting.
+//!
+//! When no profile is in use, the settings the manager writes for its own services are used,
+//! which is how the CLI works on the manager itself.
+//! These accept the manager's self-signed certificate.
+
+use crate::error::ImlManagerCliError;
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Settings the manager writes out for its own services.
+const MANAGER_SETTINGS: &str = "/var/lib/chroma/iml-settings.conf";
+
+/// Settings needed to make requests to a manager.
+const REQUIRED_VARS: [&str; 3] = ["SERVER_HTTP_URL", "API_USER", "API_KEY"];
+
+#[derive(Debug, Default, PartialEq, serde::Deserialize)]
+pub struct Profile {
+    /// The manager to connect to, e.g. `https://manager.example.com`
+    pub url: Option<String>,
+    pub api_user: Option<String>,
+    pub api_key: Option<String>,
+    /// A PEM certificate to verify the manager with
+    pub ca_cert: Option<PathBuf>,
+    /// Accept any certificate the manager presents
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+impl Profile {
+    /// The environment variables this profile sets.
+    fn vars(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("SERVER_HTTP_URL", self.url.clone()),
+            ("API_USER", self.api_user.clone()),
+            ("API_KEY", self.api_key.clone()),
+            (
+                "IML_CA_CERT",
+                self.ca_cert
+                    .as_ref()
+                    .map(|x| x.to_string_lossy().to_string()),
+            ),
+            (
+                "IML_INSECURE",
+                Some("true".to_string()).filter(|_| self.insecure),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(k, v)| v.map(|v| (k, v)))
+        .collect()
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct Config {
+    /// The profile to use when none is given
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// The profile called `name`, or the default profile if there is no `name`.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<&Profile>, ImlManagerCliError> {
+        let name = match name.or(self.default.as_deref()) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        self.profiles.get(name).map(Some).ok_or_else(|| {
+            ImlManagerCliError::ConfigError(format!("Profile {} does not exist", name))
+        })
+    }
+}
+
+/// Where profiles are read from.
+///
+/// This is `IML_CONFIG` if it is set,
+/// and `iml/config.yml` under `XDG_CONFIG_HOME` or `~/.config` otherwise.
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(x) = env::var_os("IML_CONFIG") {
+        return Some(x.into());
+    }
+
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|x| Path::new(&x).join(".config")))
+        .map(|x| x.join("iml").join("config.yml"))
+}
+
+/// Reads the config at `path`, if there is one.
+pub fn read_config(path: &Path) -> Result<Option<Config>, ImlManagerCliError> {
+    let err = |e: &dyn std::fmt::Display| {
+        ImlManagerCliError::ConfigError(format!("Could not read {}: {}", path.display(), e))
+    };
+
+    match fs::read_to_string(path) {
+        Ok(x) => serde_yaml::from_str(&x).map(Some).map_err(|e| err(&e)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(err(&e)),
+    }
+}
+
+/// Sets each of `vars` that is not already in the environment.
+fn set_missing_vars(vars: Vec<(&str, String)>) {
+    for (k, v) in vars {
+        if env::var_os(k).is_none() {
+            env::set_var(k, v);
+        }
+    }
+}
+
+/// Loads the settings for `profile` into the environment.
+///
+/// If there is no `profile`, `IML_PROFILE` is used, then the default profile.
+/// Errors if the settings needed to reach a manager are still missing afterwards.
+pub fn load_env(profile: Option<String>) -> Result<(), ImlManagerCliError> {
+    let name = profile
+        .or_else(|| env::var("IML_PROFILE").ok())
+        .filter(|x| !x.is_empty());
+
+    let path = config_path();
+
+    let config = match &path {
+        Some(x) => read_config(x)?,
+        None => None,
+    };
+
+    let profile = match (&config, &name) {
+        (Some(config), name) => config.profile(name.as_deref())?,
+        (None, Some(name)) => {
+            return Err(ImlManagerCliError::ConfigError(format!(
+                "Profile {} does not exist, as no profiles are configured",
+                name
+            )))
+        }
+        (None, None) => None,
+    };
+
+    match profile {
+        Some(x) => set_missing_vars(x.vars()),
+        None if Path::new(MANAGER_SETTINGS).exists() => {
+            dotenv::from_path(MANAGER_SETTINGS).map_err(|e| {
+                ImlManagerCliError::ConfigError(format!(
+                    "Could not load {}: {}",
+                    MANAGER_SETTINGS, e
+                ))
+            })?;
+        }
+        None => {}
+    };
+
+    let missing: Vec<_> = REQUIRED_VARS
+        .iter()
+        .filter(|x| env::var_os(x).is_none())
+        .cloned()
+        .collect();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let path = path
+        .map(|x| x.display().to_string())
+        .unwrap_or_else(|| "$IML_CONFIG".into());
+
+    Err(ImlManagerCliError::ConfigError(format!(
+        "No manager is configured. Add a profile to {}, or set {}",
+        path,
+        missing.join(", ")
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Profile};
+
+    fn config() -> Config {
+        serde_yaml::from_str(
+            r#"
+default: staging
+profiles:
+  staging:
+    url: https://staging-manager.example.com
+    api_user: admin
+    api_key: abc
+    ca_cert: /etc/pki/iml/staging-ca.pem
+  production:
+    url: https://manager.example.com
+  lab:
+    url: https://lab-manager.example.com
+    insecure: true
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_profile() {
+        let config = config();
+
+        let staging = config.profile(None).unwrap().unwrap();
+
+        assert_eq!(
+            staging.vars(),
+            vec![
+                (
+                    "SERVER_HTTP_URL",
+                    "https://staging-manager.example.com".into()
+                ),
+                ("API_USER", "admin".into()),
+                ("API_KEY", "abc".into()),
+                ("IML_CA_CERT", "/etc/pki/iml/staging-ca.pem".into()),
+            ]
+        );
+
+        assert_eq!(
+            config.profile(Some("production")).unwrap(),
+            Some(&Profile {
+                url: Some("https://manager.example.com".into()),
+                ..Default::default()
+            })
+        );
+
+        assert_eq!(
+            config.profile(Some("lab")).unwrap().unwrap().vars(),
+            vec![
+                ("SERVER_HTTP_URL", "https://lab-manager.example.com".into()),
+                ("IML_INSECURE", "true".into()),
+            ]
+        );
+
+        assert_eq!(
+            config.profile(Some("dev")).unwrap_err().to_string(),
+            "Profile dev does not exist"
+        );
+
+        assert_eq!(Config::default().profile(None).unwrap(), None);
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! Follows warp-drive while the CLI waits on something,
+//! so waits wake as soon as locks or records change
+//! and can show which jobs are holding things up.
+//!
+//! Not everything a wait is interested in is streamed (i.e. `Command`s),
+//! so waits still check in every `POLL_INTERVAL` while the stream is quiet.
+
+use crate::{error::ImlManagerCliError, messaging::messages};
+use futures::{Stream, StreamExt};
+use iml_wire_types::{
+    warp_drive::{Locks, Message},
+    CompositeId, LockAction, LockChange, ToCompositeId,
+};
+use std::{
+    collections::{BTreeSet, HashSet},
+    pin::Pin,
+    time::{Duration, Instant},
+};
+use tokio::time::{delay_for, timeout};
+
+/// How long to go without checking in, with or without a stream.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to keep reading once a change arrives,
+/// so a burst of changes only wakes a wait once.
+const SETTLE_INTERVAL: Duration = Duration::from_millis(250);
+
+type Messages = Pin<Box<dyn Stream<Item = Result<Message, ImlManagerCliError>>>>;
+
+pub struct Watch {
+    stream: Option<Messages>,
+    pub locks: Locks,
+}
+
+/// Applies a `LockChange` to `locks`, as warp-drive does.
+fn update_locks(locks: &mut Locks, x: LockChange) {
+    let id = x.composite_id().to_string();
+
+    match x.action {
+        LockAction::Add => {
+            locks.entry(id).or_default().insert(x);
+        }
+        LockAction::Remove => {
+            if let Some(xs) = locks.get_mut(&id) {
+                xs.retain(|y| y.uuid != x.uuid);
+
+                if xs.is_empty() {
+                    locks.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+impl Watch {
+    /// Subscribes to warp-drive, narrowed down by `query`.
+    ///
+    /// Nothing is sent until the first call to `changed`.
+    pub fn new(query: Vec<(String, String)>) -> Self {
+        Watch {
+            stream: Some(Box::pin(messages(query))),
+            locks: Locks::new(),
+        }
+    }
+    /// A `Watch` that only ever polls.
+    pub fn polling() -> Self {
+        Watch {
+            stream: None,
+            locks: Locks::new(),
+        }
+    }
+    /// Is this `Watch` following the stream?
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
+    fn apply(&mut self, msg: Message) {
+        match msg {
+            Message::Locks(xs) => self.locks = xs,
+            Message::LockChange(x) => update_locks(&mut self.locks, x),
+            Message::Records(_) | Message::RecordChange(_) | Message::RecordChanges(_) => {}
+        }
+    }
+    /// Waits until something has changed, or it's time to check in anyway.
+    pub async fn changed(&mut self) {
+        let mut settle_by: Option<Instant> = None;
+
+        loop {
+            let stream = match self.stream.as_mut() {
+                Some(x) => x,
+                None if settle_by.is_some() => return,
+                None => {
+                    delay_for(POLL_INTERVAL).await;
+
+                    return;
+                }
+            };
+
+            let wait = settle_by
+                .map(|x| x.saturating_duration_since(Instant::now()))
+                .unwrap_or(POLL_INTERVAL);
+
+            match timeout(wait, stream.next()).await {
+                Ok(Some(Ok(msg))) => {
+                    self.apply(msg);
+
+                    settle_by.get_or_insert_with(|| Instant::now() + SETTLE_INTERVAL);
+                }
+                Ok(Some(Err(e))) => {
+                    tracing::info!("Could not follow warp-drive, polling instead: {}", e);
+
+                    self.stream = None;
+                }
+                Ok(None) => self.stream = None,
+                Err(_) => return,
+            }
+        }
+    }
+    /// Descriptions of the locks held by any of `job_ids`.
+    pub fn held_by(&self, job_ids: &HashSet<u64>) -> BTreeSet<&str> {
+        self.locks
+            .values()
+            .flatten()
+            .filter(|x| job_ids.contains(&x.job_id))
+            .map(|x| x.description.as_str())
+            .collect()
+    }
+    /// Descriptions of the locks held on `id`.
+    pub fn held_on(&self, id: &CompositeId) -> BTreeSet<&str> {
+        self.locks
+            .get(&id.to_string())
+            .into_iter()
+            .flatten()
+            .map(|x| x.description.as_str())
+            .collect()
+    }
+}
+
+/// Formats lock descriptions as a single progress line.
+pub fn format_locks(prefix: &str, xs: BTreeSet<&str>) -> String {
+    if xs.is_empty() {
+        return String::new();
+    }
+
+    format!(
+        "↳ {} {}",
+        prefix,
+        xs.into_iter().collect::<Vec<_>>().join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_locks, Watch, POLL_INTERVAL};
+    use futures::stream;
+    use iml_wire_types::{warp_drive::Message, CompositeId, LockAction, LockChange, LockType};
+    use std::collections::HashSet;
+    use tokio::time::timeout;
+
+    fn lock(uuid: &str, job_id: u64, action: LockAction) -> Message {
+        Message::LockChange(LockChange {
+            uuid: uuid.into(),
+            job_id,
+            content_type_id: 5,
+            item_id: 1,
+            description: format!("Job {}", job_id),
+            lock_type: LockType::Write,
+            action,
+        })
+    }
+
+    #[test]
+    fn test_lock_changes() {
+        let mut watch = Watch::polling();
+
+        watch.apply(lock("a", 1, LockAction::Add));
+        watch.apply(lock("b", 2, LockAction::Add));
+
+        let jobs: HashSet<u64> = vec![2, 3].into_iter().collect();
+
+        assert_eq!(
+            format_locks("running", watch.held_by(&jobs)),
+            "↳ running Job 2"
+        );
+        assert_eq!(watch.held_on(&CompositeId(5, 1)).len(), 2);
+
+        watch.apply(lock("b", 2, LockAction::Remove));
+        watch.apply(lock("a", 1, LockAction::Remove));
+
+        assert!(watch.held_by(&jobs).is_empty());
+        assert!(watch.locks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_quiet_stream_checks_in() {
+        let mut watch = Watch {
+            stream: Some(Box::pin(stream::pending())),
+            locks: Default::default(),
+        };
+
+        assert!(timeout(POLL_INTERVAL * 2, watch.changed()).await.is_ok());
+        assert!(watch.is_streaming());
+    }
+}
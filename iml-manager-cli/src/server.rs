@@ -3,34 +3,75 @@
 // license that can be found in the LICENSE file.
 
 use crate::{
-    api_utils::{extract_api_id, get, get_all, get_hosts, post, wait_for_cmds, CmdWrapper},
+    api_utils::{
+        extract_api_id, get, get_all, get_available_actions, get_hosts, post, run_available_action,
+        wait_for_cmds, CmdWrapper,
+    },
     display_utils::{
-        display_cancelled, display_error, format_error, format_success, generate_table, wrap_fut,
+        add_progress, display_cancelled, display_output, format_error, format_success,
+        generate_table, multi_progress, wrap_fut, DisplayType,
     },
     error::ImlManagerCliError,
+    progress::{format_locks, Watch},
 };
 use console::{style, Term};
-use futures::future;
+use futures::{future, FutureExt};
 use iml_wire_types::{
-    ApiList, Command, EndpointName, Host, HostProfile, HostProfileWrapper, ProfileTest,
-    ServerProfile, TestHostJob,
+    ApiList, Command, EndpointName, Host, HostProfile, HostProfileWrapper, LnetConfiguration,
+    ProfileTest, ServerProfile, TestHostJob, ToCompositeId,
 };
 use std::{
     collections::{BTreeSet, HashMap},
     iter,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use structopt::StructOpt;
-use tokio::time::delay_for;
+use tokio::task::spawn_blocking;
 
 #[derive(StructOpt, Debug)]
 pub struct AddHosts {
     /// The host(s) to update. Takes a hostlist expression
     #[structopt(short = "h", long = "hosts")]
-    hosts: String,
+    pub hosts: String,
     /// The profile to deploy to each host
     #[structopt(short = "p", long = "profile")]
-    profile: String,
+    pub profile: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ServerHosts {
+    /// The host(s) to act on. Takes a hostlist expression
+    #[structopt(short = "h", long = "hosts")]
+    pub hosts: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum PowerCommand {
+    /// Power servers on
+    #[structopt(name = "on")]
+    On(ServerHosts),
+    /// Power servers off
+    #[structopt(name = "off")]
+    Off(ServerHosts),
+    /// Power servers off, then on again
+    #[structopt(name = "cycle")]
+    Cycle(ServerHosts),
+}
+
+#[derive(Debug, StructOpt)]
+pub enum LnetCommand {
+    /// Start LNet
+    #[structopt(name = "start")]
+    Start(ServerHosts),
+    /// Stop LNet
+    #[structopt(name = "stop")]
+    Stop(ServerHosts),
+    /// Load the LNet kernel modules
+    #[structopt(name = "load")]
+    Load(ServerHosts),
+    /// Unload the LNet kernel modules
+    #[structopt(name = "unload")]
+    Unload(ServerHosts),
 }
 
 #[derive(Debug, StructOpt)]
@@ -41,6 +82,30 @@ pub enum ServerCommand {
     /// Add new servers to IML
     #[structopt(name = "add")]
     Add(AddHosts),
+    /// Remove servers from IML, cleaning up their configuration
+    #[structopt(name = "remove")]
+    Remove(ServerHosts),
+    /// Remove servers from IML without contacting them
+    #[structopt(name = "force-remove")]
+    ForceRemove(ServerHosts),
+    /// Reboot servers
+    #[structopt(name = "reboot")]
+    Reboot(ServerHosts),
+    /// Shutdown servers
+    #[structopt(name = "shutdown")]
+    Shutdown(ServerHosts),
+    /// Control server power through their PDU outlets
+    #[structopt(name = "power")]
+    Power {
+        #[structopt(subcommand)]
+        command: PowerCommand,
+    },
+    /// Control LNet on servers
+    #[structopt(name = "lnet")]
+    Lnet {
+        #[structopt(subcommand)]
+        command: LnetCommand,
+    },
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -104,6 +169,11 @@ fn is_profile_valid(x: &HostProfile, profile_name: &str) -> bool {
     }
 }
 
+/// How long deployed agents have to pass their profile checks.
+const AGENT_START_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Waits for each host to pass the checks of `profile_name`,
+/// showing how many have passed and what holds locks on the host.
 async fn wait_till_agent_starts(
     hosts: &Vec<Host>,
     profile_name: &str,
@@ -114,94 +184,367 @@ async fn wait_till_agent_starts(
         .chain(iter::once(["limit".into(), "0".into()]))
         .collect();
 
-    let upper: u32 = 120;
+    let m = multi_progress();
 
-    for cnt in 1..=upper {
-        let ApiList { mut objects, .. }: ApiList<HostProfileWrapper> =
-            get(HostProfile::endpoint_name(), &host_ids).await?;
+    let bars: HashMap<u32, _> = hosts
+        .iter()
+        .map(|x| {
+            (
+                x.id,
+                add_progress(&m, "", &format!("{} starting", x.address)),
+            )
+        })
+        .collect();
 
-        tracing::debug!("Host Profiles {:?}", objects);
+    let fut = spawn_blocking(move || m.join())
+        .map(|x| x.map_err(|e| e.into()).and_then(std::convert::identity));
 
-        if let Some(x) = objects.iter_mut().find(|x| x.error.is_some()) {
-            return Err(ImlManagerCliError::ApiError(
-                x.error.take().unwrap().to_string(),
-            ));
+    let fut2 = async {
+        let mut watch = Watch::new(vec![
+            ("kinds".into(), "host".into()),
+            (
+                "host_ids".into(),
+                hosts
+                    .iter()
+                    .map(|x| x.id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+        ]);
+
+        let deadline = Instant::now() + AGENT_START_TIMEOUT;
+
+        loop {
+            let ApiList { mut objects, .. }: ApiList<HostProfileWrapper> =
+                get(HostProfile::endpoint_name(), &host_ids).await?;
+
+            tracing::debug!("Host Profiles {:?}", objects);
+
+            if let Some(x) = objects.iter_mut().find(|x| x.error.is_some()) {
+                return Err(ImlManagerCliError::ApiError(
+                    x.error.take().unwrap().to_string(),
+                ));
+            };
+
+            let profile_checks: HashMap<u32, Vec<ProfileTest>> = objects
+                .iter_mut()
+                .filter_map(|x| x.host_profiles.take())
+                .map(|mut x| {
+                    x.profiles.remove(profile_name).map(|y| (x.host, y)).ok_or(
+                        ImlManagerCliError::ApiError(format!(
+                            "Profile {} not found for host {} while booting",
+                            profile_name, x.host
+                        )),
+                    )
+                })
+                .collect::<Result<HashMap<u32, Vec<ProfileTest>>, ImlManagerCliError>>()?;
+
+            for (k, checks) in &profile_checks {
+                let host = hosts.iter().find(|x| &x.id == k).unwrap();
+
+                if let Some((pb, detail)) = bars.get(k) {
+                    pb.set_message(&format!(
+                        "{} {}/{} checks passed",
+                        host.address,
+                        checks.iter().filter(|y| y.pass).count(),
+                        checks.len()
+                    ));
+                    detail.set_message(&format_locks(
+                        "locked by",
+                        watch.held_on(&host.composite_id()),
+                    ));
+                }
+            }
+
+            let all_passed = profile_checks
+                .values()
+                .all(|checks| checks.iter().all(|y| y.pass));
+
+            if all_passed {
+                return Ok(());
+            } else if Instant::now() >= deadline {
+                let failed_checks = profile_checks
+                    .iter()
+                    .filter(|(_, xs)| xs.iter().any(|y| !y.pass))
+                    .fold(vec![], |mut acc, (k, v)| {
+                        let host = hosts.iter().find(|x| &x.id == k).unwrap();
+
+                        let failed = v
+                            .into_iter()
+                            .filter(|y| !y.pass)
+                            .map(|ProfileTest { description, .. }| description.to_string())
+                            .collect::<Vec<String>>()
+                            .join(",");
+
+                        acc.push(format!(
+                            "host {} has failed checks. Reasons: {}",
+                            host.address, failed
+                        ));
+
+                        acc
+                    })
+                    .join("\n");
+
+                return Err(ImlManagerCliError::ApiError(failed_checks));
+            }
+
+            watch.changed().await;
+        }
+    };
+
+    let r = fut2.await;
+
+    for (pb, detail) in bars.values() {
+        detail.finish_and_clear();
+
+        if r.is_ok() {
+            pb.finish();
+        } else {
+            pb.abandon();
+        }
+    }
+
+    fut.await?;
+
+    r
+}
+
+/// What happened to a single host during a `run_host_action`.
+#[derive(Debug, serde::Serialize)]
+struct HostOutcome {
+    host: String,
+    result: &'static str,
+    message: String,
+    command: Option<u32>,
+}
+
+impl HostOutcome {
+    fn skipped(host: &str, message: String) -> Self {
+        HostOutcome {
+            host: host.to_string(),
+            result: "skipped",
+            message,
+            command: None,
+        }
+    }
+    fn failed(host: &str, message: String) -> Self {
+        HostOutcome {
+            host: host.to_string(),
+            result: "failed",
+            message,
+            command: None,
+        }
+    }
+    fn from_cmd(host: &str, cmd: &Command) -> Self {
+        let result = if cmd.errored {
+            "errored"
+        } else if cmd.cancelled {
+            "cancelled"
+        } else {
+            "successful"
         };
 
-        let profile_checks: HashMap<u32, Vec<ProfileTest>> = objects
-            .iter_mut()
-            .filter_map(|x| x.host_profiles.take())
-            .map(|mut x| {
-                x.profiles.remove(profile_name).map(|y| (x.host, y)).ok_or(
-                    ImlManagerCliError::ApiError(format!(
-                        "Profile {} not found for host {} while booting",
-                        profile_name, x.host
-                    )),
-                )
-            })
-            .collect::<Result<HashMap<u32, Vec<ProfileTest>>, ImlManagerCliError>>()?;
+        HostOutcome {
+            host: host.to_string(),
+            result,
+            message: cmd.message.clone(),
+            command: Some(cmd.id),
+        }
+    }
+    fn is_failure(&self) -> bool {
+        match self.result {
+            "skipped" | "successful" => false,
+            _ => true,
+        }
+    }
+}
 
-        let all_passed = profile_checks
-            .values()
-            .all(|checks| checks.iter().all(|y| y.pass));
+/// The object an action runs against, either a host or its LNet configuration.
+struct ActionTarget {
+    host: String,
+    id: u32,
+    content_type_id: u32,
+    resource_uri: String,
+    state: String,
+}
+
+/// Runs the available action with `verb` on each host in `hostlist`,
+/// or on their LNet configuration when `lnet` is set,
+/// then prints what happened to each host in one table.
+///
+/// Hosts the action is not available for (i.e. starting LNet where it is already up) are skipped.
+async fn run_host_action(
+    verb: &str,
+    hostlist: &str,
+    lnet: bool,
+    display_type: DisplayType,
+) -> Result<(), ImlManagerCliError> {
+    let hostnames = hostlist_parser::parse(hostlist)?;
+
+    tracing::debug!("Parsed hosts {:?}", hostnames);
+
+    let api_hosts: ApiList<Host> = wrap_fut("Fetching hosts...", get_hosts()).await?;
+
+    let lnet_configs: Vec<LnetConfiguration> = if lnet {
+        let xs: ApiList<LnetConfiguration> =
+            wrap_fut("Fetching LNet configurations...", get_all()).await?;
+
+        xs.objects
+    } else {
+        vec![]
+    };
+
+    let mut outcomes = vec![];
+    let mut targets = vec![];
+
+    for name in &hostnames {
+        let host = match api_hosts
+            .objects
+            .iter()
+            .find(|x| &x.fqdn == name || &x.nodename == name)
+        {
+            Some(x) => x,
+            None => {
+                outcomes.push(HostOutcome::failed(name, "Unknown host".into()));
+
+                continue;
+            }
+        };
+
+        if !lnet {
+            targets.push(ActionTarget {
+                host: name.clone(),
+                id: host.id,
+                content_type_id: host.content_type_id,
+                resource_uri: host.resource_uri.clone(),
+                state: host.state.clone(),
+            });
+
+            continue;
+        }
+
+        match lnet_configs
+            .iter()
+            .find(|x| x.resource_uri == host.lnet_configuration)
+        {
+            Some(x) => targets.push(ActionTarget {
+                host: name.clone(),
+                id: x.id,
+                content_type_id: x.content_type_id,
+                resource_uri: x.resource_uri.clone(),
+                state: x.state.clone(),
+            }),
+            None => outcomes.push(HostOutcome::failed(name, "No LNet configuration".into())),
+        }
+    }
 
-        if all_passed {
-            return Ok(());
-        } else if cnt == 120 {
-            let failed_checks = profile_checks
+    let actions = wrap_fut(
+        "Fetching available actions...",
+        future::try_join_all(
+            targets
                 .iter()
-                .filter(|(_, xs)| xs.iter().any(|y| !y.pass))
-                .fold(vec![], |mut acc, (k, v)| {
-                    let host = hosts.iter().find(|x| &x.id == k).unwrap();
-
-                    let failed = v
-                        .into_iter()
-                        .filter(|y| !y.pass)
-                        .map(|ProfileTest { description, .. }| description.to_string())
-                        .collect::<Vec<String>>()
-                        .join(",");
-
-                    acc.push(format!(
-                        "host {} has failed checks. Reasons: {}",
-                        host.address, failed
-                    ));
+                .map(|x| get_available_actions(x.id, x.content_type_id)),
+        ),
+    )
+    .await?;
+
+    let mut xs = vec![];
+
+    for (t, actions) in targets.iter().zip(actions) {
+        match actions
+            .objects
+            .into_iter()
+            .find(|x| x.verb.eq_ignore_ascii_case(verb))
+        {
+            Some(action) => xs.push((t, action)),
+            None => outcomes.push(HostOutcome::skipped(
+                &t.host,
+                format!("{} is not available ({})", verb, t.state),
+            )),
+        }
+    }
 
-                    acc
-                })
-                .join("\n");
+    let cmds = future::try_join_all(
+        xs.iter()
+            .map(|(t, action)| run_available_action(action, &t.resource_uri)),
+    )
+    .await?;
 
-            return Err(ImlManagerCliError::ApiError(failed_checks));
+    let mut running = vec![];
+
+    for ((t, _), cmd) in xs.iter().zip(cmds) {
+        match cmd {
+            Some(cmd) => running.push((t.host.clone(), cmd)),
+            None => outcomes.push(HostOutcome::skipped(&t.host, "Nothing to do".into())),
         }
+    }
+
+    let (hosts, cmds): (Vec<_>, Vec<_>) = running.into_iter().unzip();
+
+    let cmds = if cmds.is_empty() {
+        cmds
+    } else {
+        wait_for_cmds(cmds).await?
+    };
+
+    outcomes.extend(
+        hosts
+            .iter()
+            .zip(cmds.iter())
+            .map(|(host, cmd)| HostOutcome::from_cmd(host, cmd)),
+    );
+
+    outcomes.sort_by(|a, b| a.host.cmp(&b.host));
 
-        delay_for(Duration::from_millis(500)).await;
+    display_output(&outcomes, display_type, || {
+        generate_table(
+            &["Host", "Result", "Message"],
+            outcomes
+                .iter()
+                .map(|x| vec![x.host.clone(), x.result.to_string(), x.message.clone()]),
+        )
+    })?;
+
+    let failed = outcomes.iter().filter(|x| x.is_failure()).count();
+
+    if failed > 0 {
+        return Err(ImlManagerCliError::ApiError(format!(
+            "{} failed on {} of {} host(s)",
+            verb,
+            failed,
+            outcomes.len()
+        )));
     }
 
     Ok(())
 }
 
-pub async fn server_cli(command: ServerCommand) -> Result<(), ImlManagerCliError> {
+pub async fn server_cli(
+    command: ServerCommand,
+    display_type: DisplayType,
+) -> Result<(), ImlManagerCliError> {
     match command {
         ServerCommand::List => {
             let hosts: ApiList<Host> = wrap_fut("Fetching hosts...", get_hosts()).await?;
 
             tracing::debug!("Hosts: {:?}", hosts);
 
-            let table = generate_table(
-                &["Id", "FQDN", "State", "Nids"],
-                hosts.objects.into_iter().map(|h| {
-                    vec![
-                        h.id.to_string(),
-                        h.fqdn,
-                        h.state,
-                        h.nids.unwrap_or_else(|| vec![]).join(" "),
-                    ]
-                }),
-            );
-
-            table.printstd();
+            display_output(&hosts.objects, display_type, || {
+                generate_table(
+                    &["Id", "FQDN", "State", "Nids"],
+                    hosts.objects.iter().map(|h| {
+                        vec![
+                            h.id.to_string(),
+                            h.fqdn.clone(),
+                            h.state.clone(),
+                            h.nids.as_ref().map(|x| x.join(" ")).unwrap_or_default(),
+                        ]
+                    }),
+                )
+            })?;
         }
         ServerCommand::Add(config) => {
-            let term = Term::stdout();
+            let term = Term::stderr();
 
             let new_hosts = hostlist_parser::parse(&config.hosts)?;
 
@@ -216,14 +559,8 @@ pub async fn server_cli(command: ServerCommand) -> Result<(), ImlManagerCliError
                 .filter(|x| x.user_selectable)
                 .find(|x| x.name == config.profile);
 
-            let profile = match profile_opt {
-                Some(p) => p,
-                None => {
-                    display_error(format!("Unknown profile {}", config.profile));
-
-                    return Ok(());
-                }
-            };
+            let profile = profile_opt
+                .ok_or_else(|| ImlManagerCliError::DoesNotExist(ServerProfile::endpoint_name()))?;
 
             let known_hosts = filter_known_hosts(&new_hosts, &api_hosts.objects);
 
@@ -269,40 +606,37 @@ pub async fn server_cli(command: ServerCommand) -> Result<(), ImlManagerCliError
 
             tracing::debug!("jobs {:?}", jobs);
 
-            let all_passed = jobs
+            let failed: Vec<_> = jobs
                 .objects
                 .into_iter()
                 .flat_map(|x| x.step_results.into_iter().map(|(_, b)| b))
-                .fold(true, |state, check| {
-                    if !check.valid {
-                        display_error(format!(
-                            "Host {} has failed pre-flight checks.",
-                            check.address,
-                        ));
+                .filter(|check| !check.valid)
+                .map(|check| {
+                    let table = generate_table(
+                        &["Check", "Passed"],
+                        check.status.iter().map(|x| {
+                            let v = if x.value {
+                                format_success("Pass")
+                            } else {
+                                format_error("Fail")
+                            };
+
+                            vec![x.name.clone(), v]
+                        }),
+                    );
+
+                    format!(
+                        "Host {} has failed pre-flight checks.\n{}",
+                        check.address, table
+                    )
+                })
+                .collect();
 
-                        generate_table(
-                            &["Check", "Passed"],
-                            check.status.iter().map(|x| {
-                                let v = if x.value {
-                                    format_success("Pass")
-                                } else {
-                                    format_error("Fail")
-                                };
-
-                                vec![x.name.clone(), v]
-                            }),
-                        );
-
-                        false
-                    } else {
-                        state && true
-                    }
-                });
-
-            if !all_passed {
-                return Err(ImlManagerCliError::ApiError(
-                    "Preflight checks failed".into(),
-                ));
+            if !failed.is_empty() {
+                return Err(ImlManagerCliError::ApiError(format!(
+                    "Preflight checks failed\n{}",
+                    failed.join("\n")
+                )));
             }
 
             let objects = new_hosts
@@ -373,7 +707,72 @@ pub async fn server_cli(command: ServerCommand) -> Result<(), ImlManagerCliError
 
             wait_for_cmds(cmds).await?;
         }
+        ServerCommand::Remove(x) => {
+            run_host_action("Remove", &x.hosts, false, display_type).await?
+        }
+        ServerCommand::ForceRemove(x) => {
+            run_host_action("Force Remove", &x.hosts, false, display_type).await?
+        }
+        ServerCommand::Reboot(x) => {
+            run_host_action("Reboot", &x.hosts, false, display_type).await?
+        }
+        ServerCommand::Shutdown(x) => {
+            run_host_action("Shutdown", &x.hosts, false, display_type).await?
+        }
+        ServerCommand::Power { command } => {
+            let (verb, x) = match command {
+                PowerCommand::On(x) => ("Power On", x),
+                PowerCommand::Off(x) => ("Power Off", x),
+                PowerCommand::Cycle(x) => ("Power Cycle", x),
+            };
+
+            run_host_action(verb, &x.hosts, false, display_type).await?
+        }
+        ServerCommand::Lnet { command } => {
+            let (verb, x) = match command {
+                LnetCommand::Start(x) => ("Start LNet", x),
+                LnetCommand::Stop(x) => ("Stop LNet", x),
+                LnetCommand::Load(x) => ("Load LNet", x),
+                LnetCommand::Unload(x) => ("Unload LNet", x),
+            };
+
+            run_host_action(verb, &x.hosts, true, display_type).await?
+        }
     };
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::HostOutcome;
+    use iml_wire_types::Command;
+
+    #[test]
+    fn test_host_outcome() {
+        let mut cmd = Command {
+            cancelled: false,
+            complete: true,
+            created_at: "2019-11-01T00:00:00".into(),
+            errored: false,
+            id: 7,
+            jobs: vec![],
+            logs: "".into(),
+            message: "Rebooting host oss1.local".into(),
+            resource_uri: "/api/command/7/".into(),
+        };
+
+        assert!(!HostOutcome::from_cmd("oss1.local", &cmd).is_failure());
+
+        cmd.errored = true;
+
+        let x = HostOutcome::from_cmd("oss1.local", &cmd);
+
+        assert_eq!(x.result, "errored");
+        assert_eq!(x.command, Some(7));
+        assert!(x.is_failure());
+
+        assert!(!HostOutcome::skipped("oss1.local", "Nothing to do".into()).is_failure());
+        assert!(HostOutcome::failed("oss2.local", "Unknown host".into()).is_failure());
+    }
+}
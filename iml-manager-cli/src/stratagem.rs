@@ -3,8 +3,10 @@
 // license that can be found in the LICENSE file.
 
 use crate::{
-    api_utils::{delete, first, get, post, put, wait_for_cmd, CmdWrapper},
-    display_utils::{display_cmd_state, generate_table, start_spinner},
+    api_utils::{delete, first, get, get_influx, post, put, wait_for_cmd, CmdWrapper},
+    display_utils::{
+        display_cmd_state, display_output, generate_table, start_spinner, wrap_fut, DisplayType,
+    },
     error::{
         DurationParseError, ImlManagerCliError, RunStratagemCommandResult,
         RunStratagemValidationError,
@@ -12,8 +14,31 @@ use crate::{
 };
 use iml_manager_client::ImlManagerClientError;
 use iml_wire_types::{ApiList, EndpointName, Filesystem, StratagemConfiguration};
+use number_formatter::format_bytes;
+use prettytable::Table;
+use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 
+/// The InfluxDB database scan results are stored in.
+const SCANS_DB: &str = "iml_stratagem_scans";
+
+/// The columns every scan query selects, after `time`.
+const SCAN_COLUMNS: &str = r#"group_name, counter_name, "count", size"#;
+
+/// How many of the top inode users to show.
+const MAX_INODE_USERS: usize = 20;
+
+/// How many columns the longest histogram bar takes up.
+const HISTOGRAM_WIDTH: u64 = 50;
+
+/// Size distribution counter names, and how they are shown, in order.
+const SIZE_BUCKETS: [(&str, &str); 4] = [
+    ("less_than_1m", "< 1 MiB"),
+    ("greater_than_equal_1m_less_than_1g", ">= 1 MiB, < 1 GiB"),
+    ("greater_than_equal_1g", ">= 1 GiB"),
+    ("greater_than_equal_1t", ">= 1 TiB"),
+];
+
 #[derive(Debug, StructOpt)]
 pub enum StratagemCommand {
     /// Kickoff a Stratagem scan
@@ -22,6 +47,9 @@ pub enum StratagemCommand {
     /// Configure Stratagem scanning interval
     #[structopt(name = "interval")]
     StratagemInterval(StratagemInterval),
+    /// Show the results of the latest Stratagem scan
+    #[structopt(name = "report")]
+    Report(StratagemReportData),
 }
 
 #[derive(Debug, StructOpt)]
@@ -44,23 +72,23 @@ pub enum StratagemInterval {
 pub struct StratagemIntervalConfig {
     /// Filesystem to configure
     #[structopt(short = "f", long = "filesystem")]
-    filesystem: String,
+    pub filesystem: String,
     /// Interval to scan
     #[structopt(short = "i", long = "interval", parse(try_from_str = "parse_duration"))]
-    interval: u64,
+    pub interval: u64,
     /// The report duration
     #[structopt(short = "r", long = "report", parse(try_from_str = "parse_duration"))]
-    report_duration: Option<u64>,
+    pub report_duration: Option<u64>,
     /// The purge duration
     #[structopt(short = "p", long = "purge", parse(try_from_str = "parse_duration"))]
-    purge_duration: Option<u64>,
+    pub purge_duration: Option<u64>,
 }
 
 #[derive(Debug, StructOpt, serde::Serialize)]
 pub struct StratagemRemoveData {
     /// Filesystem to unconfigure
     #[structopt(short = "f", long = "filesystem")]
-    name: String,
+    pub name: String,
 }
 
 #[derive(serde::Serialize, StructOpt, Debug)]
@@ -76,7 +104,17 @@ pub struct StratagemScanData {
     purge_duration: Option<u64>,
 }
 
-fn parse_duration(src: &str) -> Result<u64, ImlManagerCliError> {
+#[derive(Debug, StructOpt)]
+pub struct StratagemReportData {
+    /// The name of the filesystem to report on
+    #[structopt(name = "FSNAME")]
+    filesystem: String,
+    /// Compare with the latest scan from at least this long ago, up to 90 days
+    #[structopt(long = "since", parse(try_from_str = "parse_duration"))]
+    since: Option<u64>,
+}
+
+pub fn parse_duration(src: &str) -> Result<u64, ImlManagerCliError> {
     if src.len() < 2 {
         return Err(DurationParseError::InvalidValue.into());
     }
@@ -147,7 +185,368 @@ async fn handle_cmd_resp(
     }
 }
 
-pub async fn stratagem_cli(command: StratagemCommand) -> Result<(), ImlManagerCliError> {
+#[derive(serde::Deserialize, Debug)]
+struct InfluxSeries<T> {
+    values: Vec<T>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct InfluxResult<T> {
+    series: Option<Vec<InfluxSeries<T>>>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct InfluxResults<T> {
+    results: Vec<InfluxResult<T>>,
+}
+
+impl<T> InfluxResults<T> {
+    fn values(self) -> Vec<T> {
+        self.results
+            .into_iter()
+            .flat_map(|x| x.series.unwrap_or_default())
+            .flat_map(|x| x.values)
+            .collect()
+    }
+}
+
+/// A `time`, followed by the `SCAN_COLUMNS` of a row.
+type ScanRow = (i64, String, String, f64, f64);
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ScanCounter {
+    pub name: String,
+    pub count: u64,
+    pub size: u64,
+}
+
+/// The stored results of a Stratagem scan.
+#[derive(Debug, Default, PartialEq, serde::Serialize)]
+pub struct ScanResults {
+    /// When the results were stored, in nanoseconds since the epoch
+    pub time: i64,
+    pub size_distribution: Vec<ScanCounter>,
+    /// Sorted by inode count, highest first
+    pub top_inode_users: Vec<ScanCounter>,
+    pub fids_expiring_soon: Option<ScanCounter>,
+    pub fids_expired: Option<ScanCounter>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct StratagemReport {
+    pub filesystem: String,
+    pub latest: ScanResults,
+    pub since: Option<ScanResults>,
+}
+
+fn scan_results(rows: Vec<ScanRow>) -> Option<ScanResults> {
+    let mut x = ScanResults {
+        time: rows.iter().map(|r| r.0).max()?,
+        ..Default::default()
+    };
+
+    for (_, group, name, count, size) in rows {
+        let mut counter = ScanCounter {
+            name,
+            count: count as u64,
+            size: size as u64,
+        };
+
+        match group.as_str() {
+            "size_distribution" => {
+                if let Some((_, label)) = SIZE_BUCKETS.iter().find(|(n, _)| *n == counter.name) {
+                    counter.name = (*label).to_string();
+                }
+
+                x.size_distribution.push(counter);
+            }
+            "user_distribution" => x.top_inode_users.push(counter),
+            "warn_fids" => x.fids_expiring_soon = Some(counter),
+            "purge_fids" => x.fids_expired = Some(counter),
+            _ => {}
+        }
+    }
+
+    x.size_distribution
+        .sort_by_key(|c| SIZE_BUCKETS.iter().position(|(_, label)| *label == c.name));
+    x.top_inode_users
+        .sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+    Some(x)
+}
+
+/// Quotes `x` for use as a string literal in InfluxQL.
+fn influx_str(x: &str) -> String {
+    format!("'{}'", x.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+async fn get_latest_scan(fs_name: &str) -> Result<Option<ScanResults>, ImlManagerCliError> {
+    let q = format!(
+        "SELECT {} FROM stratagem_scan WHERE fs_name={}",
+        SCAN_COLUMNS,
+        influx_str(fs_name)
+    );
+
+    let x: InfluxResults<ScanRow> = get_influx(SCANS_DB, &q).await?;
+
+    Ok(scan_results(x.values()))
+}
+
+/// Gets the latest scan that is at least `ago` milliseconds old.
+async fn get_scan_since(
+    fs_name: &str,
+    ago: u64,
+) -> Result<Option<ScanResults>, ImlManagerCliError> {
+    let q = format!(
+        r#"SELECT last("count") FROM stratagem_scan_history WHERE fs_name={} AND time <= now() - {}ms"#,
+        influx_str(fs_name),
+        ago
+    );
+
+    let x: InfluxResults<(i64, f64)> = get_influx(SCANS_DB, &q).await?;
+
+    let time = match x.values().into_iter().next() {
+        Some((time, _)) => time,
+        None => return Ok(None),
+    };
+
+    let q = format!(
+        "SELECT {} FROM stratagem_scan_history WHERE fs_name={} AND time = {}",
+        SCAN_COLUMNS,
+        influx_str(fs_name),
+        time
+    );
+
+    let x: InfluxResults<ScanRow> = get_influx(SCANS_DB, &q).await?;
+
+    Ok(scan_results(x.values()))
+}
+
+async fn get_report(
+    filesystem: String,
+    since: Option<u64>,
+) -> Result<StratagemReport, ImlManagerCliError> {
+    let latest = get_latest_scan(&filesystem).await?.ok_or_else(|| {
+        ImlManagerCliError::ApiError(format!("No Stratagem scan results for {}", filesystem))
+    })?;
+
+    let since = match since {
+        Some(ago) => Some(get_scan_since(&filesystem, ago).await?.ok_or_else(|| {
+            ImlManagerCliError::ApiError(format!(
+                "No Stratagem scan results for {} from {} ago or earlier",
+                filesystem,
+                format_age(ago)
+            ))
+        })?),
+        None => None,
+    };
+
+    Ok(StratagemReport {
+        filesystem,
+        latest,
+        since,
+    })
+}
+
+/// Formats milliseconds in the largest whole unit `parse_duration` accepts.
+fn format_age(ms: u64) -> String {
+    match ms {
+        x if x >= 86_400_000 => format!("{}d", x / 86_400_000),
+        x if x >= 3_600_000 => format!("{}h", x / 3_600_000),
+        x if x >= 60_000 => format!("{}m", x / 60_000),
+        x => format!("{}s", x / 1_000),
+    }
+}
+
+/// How many milliseconds ago `time`, in nanoseconds since the epoch, was.
+fn ms_since(time: i64) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as i64)
+        .unwrap_or_default();
+
+    (now - time / 1_000_000).max(0) as u64
+}
+
+/// Draws a horizontal histogram of counts, scaled to `HISTOGRAM_WIDTH`.
+fn histogram(xs: &[ScanCounter]) -> String {
+    let max = xs.iter().map(|x| x.count).max().unwrap_or_default();
+    let width = xs
+        .iter()
+        .map(|x| x.name.chars().count())
+        .max()
+        .unwrap_or_default();
+
+    xs.iter()
+        .map(|x| {
+            let len = (x.count * HISTOGRAM_WIDTH)
+                .checked_div(max)
+                .unwrap_or_default();
+
+            format!(
+                "{:>width$} │{} {}",
+                x.name,
+                "█".repeat(len as usize),
+                x.count,
+                width = width
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The expiry counters, named as they are shown.
+fn expiry_counters(x: &ScanResults) -> Vec<ScanCounter> {
+    vec![
+        ("Expiring soon", &x.fids_expiring_soon),
+        ("Expired", &x.fids_expired),
+    ]
+    .into_iter()
+    .filter_map(|(name, c)| {
+        c.as_ref().map(|c| ScanCounter {
+            name: name.to_string(),
+            ..c.clone()
+        })
+    })
+    .collect()
+}
+
+fn format_change(after: u64, before: u64, fmt: impl Fn(f64) -> String) -> String {
+    if after > before {
+        format!("+{}", fmt((after - before) as f64))
+    } else if after < before {
+        format!("-{}", fmt((before - after) as f64))
+    } else {
+        "0".into()
+    }
+}
+
+fn counter_rows(
+    group: &str,
+    xs: &[ScanCounter],
+    before: Option<&[ScanCounter]>,
+) -> Vec<Vec<String>> {
+    xs.iter()
+        .map(|x| {
+            let mut row = vec![
+                group.to_string(),
+                x.name.clone(),
+                x.count.to_string(),
+                format_bytes(x.size as f64, None),
+            ];
+
+            if let Some(before) = before {
+                let (count, size) = before
+                    .iter()
+                    .find(|y| y.name == x.name)
+                    .map(|y| (y.count, y.size))
+                    .unwrap_or_default();
+
+                row.push(format_change(x.count, count, |x| x.to_string()));
+                row.push(format_change(x.size, size, |x| format_bytes(x, None)));
+            }
+
+            row
+        })
+        .collect()
+}
+
+/// The first `MAX_INODE_USERS` of `xs`, which are sorted by inode count.
+fn top_users(xs: &[ScanCounter]) -> &[ScanCounter] {
+    &xs[..xs.len().min(MAX_INODE_USERS)]
+}
+
+/// `xs`, followed by any counters in `before` that are no longer among them.
+///
+/// Those take their current values from `all`, or are zeroed if they are gone altogether,
+/// so they show up with a negative change.
+fn with_dropped(
+    xs: &[ScanCounter],
+    all: &[ScanCounter],
+    before: Option<&[ScanCounter]>,
+) -> Vec<ScanCounter> {
+    let dropped = before
+        .unwrap_or_default()
+        .iter()
+        .filter(|y| !xs.iter().any(|x| x.name == y.name))
+        .map(|y| {
+            all.iter()
+                .find(|x| x.name == y.name)
+                .cloned()
+                .unwrap_or_else(|| ScanCounter {
+                    name: y.name.clone(),
+                    count: 0,
+                    size: 0,
+                })
+        });
+
+    xs.iter().cloned().chain(dropped).collect()
+}
+
+fn report_table(x: &StratagemReport) -> Table {
+    let mut columns = vec!["Group", "Name", "Count", "Used"];
+
+    if x.since.is_some() {
+        columns.extend(&["Count Change", "Used Change"]);
+    }
+
+    let since = x.since.as_ref();
+    let sizes = &x.latest.size_distribution;
+    let sizes_before = since.map(|y| &y.size_distribution[..]);
+    let users = &x.latest.top_inode_users;
+    let users_before = since.map(|y| &y.top_inode_users[..]);
+    let expiry = expiry_counters(&x.latest);
+    let expiry_before = since.map(expiry_counters);
+
+    let rows = counter_rows(
+        "Size",
+        &with_dropped(sizes, sizes, sizes_before),
+        sizes_before,
+    )
+    .into_iter()
+    .chain(counter_rows(
+        "User",
+        // Users that were in the earlier top are shown even if they no longer are.
+        &with_dropped(top_users(users), users, users_before.map(top_users)),
+        users_before,
+    ))
+    .chain(counter_rows(
+        "Expiry",
+        &with_dropped(&expiry, &expiry, expiry_before.as_deref()),
+        expiry_before.as_deref(),
+    ));
+
+    generate_table(&columns, rows)
+}
+
+fn print_histograms(x: &StratagemReport) {
+    let mut title = format!(
+        "Stratagem scan of {}, {} ago",
+        x.filesystem,
+        format_age(ms_since(x.latest.time))
+    );
+
+    if let Some(since) = &x.since {
+        title.push_str(&format!(
+            ", compared with the scan {} ago",
+            format_age(ms_since(since.time))
+        ));
+    }
+
+    let users = &x.latest.top_inode_users;
+
+    println!("{}\n", title);
+    println!(
+        "Size distribution\n{}\n",
+        histogram(&x.latest.size_distribution)
+    );
+    println!("Top inode users\n{}\n", histogram(top_users(users)));
+}
+
+pub async fn stratagem_cli(
+    command: StratagemCommand,
+    display_type: DisplayType,
+) -> Result<(), ImlManagerCliError> {
     match command {
         StratagemCommand::Scan(data) => {
             let r = post("run_stratagem", data).await?;
@@ -176,26 +575,26 @@ pub async fn stratagem_cli(command: StratagemCommand) -> Result<(), ImlManagerCl
 
                 stop_spinner(None);
 
-                if r.objects.is_empty() {
+                if r.objects.is_empty() && display_type == DisplayType::Table {
                     println!("No Stratagem intervals found");
                     return Ok(());
                 }
 
-                let table = generate_table(
-                    &["Id", "Filesystem", "State", "Interval", "Purge", "Report"],
-                    r.objects.into_iter().map(|x| {
-                        vec![
-                            x.id.to_string(),
-                            x.filesystem,
-                            x.state,
-                            x.interval.to_string(),
-                            x.purge_duration.map(|x| x.to_string()).unwrap_or_default(),
-                            x.report_duration.map(|x| x.to_string()).unwrap_or_default(),
-                        ]
-                    }),
-                );
-
-                table.printstd();
+                display_output(&r.objects, display_type, || {
+                    generate_table(
+                        &["Id", "Filesystem", "State", "Interval", "Purge", "Report"],
+                        r.objects.iter().map(|x| {
+                            vec![
+                                x.id.to_string(),
+                                x.filesystem.clone(),
+                                x.state.clone(),
+                                x.interval.to_string(),
+                                x.purge_duration.map(|x| x.to_string()).unwrap_or_default(),
+                                x.report_duration.map(|x| x.to_string()).unwrap_or_default(),
+                            ]
+                        }),
+                    )
+                })?;
             }
             StratagemInterval::Add(c) => {
                 let r = post(StratagemConfiguration::endpoint_name(), c).await?;
@@ -249,6 +648,15 @@ pub async fn stratagem_cli(command: StratagemCommand) -> Result<(), ImlManagerCl
                 display_cmd_state(&command);
             }
         },
+        StratagemCommand::Report(StratagemReportData { filesystem, since }) => {
+            let x = wrap_fut("Fetching scan results...", get_report(filesystem, since)).await?;
+
+            if display_type == DisplayType::Table {
+                print_histograms(&x);
+            }
+
+            display_output(&x, display_type, || report_table(&x))?;
+        }
     };
 
     Ok(())
@@ -320,6 +728,99 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_scan_results() {
+        let x: InfluxResults<ScanRow> = serde_json::from_value(serde_json::json!({
+            "results": [{
+                "statement_id": 0,
+                "series": [{
+                    "name": "stratagem_scan",
+                    "columns": ["time", "group_name", "counter_name", "count", "size"],
+                    "values": [
+                        [10, "size_distribution", "greater_than_equal_1g", 2, 4_294_967_296_u64],
+                        [10, "size_distribution", "less_than_1m", 40, 1024],
+                        [10, "user_distribution", "0", 5, 10],
+                        [10, "user_distribution", "1000", 37, 4_294_968_310_u64],
+                        [10, "warn_fids", "fids_expiring_soon", 3, 30],
+                    ]
+                }]
+            }]
+        }))
+        .unwrap();
+
+        let x = scan_results(x.values()).unwrap();
+
+        assert_eq!(x.time, 10);
+        assert_eq!(
+            x.size_distribution
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["< 1 MiB", ">= 1 GiB"]
+        );
+        assert_eq!(x.top_inode_users[0].name, "1000");
+        assert_eq!(x.fids_expiring_soon.as_ref().map(|c| c.count), Some(3));
+        assert_eq!(x.fids_expired, None);
+
+        let empty: InfluxResults<ScanRow> =
+            serde_json::from_value(serde_json::json!({ "results": [{ "statement_id": 0 }] }))
+                .unwrap();
+
+        assert_eq!(scan_results(empty.values()), None);
+    }
+
+    #[test]
+    fn test_counter_rows() {
+        let counter = |name: &str, count, size| ScanCounter {
+            name: name.into(),
+            count,
+            size,
+        };
+
+        let rows = counter_rows(
+            "User",
+            &[counter("0", 5, 2048), counter("1000", 7, 1024)],
+            Some(&[counter("0", 8, 1024)]),
+        );
+
+        assert_eq!(rows[0], vec!["User", "0", "5", "2.0 KiB", "-3", "+1.0 KiB"]);
+        assert_eq!(
+            rows[1],
+            vec!["User", "1000", "7", "1.0 KiB", "+7", "+1.0 KiB"]
+        );
+    }
+
+    #[test]
+    fn test_with_dropped() {
+        let counter = |name: &str, count| ScanCounter {
+            name: name.into(),
+            count,
+            size: count * 1024,
+        };
+
+        let all = vec![counter("1000", 9), counter("0", 5), counter("1001", 2)];
+        let before = vec![counter("1002", 8), counter("0", 7), counter("1001", 6)];
+
+        let xs = with_dropped(&all[..2], &all, Some(&before[..]));
+
+        assert_eq!(
+            xs,
+            vec![
+                counter("1000", 9),
+                counter("0", 5),
+                counter("1002", 0),
+                counter("1001", 2),
+            ]
+        );
+
+        let rows = counter_rows("User", &xs, Some(&before));
+
+        assert_eq!(rows[2], vec!["User", "1002", "0", "0.0 B", "-8", "-8.0 KiB"]);
+        assert_eq!(rows[3][4], "-4");
+
+        assert_eq!(with_dropped(&all[..2], &all, None), all[..2].to_vec());
+    }
+
     #[test]
     fn test_parse_duration_with_invalid_data() {
         match parse_duration("abch") {
@@ -0,0 +1,249 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    api_utils::{
+        check_cmds, get, get_available_actions, get_one, run_available_action, wait_for_cmds,
+    },
+    display_utils::{display_cancelled, display_output, generate_table, wrap_fut, DisplayType},
+    error::ImlManagerCliError,
+};
+use futures::future::try_join_all;
+use iml_wire_types::{ApiList, EndpointName, Filesystem, FlatQuery, Target, TargetConfParam};
+use prettytable::{Row, Table};
+use structopt::StructOpt;
+
+type AnyTarget = Target<TargetConfParam>;
+
+/// Selects a single target by name, or every target in a filesystem.
+#[derive(Debug, StructOpt)]
+pub struct TargetSelector {
+    /// The target name, i.e. fs-OST0000
+    #[structopt(name = "TARGET", raw(required_unless = "\"fsname\""))]
+    name: Option<String>,
+    /// The filesystem the target(s) belong to
+    #[structopt(short = "f", long = "filesystem")]
+    fsname: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum TargetCommand {
+    /// List all targets
+    #[structopt(name = "list")]
+    List {
+        /// Only list the targets of this filesystem
+        #[structopt(short = "f", long = "filesystem")]
+        fsname: Option<String>,
+    },
+    /// Show target
+    #[structopt(name = "show")]
+    Show {
+        #[structopt(name = "TARGET")]
+        name: String,
+        /// The filesystem the target belongs to
+        #[structopt(short = "f", long = "filesystem")]
+        fsname: Option<String>,
+    },
+    /// Start (mount) target(s)
+    #[structopt(name = "start")]
+    Start(TargetSelector),
+    /// Stop (unmount) target(s)
+    #[structopt(name = "stop")]
+    Stop(TargetSelector),
+    /// Failover target(s) to their failover server
+    #[structopt(name = "failover")]
+    Failover(TargetSelector),
+    /// Failback target(s) to their primary server
+    #[structopt(name = "failback")]
+    Failback(TargetSelector),
+}
+
+/// Fetches the targets matching `name` and / or `fsname`.
+async fn get_targets(
+    name: Option<&str>,
+    fsname: Option<&str>,
+) -> Result<Vec<AnyTarget>, ImlManagerCliError> {
+    let fs_id = match fsname {
+        Some(fsname) => {
+            let fs: Filesystem = get_one(vec![("name", fsname)]).await?;
+
+            Some(fs.id.to_string())
+        }
+        None => None,
+    };
+
+    let mut query = AnyTarget::query();
+
+    if let Some(name) = name {
+        query.push(("name", name));
+    }
+
+    if let Some(fs_id) = fs_id.as_ref() {
+        query.push(("filesystem_id", fs_id));
+    }
+
+    let targets: ApiList<AnyTarget> = get(AnyTarget::endpoint_name(), query).await?;
+
+    Ok(targets.objects)
+}
+
+/// Fetches the single target called `name`.
+///
+/// Target names are only unique within a filesystem,
+/// so `fsname` is needed when more than one matches.
+async fn get_target(name: &str, fsname: Option<&str>) -> Result<AnyTarget, ImlManagerCliError> {
+    let mut xs = get_targets(Some(name), fsname).await?;
+
+    match xs.len() {
+        0 => Err(ImlManagerCliError::DoesNotExist(AnyTarget::endpoint_name())),
+        1 => Ok(xs.remove(0)),
+        _ => Err(ImlManagerCliError::ApiError(format!(
+            "{} matches more than one target, pass --filesystem",
+            name
+        ))),
+    }
+}
+
+fn option_str(x: &Option<String>) -> String {
+    x.clone().unwrap_or_else(|| "---".into())
+}
+
+/// Runs the available action with `verb` on each selected target,
+/// then waits for the resulting commands.
+///
+/// When a filesystem is selected, targets the action is not available for
+/// (i.e. starting a target that is already mounted) are skipped.
+async fn run_target_action(
+    verb: &str,
+    TargetSelector { name, fsname }: TargetSelector,
+) -> Result<(), ImlManagerCliError> {
+    let targets = match name.as_ref() {
+        Some(name) => {
+            vec![wrap_fut("Fetching target...", get_target(name, fsname.as_deref())).await?]
+        }
+        None => wrap_fut("Fetching targets...", get_targets(None, fsname.as_deref())).await?,
+    };
+
+    let actions = wrap_fut(
+        "Fetching available actions...",
+        try_join_all(
+            targets
+                .iter()
+                .map(|t| get_available_actions(t.id, t.content_type_id)),
+        ),
+    )
+    .await?;
+
+    let mut xs = vec![];
+
+    for (t, actions) in targets.iter().zip(actions) {
+        match actions
+            .objects
+            .into_iter()
+            .find(|x| x.verb.eq_ignore_ascii_case(verb))
+        {
+            Some(action) => xs.push((t, action)),
+            None if name.is_some() => {
+                return Err(ImlManagerCliError::ApiError(format!(
+                    "{} is not available for {} ({})",
+                    verb, t.name, t.state
+                )))
+            }
+            None => display_cancelled(format!(
+                "Skipping {}, {} is not available ({})",
+                t.name, verb, t.state
+            )),
+        }
+    }
+
+    let cmds = try_join_all(
+        xs.iter()
+            .map(|(t, action)| run_available_action(action, &t.resource_uri)),
+    )
+    .await?;
+
+    let cmds: Vec<_> = cmds.into_iter().flatten().collect();
+
+    if cmds.is_empty() {
+        display_cancelled("Nothing to do");
+
+        return Ok(());
+    }
+
+    let cmds = wait_for_cmds(cmds).await?;
+
+    check_cmds(&cmds)
+}
+
+pub async fn target_cli(
+    command: TargetCommand,
+    display_type: DisplayType,
+) -> Result<(), ImlManagerCliError> {
+    match command {
+        TargetCommand::List { fsname } => {
+            let targets =
+                wrap_fut("Fetching targets...", get_targets(None, fsname.as_deref())).await?;
+
+            tracing::debug!("Targets: {:?}", targets);
+
+            display_output(&targets, display_type, || {
+                generate_table(
+                    &[
+                        "Id",
+                        "Name",
+                        "Filesystem",
+                        "Kind",
+                        "State",
+                        "Active Host",
+                        "Primary Server",
+                        "Failover Server",
+                    ],
+                    targets.iter().map(|t| {
+                        vec![
+                            t.id.to_string(),
+                            t.name.clone(),
+                            option_str(&t.filesystem_name),
+                            t.kind.clone(),
+                            t.state.clone(),
+                            t.active_host_name.clone(),
+                            t.primary_server_name.clone(),
+                            t.failover_server_name.clone(),
+                        ]
+                    }),
+                )
+            })?;
+        }
+        TargetCommand::Show { name, fsname } => {
+            let t = wrap_fut("Fetching target...", get_target(&name, fsname.as_deref())).await?;
+
+            tracing::debug!("Target: {:?}", t);
+
+            display_output(&t, display_type, || {
+                let mut table = Table::new();
+
+                for (k, v) in &[
+                    ("Name", t.name.clone()),
+                    ("Kind", t.kind.clone()),
+                    ("Filesystem", option_str(&t.filesystem_name)),
+                    ("State", t.state.clone()),
+                    ("Active Host", t.active_host_name.clone()),
+                    ("Primary Server", t.primary_server_name.clone()),
+                    ("Failover Server", t.failover_server_name.clone()),
+                    ("Volume", t.volume_name.clone()),
+                    ("UUID", option_str(&t.uuid)),
+                ] {
+                    table.add_row(Row::from(&[k.to_string(), v.clone()]));
+                }
+
+                table
+            })?;
+        }
+        TargetCommand::Start(x) => run_target_action("Start", x).await?,
+        TargetCommand::Stop(x) => run_target_action("Stop", x).await?,
+        TargetCommand::Failover(x) => run_target_action("Failover", x).await?,
+        TargetCommand::Failback(x) => run_target_action("Failback", x).await?,
+    };
+
+    Ok(())
+}
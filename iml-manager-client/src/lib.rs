@@ -2,7 +2,7 @@
 // Use of this source code is governed by a MIT-style
 // license that can be found in the LICENSE file.
 
-pub use reqwest::{header, Client, Response, StatusCode, Url};
+pub use reqwest::{header, Certificate, Client, Response, StatusCode, Url};
 use serde::de::DeserializeOwned;
 use std::{fmt::Debug, time::Duration};
 
@@ -12,6 +12,7 @@ pub enum ImlManagerClientError {
     InvalidHeaderValue(reqwest::header::InvalidHeaderValue),
     UrlParseError(url::ParseError),
     SerdeJsonError(serde_json::error::Error),
+    IoError(std::io::Error),
 }
 
 impl std::fmt::Display for ImlManagerClientError {
@@ -21,6 +22,7 @@ impl std::fmt::Display for ImlManagerClientError {
             ImlManagerClientError::InvalidHeaderValue(ref err) => write!(f, "{}", err),
             ImlManagerClientError::UrlParseError(ref err) => write!(f, "{}", err),
             ImlManagerClientError::SerdeJsonError(ref err) => write!(f, "{}", err),
+            ImlManagerClientError::IoError(ref err) => write!(f, "{}", err),
         }
     }
 }
@@ -32,6 +34,7 @@ impl std::error::Error for ImlManagerClientError {
             ImlManagerClientError::InvalidHeaderValue(ref err) => Some(err),
             ImlManagerClientError::UrlParseError(ref err) => Some(err),
             ImlManagerClientError::SerdeJsonError(ref err) => Some(err),
+            ImlManagerClientError::IoError(ref err) => Some(err),
         }
     }
 }
@@ -60,9 +63,18 @@ impl From<serde_json::error::Error> for ImlManagerClientError {
     }
 }
 
-/// Get a client that is able to make authenticated requests
-/// against the API
-pub fn get_client() -> Result<Client, ImlManagerClientError> {
+impl From<std::io::Error> for ImlManagerClientError {
+    fn from(err: std::io::Error) -> Self {
+        ImlManagerClientError::IoError(err)
+    }
+}
+
+/// Builds a client that authenticates with the API key from the env.
+///
+/// The manager is verified against the configured CA certificate, if there is one,
+/// and the system roots otherwise.
+/// Any certificate is only accepted when `IML_INSECURE` is set, as it is on the manager.
+fn client_builder() -> Result<reqwest::ClientBuilder, ImlManagerClientError> {
     let header_value = header::HeaderValue::from_str(&format!(
         "ApiKey {}:{}",
         iml_manager_env::get_api_user(),
@@ -73,10 +85,40 @@ pub fn get_client() -> Result<Client, ImlManagerClientError> {
         .into_iter()
         .collect();
 
-    Client::builder()
+    let builder = Client::builder().default_headers(headers);
+
+    let builder = match iml_manager_env::get_ca_cert() {
+        Some(path) => {
+            let pem = std::fs::read(path)?;
+
+            builder.add_root_certificate(Certificate::from_pem(&pem)?)
+        }
+        None => builder,
+    };
+
+    let builder = if iml_manager_env::get_insecure() {
+        builder.danger_accept_invalid_certs(true)
+    } else {
+        builder
+    };
+
+    Ok(builder)
+}
+
+/// Get a client that is able to make authenticated requests
+/// against the API
+pub fn get_client() -> Result<Client, ImlManagerClientError> {
+    client_builder()?
         .timeout(Duration::from_secs(60))
-        .default_headers(headers)
-        .danger_accept_invalid_certs(true)
+        .build()
+        .map_err(ImlManagerClientError::Reqwest)
+}
+
+/// Get a client for long lived streams, such as `/messaging`.
+///
+/// Unlike `get_client`, requests made by this client never time out.
+pub fn get_stream_client() -> Result<Client, ImlManagerClientError> {
+    client_builder()?
         .build()
         .map_err(ImlManagerClientError::Reqwest)
 }
@@ -161,6 +203,73 @@ pub async fn put(
         .error_for_status()?)
 }
 
+/// Performs a PATCH to the given API path
+pub async fn patch(
+    client: Client,
+    path: &str,
+    body: impl serde::Serialize,
+) -> Result<Response, ImlManagerClientError> {
+    let uri = create_api_url(path)?;
+
+    Ok(client
+        .patch(uri)
+        .json(&body)
+        .send()
+        .await?
+        .error_for_status()?)
+}
+
+/// Opens the warp-drive SSE stream at `/messaging`.
+///
+/// If `last_event_id` is set, warp-drive replays what was sent after it
+/// where it can, and starts from a fresh snapshot otherwise.
+pub async fn get_messaging(
+    client: Client,
+    query: impl serde::Serialize,
+    last_event_id: Option<&str>,
+) -> Result<Response, ImlManagerClientError> {
+    let uri = Url::parse(&iml_manager_env::get_manager_url())?.join("/messaging")?;
+
+    tracing::debug!("GET to {} {}", uri, serde_json::json!(query));
+
+    let mut req = client
+        .get(uri)
+        .query(&query)
+        .header(header::ACCEPT, "text/event-stream");
+
+    if let Some(id) = last_event_id {
+        req = req.header("Last-Event-ID", id);
+    }
+
+    Ok(req.send().await?.error_for_status()?)
+}
+
+/// Runs an InfluxQL query against `db` through the `/influx` proxy.
+///
+/// Timestamps in the results are nanosecond epochs.
+pub async fn get_influx<T: DeserializeOwned + Debug>(
+    client: Client,
+    db: &str,
+    q: &str,
+) -> Result<T, ImlManagerClientError> {
+    let uri = Url::parse(&iml_manager_env::get_manager_url())?.join("/influx")?;
+
+    tracing::debug!("GET to {} {}", uri, q);
+
+    let resp = client
+        .get(uri)
+        .query(&[("db", db), ("epoch", "ns"), ("q", q)])
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let json = resp.json().await?;
+
+    tracing::debug!("Resp: {:?}", json);
+
+    Ok(json)
+}
+
 /// Performs a DELETE to the given API path
 pub async fn delete(
     client: Client,
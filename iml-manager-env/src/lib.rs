@@ -109,6 +109,27 @@ pub fn get_mailbox_path() -> PathBuf {
     get_var("MAILBOX_PATH").into()
 }
 
+/// Get the max number of bytes a single mailbox address may hold from the env or panic
+pub fn get_mailbox_address_quota() -> u64 {
+    get_var("MAILBOX_ADDRESS_QUOTA")
+        .parse()
+        .expect("MAILBOX_ADDRESS_QUOTA must be a number of bytes")
+}
+
+/// Get the max number of bytes the whole mailbox may hold from the env or panic
+pub fn get_mailbox_total_quota() -> u64 {
+    get_var("MAILBOX_TOTAL_QUOTA")
+        .parse()
+        .expect("MAILBOX_TOTAL_QUOTA must be a number of bytes")
+}
+
+/// Get the number of seconds an unwritten mailbox address is kept for from the env or panic
+pub fn get_mailbox_ttl() -> u64 {
+    get_var("MAILBOX_TTL")
+        .parse()
+        .expect("MAILBOX_TTL must be a number of seconds")
+}
+
 /// Get the api key from the env or panic
 pub fn get_api_key() -> String {
     get_var("API_KEY")
@@ -123,6 +144,20 @@ pub fn get_manager_url() -> String {
     get_var("SERVER_HTTP_URL")
 }
 
+/// Get the path to a PEM CA certificate to verify the manager with, if one is set
+pub fn get_ca_cert() -> Option<String> {
+    env::var("IML_CA_CERT").ok().and_then(empty_str_to_none)
+}
+
+/// Should any certificate the manager presents be accepted?
+///
+/// The manager sets this for its own services, as its certificate is self-signed.
+pub fn get_insecure() -> bool {
+    env::var("IML_INSECURE")
+        .map(|x| x == "true")
+        .unwrap_or(false)
+}
+
 pub fn get_db_user() -> String {
     get_var("DB_USER")
 }
@@ -0,0 +1,312 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! # Fan-out
+//!
+//! Dispatches a single `Action` to every host in a hostlist expression.
+//!
+//! Results are streamed back to the caller as server-sent events,
+//! one `HostResult` per host, in the order they complete.
+//! A failure on one host does not affect the others.
+//!
+//! A result that can't be serialized is sent as an `error` event instead.
+
+use crate::{
+    data::SessionToRpcs,
+    error::{ActionRunnerError, RequiredError},
+    sender::send_remote_action,
+    Sessions, Shared,
+};
+use futures::{stream, Future, FutureExt, Stream, StreamExt, TryFutureExt};
+use iml_rabbit::Client;
+use iml_wire_types::{Action, ActionId, Fqdn};
+use std::{convert::Infallible, sync::Arc};
+use warp::{self, sse::ServerSentEvent, Filter};
+
+/// The number of hosts dispatched to at once when the caller does not specify.
+pub const DEFAULT_PARALLELISM: usize = 32;
+
+/// An `Action` to be run on every host matched by `hosts`.
+///
+/// Each host receives its own `ActionId`, derived from the id of `action`.
+/// Sending a `FanOut` containing an `ActionCancel` with the same id and hosts
+/// will cancel the action on every host.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct FanOut {
+    /// A hostlist expression, i.e. `oss[1-4].local`
+    pub hosts: String,
+    pub action: Action,
+    /// The maximum number of hosts to dispatch to at once.
+    #[serde(default)]
+    pub parallelism: Option<usize>,
+}
+
+/// The outcome of running a fanned out `Action` on a single host.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct HostResult {
+    pub fqdn: Fqdn,
+    pub result: Result<serde_json::Value, String>,
+}
+
+/// Derives the `ActionId` used for a given host.
+pub fn host_action_id(id: &ActionId, fqdn: &Fqdn) -> ActionId {
+    ActionId(format!("{}-{}", id, fqdn))
+}
+
+/// Rewrites an `Action` so its id is unique to the given host.
+pub fn host_action(action: &Action, fqdn: &Fqdn) -> Action {
+    match action {
+        Action::ActionStart { action, args, id } => Action::ActionStart {
+            action: action.clone(),
+            args: args.clone(),
+            id: host_action_id(id, fqdn),
+        },
+        Action::ActionCancel { id } => Action::ActionCancel {
+            id: host_action_id(id, fqdn),
+        },
+    }
+}
+
+/// Expands the hostlist expression of a `FanOut` into a list of `Fqdn`s.
+pub fn expand_hosts(fan_out: &FanOut) -> Result<Vec<Fqdn>, ActionRunnerError> {
+    let hosts = hostlist_parser::parse(&fan_out.hosts)
+        .map_err(|e| RequiredError(format!("Could not parse hostlist {}: {}", fan_out.hosts, e)))?;
+
+    Ok(hosts.into_iter().map(Fqdn).collect())
+}
+
+/// Runs `action` on each of `hosts` with `send`, with at most `parallelism`
+/// hosts in flight at once.
+///
+/// Errors sending to a host are reported as that host's result.
+pub fn dispatch<F, Fut>(
+    action: Action,
+    hosts: Vec<Fqdn>,
+    parallelism: Option<usize>,
+    send: F,
+) -> impl Stream<Item = HostResult>
+where
+    F: Fn(Fqdn, Action) -> Fut,
+    Fut: Future<Output = Result<Result<serde_json::Value, String>, ActionRunnerError>>,
+{
+    let parallelism = parallelism.unwrap_or(DEFAULT_PARALLELISM).max(1);
+
+    stream::iter(hosts)
+        .map(move |fqdn| {
+            let action = host_action(&action, &fqdn);
+
+            send(fqdn.clone(), action)
+                .map_ok_or_else(|e| Err(format!("{}", e)), |x| x)
+                .map(move |result| HostResult { fqdn, result })
+        })
+        .buffer_unordered(parallelism)
+}
+
+/// Runs the `Action` of a `FanOut` on each host, with at most `parallelism`
+/// hosts in flight at once.
+pub fn run_fan_out(
+    fan_out: FanOut,
+    hosts: Vec<Fqdn>,
+    client: Client,
+    queue_name: String,
+    sessions: Shared<Sessions>,
+    session_to_rpcs: Shared<SessionToRpcs>,
+) -> impl Stream<Item = HostResult> {
+    dispatch(
+        fan_out.action,
+        hosts,
+        fan_out.parallelism,
+        move |fqdn, action| {
+            send_remote_action(
+                fqdn,
+                action,
+                client.clone(),
+                queue_name.clone(),
+                Arc::clone(&sessions),
+                Arc::clone(&session_to_rpcs),
+            )
+        },
+    )
+}
+
+/// Creates a warp `Filter` that accepts a `FanOut` at `POST /fan_out`.
+pub fn fan_out(
+    queue_name: impl Into<String>,
+    sessions: Shared<Sessions>,
+    session_to_rpcs: Shared<SessionToRpcs>,
+    client_filter: impl Filter<Extract = (Client,), Error = warp::Rejection> + Clone + Send,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let queue_name = queue_name.into();
+
+    let sessions_filter = warp::any().map(move || Arc::clone(&sessions));
+    let session_to_rpcs_filter = warp::any().map(move || Arc::clone(&session_to_rpcs));
+    let queue_name_filter = warp::any().map(move || queue_name.clone());
+
+    let deps = sessions_filter
+        .and(session_to_rpcs_filter)
+        .and(client_filter)
+        .and(queue_name_filter);
+
+    warp::post()
+        .and(warp::path("fan_out"))
+        .and(warp::path::end())
+        .and(deps)
+        .and(warp::body::json())
+        .and_then(
+            move |sessions: Shared<Sessions>,
+                  session_to_rpcs: Shared<SessionToRpcs>,
+                  client: Client,
+                  queue_name: String,
+                  fan_out: FanOut| {
+                async move {
+                    let hosts = expand_hosts(&fan_out)?;
+
+                    tracing::debug!("Fanning out {:?} to {} hosts", fan_out.action, hosts.len());
+
+                    let stream = run_fan_out(
+                        fan_out,
+                        hosts,
+                        client,
+                        queue_name,
+                        sessions,
+                        session_to_rpcs,
+                    )
+                    .map(|x| {
+                        let event = match serde_json::to_string(&x) {
+                            Ok(s) => warp::sse::data(s).into_a(),
+                            Err(e) => (
+                                warp::sse::event("error"),
+                                warp::sse::data(format!(
+                                    "Could not serialize the result for {}: {}",
+                                    x.fqdn, e
+                                )),
+                            )
+                                .into_b(),
+                        };
+
+                        Ok::<_, Infallible>(event)
+                    });
+
+                    Ok::<_, ActionRunnerError>(warp::sse::reply(stream))
+                }
+                .map_err(warp::reject::custom)
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dispatch, expand_hosts, host_action, FanOut, HostResult};
+    use crate::error::ActionRunnerError;
+    use futures::StreamExt;
+    use iml_wire_types::{Action, ActionId, Fqdn};
+    use std::sync::{Arc, Mutex};
+    use tokio::time::{delay_for, Duration};
+
+    #[test]
+    fn test_expand_hosts() {
+        let fan_out = FanOut {
+            hosts: "oss[1-3].local".into(),
+            action: Action::ActionCancel {
+                id: ActionId("1234".into()),
+            },
+            parallelism: None,
+        };
+
+        let actual = expand_hosts(&fan_out).unwrap();
+
+        assert_eq!(
+            actual,
+            vec![
+                Fqdn("oss1.local".into()),
+                Fqdn("oss2.local".into()),
+                Fqdn("oss3.local".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_hosts_bad_expression() {
+        let fan_out = FanOut {
+            hosts: "oss[1-".into(),
+            action: Action::ActionCancel {
+                id: ActionId("1234".into()),
+            },
+            parallelism: None,
+        };
+
+        assert!(expand_hosts(&fan_out).is_err());
+    }
+
+    #[test]
+    fn test_host_action_has_unique_id() {
+        let action = Action::ActionStart {
+            action: "get_kernel".into(),
+            args: serde_json::Value::Null,
+            id: ActionId("1234".into()),
+        };
+
+        let a = host_action(&action, &Fqdn("oss1".into()));
+        let b = host_action(&action, &Fqdn("oss2".into()));
+
+        assert_ne!(a.get_id(), b.get_id());
+        assert_eq!(a.get_id(), &ActionId("1234-oss1".into()));
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let action = Action::ActionStart {
+            action: "get_kernel".into(),
+            args: serde_json::Value::Null,
+            id: ActionId("1234".into()),
+        };
+
+        let hosts: Vec<_> = (1..=6).map(|i| Fqdn(format!("oss{}", i))).collect();
+
+        // (in flight, most in flight at once)
+        let counts = Arc::new(Mutex::new((0, 0)));
+
+        let send = {
+            let counts = Arc::clone(&counts);
+
+            move |fqdn: Fqdn, action: Action| {
+                let counts = Arc::clone(&counts);
+
+                async move {
+                    {
+                        let mut x = counts.lock().unwrap();
+                        x.0 += 1;
+                        x.1 = x.1.max(x.0);
+                    }
+
+                    delay_for(Duration::from_millis(10)).await;
+
+                    counts.lock().unwrap().0 -= 1;
+
+                    match fqdn.0.as_str() {
+                        "oss3" => Err(ActionRunnerError::AwaitSession(fqdn)),
+                        "oss4" => Ok(Err("boom".into())),
+                        _ => Ok(Ok(serde_json::json!(action.get_id()))),
+                    }
+                }
+            }
+        };
+
+        let mut results: Vec<HostResult> = dispatch(action, hosts, Some(2), send).collect().await;
+
+        results.sort_by(|a, b| a.fqdn.0.cmp(&b.fqdn.0));
+
+        assert_eq!(counts.lock().unwrap().1, 2);
+        assert_eq!(results.len(), 6);
+        assert_eq!(
+            results[0].result,
+            Ok(serde_json::json!(ActionId("1234-oss1".into())))
+        );
+        assert_eq!(
+            results[2].result,
+            Err(ActionRunnerError::AwaitSession(Fqdn("oss3".into())).to_string())
+        );
+        assert_eq!(results[3].result, Err("boom".into()));
+    }
+}
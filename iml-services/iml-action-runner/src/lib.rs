@@ -4,7 +4,9 @@
 
 pub mod data;
 pub mod error;
+pub mod fan_out;
 pub mod local_actions;
+pub mod queue_stats;
 pub mod receiver;
 pub mod sender;
 
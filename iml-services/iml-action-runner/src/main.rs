@@ -7,13 +7,16 @@
 use futures::{lock::Mutex, prelude::*};
 use iml_action_runner::{
     data::SessionToRpcs,
+    fan_out::fan_out,
     local_actions::SharedLocalActionsInFlight,
+    queue_stats::{queue_stats, QueueStats},
     receiver::handle_agent_data,
     sender::{create_client_filter, sender},
     Sessions, Shared,
 };
-use iml_service_queue::service_queue::{consume_service_queue, ImlServiceQueueError};
+use iml_service_queue::service_queue::{consume_data, consume_service_queue, ImlServiceQueueError};
 use iml_util::tokio_utils::get_tcp_or_unix_listener;
+use iml_wire_types::ActionQueueStats;
 use std::{collections::HashMap, sync::Arc};
 use tracing_subscriber::{fmt::Subscriber, EnvFilter};
 use warp::{self, Filter as _};
@@ -33,6 +36,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let sessions: Shared<Sessions> = Arc::new(Mutex::new(HashMap::new()));
     let rpcs: Shared<SessionToRpcs> = Arc::new(Mutex::new(HashMap::new()));
     let local_actions: SharedLocalActionsInFlight = Arc::new(Mutex::new(HashMap::new()));
+    let stats: Shared<QueueStats> = Arc::new(Mutex::new(HashMap::new()));
 
     let log = warp::log("iml_action_runner::sender");
 
@@ -40,15 +44,28 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     tokio::spawn(fut);
 
-    let routes = sender(
+    let fan_out_route = fan_out(
+        AGENT_TX_RUST,
+        Arc::clone(&sessions),
+        Arc::clone(&rpcs),
+        client_filter.clone(),
+    );
+
+    let sender_route = sender(
         AGENT_TX_RUST,
         Arc::clone(&sessions),
         Arc::clone(&rpcs),
         Arc::clone(&local_actions),
         client_filter,
     )
-    .map(|x| warp::reply::json(&x))
-    .with(log);
+    .map(|x| warp::reply::json(&x));
+
+    let queue_stats_route = queue_stats(Arc::clone(&stats));
+
+    let routes = fan_out_route
+        .or(queue_stats_route)
+        .or(sender_route)
+        .with(log);
 
     let mut listener = get_tcp_or_unix_listener("ACTION_RUNNER_PORT").await?;
 
@@ -79,6 +96,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .map(drop),
     );
 
+    let mut queue_stats_rx = valve.wrap(consume_data::<ActionQueueStats>(
+        "rust_agent_action_queue_rx",
+    ));
+
+    tokio::spawn(
+        async move {
+            while let Some((fqdn, x)) = queue_stats_rx.try_next().await? {
+                tracing::debug!("Action queue on {}: {:?}", fqdn, x);
+
+                stats.lock().await.insert(fqdn, x);
+            }
+
+            Ok(())
+        }
+        .map_err(|e: ImlServiceQueueError| {
+            tracing::error!("Stopped reading action queue stats: {}", e);
+        })
+        .map(drop),
+    );
+
     warp::serve(routes).run_incoming(incoming).await;
 
     Ok(())
@@ -0,0 +1,35 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! # Queue Stats
+//!
+//! Serves the last `ActionQueueStats` reported by each agent
+//! at `GET /queue_stats`.
+
+use crate::Shared;
+use iml_wire_types::{ActionQueueStats, Fqdn};
+use std::{collections::HashMap, sync::Arc};
+use warp::{self, Filter};
+
+/// The last stats reported by each host.
+pub type QueueStats = HashMap<Fqdn, ActionQueueStats>;
+
+/// Creates a warp `Filter` that returns the last stats reported by each host.
+pub fn queue_stats(
+    queue_stats: Shared<QueueStats>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let queue_stats_filter = warp::any().map(move || Arc::clone(&queue_stats));
+
+    warp::get()
+        .and(warp::path("queue_stats"))
+        .and(warp::path::end())
+        .and(queue_stats_filter)
+        .and_then(|queue_stats: Shared<QueueStats>| {
+            async move {
+                let x = queue_stats.lock().await;
+
+                Ok::<_, warp::Rejection>(warp::reply::json(&*x))
+            }
+        })
+}
@@ -13,7 +13,7 @@ use crate::{
 };
 use futures::{channel::oneshot, Future, TryFutureExt};
 use iml_rabbit::{connect_to_rabbit, get_cloned_conns, send_message, Client};
-use iml_wire_types::{Action, ActionId, Id, ManagerMessage};
+use iml_wire_types::{Action, ActionId, Fqdn, Id, ManagerMessage};
 use std::{sync::Arc, time::Duration};
 use warp::{self, Filter};
 
@@ -57,6 +57,47 @@ async fn cancel_running_action(
     Ok(Ok(serde_json::Value::Null))
 }
 
+/// Sends an `Action` to the agent on the given `Fqdn` and waits for the result.
+///
+/// Cancel actions are sent immediately and resolve once the matching
+/// `ActionInFlight` has been completed.
+pub(crate) async fn send_remote_action(
+    fqdn: Fqdn,
+    action: Action,
+    client: Client,
+    queue_name: String,
+    sessions: Shared<Sessions>,
+    session_to_rpcs: Shared<SessionToRpcs>,
+) -> Result<Result<serde_json::Value, String>, ActionRunnerError> {
+    let session_id: Id = await_session(fqdn.clone(), sessions, Duration::from_secs(30)).await?;
+
+    tracing::debug!("Sending {:?} to {}", action, fqdn);
+
+    let msg = create_data_message(session_id.clone(), fqdn, action.clone());
+
+    match action {
+        Action::ActionCancel { id } => {
+            cancel_running_action(client, msg, queue_name, session_id, id, session_to_rpcs).await
+        }
+        action => {
+            let (tx, rx) = oneshot::channel();
+
+            send_message(client, "", queue_name, msg).await?;
+
+            let action_id: ActionId = action.get_id().clone();
+            let af = ActionInFlight::new(action, tx);
+
+            {
+                let mut lock = session_to_rpcs.lock().await;
+
+                insert_action_in_flight(session_id, action_id, af, &mut lock);
+            }
+
+            rx.await.map_err(|e| e.into())
+        }
+    }
+}
+
 /// Creates a warp `Filter` that will hand out
 /// a cloned client for each request.
 pub async fn create_client_filter() -> Result<
@@ -117,43 +158,15 @@ pub fn sender(
                         handle_local_action(action, local_actions, shared_sessions).await
                     }
                     ActionType::Remote((fqdn, action)) => {
-                        let session_id: Id =
-                            await_session(fqdn.clone(), shared_sessions, Duration::from_secs(30))
-                                .await?;
-
-                        tracing::debug!("Sending {:?} to {}", action, fqdn);
-
-                        let msg = create_data_message(session_id.clone(), fqdn, action.clone());
-
-                        match action {
-                            Action::ActionCancel { id } => {
-                                cancel_running_action(
-                                    client.clone(),
-                                    msg,
-                                    queue_name,
-                                    session_id,
-                                    id,
-                                    shared_session_to_rpcs,
-                                )
-                                .await
-                            }
-                            action => {
-                                let (tx, rx) = oneshot::channel();
-
-                                send_message(client.clone(), "", queue_name, msg).await?;
-
-                                let action_id: ActionId = action.get_id().clone();
-                                let af = ActionInFlight::new(action, tx);
-
-                                {
-                                    let mut lock = shared_session_to_rpcs.lock().await;
-
-                                    insert_action_in_flight(session_id, action_id, af, &mut lock);
-                                }
-
-                                rx.await.map_err(|e| e.into())
-                            }
-                        }
+                        send_remote_action(
+                            fqdn,
+                            action,
+                            client,
+                            queue_name,
+                            shared_sessions,
+                            shared_session_to_rpcs,
+                        )
+                        .await
                     }
                 }
             }
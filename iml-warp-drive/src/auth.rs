@@ -0,0 +1,318 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! # Auth
+//!
+//! Authenticates users of the SSE stream the same way the Django API does,
+//! with either a session cookie or an `ApiKey` authorization header.
+//!
+//! Credentials are looked up directly in the `chroma` database.
+
+use crate::error::ImlWarpDriveError;
+use futures::lock::Mutex;
+use iml_wire_types::{
+    warp_drive::{Locks, Message},
+    LockChange, LockType,
+};
+use std::sync::Arc;
+
+/// The name of the cookie Django stores the session key in.
+pub const SESSION_COOKIE_NAME: &str = "sessionid";
+
+/// The db client used for auth lookups.
+///
+/// This is `None` while the db is being (re)connected to.
+pub type SharedDbClient = Arc<Mutex<Option<iml_postgres::SharedClient>>>;
+
+/// The IML groups a user can belong to, from least to most privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Group {
+    FilesystemUsers,
+    FilesystemAdministrators,
+    Superusers,
+}
+
+impl Group {
+    fn from_name(x: &str) -> Option<Self> {
+        match x {
+            "filesystem_users" => Some(Group::FilesystemUsers),
+            "filesystem_administrators" => Some(Group::FilesystemAdministrators),
+            "superusers" => Some(Group::Superusers),
+            _ => None,
+        }
+    }
+    /// Resolves the most privileged group of a user.
+    ///
+    /// Django superusers are treated as `Superusers` whatever their groups,
+    /// and users in no IML group default to `FilesystemUsers`.
+    fn resolve(is_superuser: bool, groups: &[String]) -> Self {
+        if is_superuser {
+            return Group::Superusers;
+        }
+
+        groups
+            .iter()
+            .filter_map(|x| Group::from_name(x))
+            .max()
+            .unwrap_or(Group::FilesystemUsers)
+    }
+}
+
+/// An authenticated user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub user_id: i32,
+    pub username: String,
+    /// The most privileged group the user belongs to.
+    pub group: Group,
+}
+
+impl Session {
+    /// Users that can only view, not modify.
+    pub fn is_read_only(&self) -> bool {
+        self.group == Group::FilesystemUsers
+    }
+    /// Strips anything this user is not allowed to see from a `Message`.
+    pub fn redact(&self, msg: Message) -> Message {
+        if !self.is_read_only() {
+            return msg;
+        }
+
+        match msg {
+            Message::Locks(xs) => Message::Locks(self.redact_locks(xs)),
+            Message::LockChange(x) => Message::LockChange(redact_lock(x)),
+            x => x,
+        }
+    }
+    /// Strips what this user is not allowed to see from `Locks`.
+    pub fn redact_locks(&self, xs: Locks) -> Locks {
+        if !self.is_read_only() {
+            return xs;
+        }
+
+        xs.into_iter()
+            .map(|(k, v)| (k, v.into_iter().map(redact_lock).collect()))
+            .collect()
+    }
+}
+
+/// Write locks describe the job holding them, which read-only users don't get to see.
+fn redact_lock(mut x: LockChange) -> LockChange {
+    if x.lock_type == LockType::Write {
+        x.description = String::new();
+    }
+
+    x
+}
+
+/// Pulls the user id out of Django's encoded `session_data`.
+///
+/// The data is base64 of `<hash>:<json>`. The hash is not checked,
+/// as the data is read straight from the db.
+fn session_user_id(session_data: &str) -> Option<i32> {
+    let decoded = base64::decode(session_data).ok()?;
+
+    let idx = decoded.iter().position(|&x| x == b':')?;
+
+    let data: serde_json::Value = serde_json::from_slice(&decoded[idx + 1..]).ok()?;
+
+    data.get("_auth_user_id")?.as_str()?.parse().ok()
+}
+
+/// Parses an `Authorization: ApiKey <username>:<key>` header.
+fn parse_api_key(header: &str) -> Option<(&str, &str)> {
+    let mut xs = header.trim().splitn(2, ' ');
+
+    if !xs.next()?.eq_ignore_ascii_case("apikey") {
+        return None;
+    }
+
+    let mut xs = xs.next()?.trim().splitn(2, ':');
+
+    Some((xs.next()?, xs.next()?))
+}
+
+const USER_QUERY: &str = r#"
+    SELECT u.id, u.username, u.is_superuser, array_remove(array_agg(g.name), NULL) AS groups
+    FROM auth_user u
+    LEFT JOIN auth_user_groups ug ON ug.user_id = u.id
+    LEFT JOIN auth_group g ON g.id = ug.group_id
+"#;
+
+const SESSION_QUERY: &str = r#"
+    SELECT session_data FROM django_session
+    WHERE session_key = $1 AND expire_date > now()
+"#;
+
+async fn fetch_user(
+    client: &iml_postgres::Client,
+    filter: &str,
+    params: &[&(dyn iml_postgres::ToSql + Sync)],
+) -> Result<Option<Session>, ImlWarpDriveError> {
+    let query = format!(
+        "{} {} AND u.is_active GROUP BY u.id, u.username, u.is_superuser",
+        USER_QUERY, filter
+    );
+
+    let row = client
+        .query(query.as_str(), params)
+        .await?
+        .into_iter()
+        .next();
+
+    Ok(row.map(|row| {
+        let groups: Vec<String> = row.get("groups");
+
+        Session {
+            user_id: row.get("id"),
+            username: row.get("username"),
+            group: Group::resolve(row.get("is_superuser"), &groups),
+        }
+    }))
+}
+
+/// Authenticates a user by session cookie or API key.
+///
+/// Anonymous users are rejected with `ImlWarpDriveError::Unauthorized`.
+pub async fn authenticate(
+    db_client: SharedDbClient,
+    session_key: Option<String>,
+    authorization: Option<String>,
+) -> Result<Session, ImlWarpDriveError> {
+    let client = db_client
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| ImlWarpDriveError::Unavailable("Database not available".into()))?;
+
+    let client = client.lock().await;
+
+    if let Some((username, key)) = authorization.as_ref().and_then(|x| parse_api_key(x)) {
+        let session = fetch_user(
+            &client,
+            "JOIN tastypie_apikey k ON k.user_id = u.id WHERE u.username = $1 AND k.key = $2",
+            &[&username, &key],
+        )
+        .await?;
+
+        return session.ok_or_else(|| ImlWarpDriveError::Unauthorized("Invalid API key".into()));
+    }
+
+    if let Some(session_key) = session_key {
+        let row = client
+            .query(SESSION_QUERY, &[&session_key])
+            .await?
+            .into_iter()
+            .next();
+
+        let user_id = row
+            .and_then(|row| session_user_id(row.get("session_data")))
+            .ok_or_else(|| ImlWarpDriveError::Unauthorized("Invalid session".into()))?;
+
+        let session = fetch_user(&client, "WHERE u.id = $1", &[&user_id]).await?;
+
+        return session.ok_or_else(|| ImlWarpDriveError::Unauthorized("Invalid session".into()));
+    }
+
+    Err(ImlWarpDriveError::Unauthorized(
+        "Authentication required".into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_api_key, session_user_id, Group, Session};
+    use iml_wire_types::{warp_drive::Message, LockAction, LockChange, LockType};
+
+    fn lock(lock_type: LockType) -> LockChange {
+        LockChange {
+            uuid: "1".into(),
+            job_id: 1,
+            content_type_id: 1,
+            item_id: 1,
+            description: "Stop filesystem".into(),
+            lock_type,
+            action: LockAction::Add,
+        }
+    }
+
+    fn description(msg: Message) -> String {
+        match msg {
+            Message::LockChange(x) => x.description,
+            x => panic!("Unexpected message {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_session_user_id() {
+        let data = base64::encode(r#"abc123:{"_auth_user_id":"7","_auth_user_backend":"x"}"#);
+
+        assert_eq!(session_user_id(&data), Some(7));
+        assert_eq!(session_user_id("not base64!"), None);
+    }
+
+    #[test]
+    fn test_parse_api_key() {
+        assert_eq!(parse_api_key("ApiKey admin:abc"), Some(("admin", "abc")));
+        assert_eq!(parse_api_key("Basic YWRtaW4="), None);
+        assert_eq!(parse_api_key("ApiKey admin"), None);
+    }
+
+    #[test]
+    fn test_resolve_group() {
+        let groups = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(Group::resolve(false, &[]), Group::FilesystemUsers);
+        assert_eq!(
+            Group::resolve(false, &groups(&["other"])),
+            Group::FilesystemUsers
+        );
+        assert_eq!(
+            Group::resolve(
+                false,
+                &groups(&["filesystem_users", "filesystem_administrators"])
+            ),
+            Group::FilesystemAdministrators
+        );
+        assert_eq!(Group::resolve(true, &[]), Group::Superusers);
+        assert_eq!(
+            Group::resolve(true, &groups(&["filesystem_users"])),
+            Group::Superusers
+        );
+    }
+
+    #[test]
+    fn test_superusers_without_groups_are_not_redacted() {
+        let session = Session {
+            user_id: 1,
+            username: "admin".into(),
+            group: Group::resolve(true, &[]),
+        };
+
+        assert!(!session.is_read_only());
+
+        let redacted = session.redact(Message::LockChange(lock(LockType::Write)));
+        assert_eq!(description(redacted), "Stop filesystem");
+    }
+
+    #[test]
+    fn test_read_only_users_do_not_see_write_lock_descriptions() {
+        let mut session = Session {
+            user_id: 1,
+            username: "user".into(),
+            group: Group::FilesystemUsers,
+        };
+
+        let redacted = session.redact(Message::LockChange(lock(LockType::Write)));
+        assert_eq!(description(redacted), "");
+
+        let redacted = session.redact(Message::LockChange(lock(LockType::Read)));
+        assert_eq!(description(redacted), "Stop filesystem");
+
+        session.group = Group::FilesystemAdministrators;
+
+        let redacted = session.redact(Message::LockChange(lock(LockType::Write)));
+        assert_eq!(description(redacted), "Stop filesystem");
+    }
+}
@@ -3,168 +3,293 @@
 // license that can be found in the LICENSE file.
 
 use crate::{listen::MessageType, DbRecord};
-use futures::{future, lock::Mutex, Future, FutureExt, Stream, TryFutureExt, TryStreamExt};
+use futures::{future, lock::Mutex, Stream, TryFutureExt, TryStreamExt};
 use iml_manager_client::{get, get_client, Client, ImlManagerClientError};
 use iml_postgres::Client as PgClient;
 use iml_wire_types::{
     db::{
-        AlertStateRecord, FsRecord, Id, LnetConfigurationRecord, ManagedHostRecord,
-        ManagedTargetMountRecord, ManagedTargetRecord, Name, NotDeleted, OstPoolOstsRecord,
-        OstPoolRecord, StratagemConfiguration, VolumeNodeRecord, VolumeRecord,
+        ApplyToApiRecord, Device, DeviceHost, Id, LnetConfigurationRecord,
+        ManagedTargetMountRecord, Name, NotDeleted, OstPoolOstsRecord, OstPoolRecord,
+        StratagemConfiguration, VolumeNodeRecord,
     },
     warp_drive::{Cache, Record, RecordChange, RecordId},
     Alert, ApiList, EndpointName, Filesystem, FlatQuery, Host, Target, TargetConfParam, Volume,
 };
-use std::{collections::HashMap, fmt::Debug, iter, pin::Pin, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    iter,
+    sync::Arc,
+};
 
 pub type SharedCache = Arc<Mutex<Cache>>;
 
-pub trait ToApiRecord: std::fmt::Debug + Id {
-    fn to_api_record<T: 'static>(
-        &self,
-        client: Client,
-    ) -> Pin<Box<dyn Future<Output = Result<T, ImlManagerClientError>> + Send>>
-    where
-        T: Debug + serde::de::DeserializeOwned + EndpointName + FlatQuery + Send,
-    {
-        let id = self.id();
-
-        get(
-            client,
-            format!("{}/{}/", T::endpoint_name(), id),
-            T::query(),
-        )
-        .boxed()
+/// Builds the API record for `x` by applying it to the record in the `Cache`.
+///
+/// Returns `None` if the record is not cached yet, or if a foreign key changed.
+fn from_cache<T: Clone>(
+    x: &(impl Id + ApplyToApiRecord<T>),
+    cache: &Cache,
+    cached_fn: fn(&Cache) -> &HashMap<u32, T>,
+) -> Option<T> {
+    cached_fn(cache).get(&x.id()).cloned().and_then(|mut r| {
+        if x.apply_to(&mut r, cache) {
+            Some(r)
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the records in `xs` that can't be built from the `Cache`,
+/// and so have to be fetched from the API.
+pub fn missing_records(xs: &[(MessageType, DbRecord)], cache: &Cache) -> HashSet<RecordId> {
+    xs.iter()
+        .filter(|(msg_type, _)| match msg_type {
+            MessageType::Delete => false,
+            MessageType::Insert | MessageType::Update => true,
+        })
+        .filter(|(_, x)| match x {
+            DbRecord::ManagedHost(x) => {
+                x.not_deleted() && from_cache(x, cache, |c| &c.host).is_none()
+            }
+            DbRecord::ManagedFilesystem(x) => {
+                x.not_deleted() && from_cache(x, cache, |c| &c.filesystem).is_none()
+            }
+            DbRecord::ManagedTarget(x) => {
+                x.not_deleted() && from_cache(x, cache, |c| &c.target).is_none()
+            }
+            DbRecord::Volume(x) => x.not_deleted() && from_cache(x, cache, |c| &c.volume).is_none(),
+            DbRecord::AlertState(x) => {
+                x.is_active() && from_cache(x, cache, |c| &c.active_alert).is_none()
+            }
+            _ => false,
+        })
+        .map(|(_, x)| x.record_id())
+        .collect()
+}
+
+/// Fetches every `T` in `ids` with a single `id__in` query.
+async fn fetch_by_ids<T>(
+    client: Client,
+    ids: Vec<u32>,
+    id_fn: fn(&T) -> u32,
+) -> Result<HashMap<u32, T>, ImlManagerClientError>
+where
+    T: Debug + serde::de::DeserializeOwned + EndpointName + FlatQuery,
+{
+    if ids.is_empty() {
+        return Ok(HashMap::new());
     }
+
+    let ids = ids.iter().map(u32::to_string).collect::<Vec<_>>().join(",");
+
+    let query: Vec<(&str, &str)> = T::query()
+        .into_iter()
+        .chain(iter::once(("id__in", ids.as_str())))
+        .collect();
+
+    let xs: ApiList<T> = get(client, T::endpoint_name(), query).await?;
+
+    Ok(xs.objects.into_iter().map(|x| (id_fn(&x), x)).collect())
 }
 
-async fn converter<T>(
+/// Fetches the API records for `missing`, with one query per kind of record.
+///
+/// Records the API no longer has are left out of the returned `Cache`.
+pub async fn fetch_missing(
+    missing: &HashSet<RecordId>,
     client: Client,
+) -> Result<Cache, ImlManagerClientError> {
+    let ids =
+        |f: fn(&RecordId) -> Option<u32>| -> Vec<u32> { missing.iter().filter_map(f).collect() };
+
+    let (host, filesystem, target, volume, active_alert) = future::try_join5(
+        fetch_by_ids(
+            client.clone(),
+            ids(|x| match x {
+                RecordId::Host(id) => Some(*id),
+                _ => None,
+            }),
+            |x: &Host| x.id,
+        ),
+        fetch_by_ids(
+            client.clone(),
+            ids(|x| match x {
+                RecordId::Filesystem(id) => Some(*id),
+                _ => None,
+            }),
+            |x: &Filesystem| x.id,
+        ),
+        fetch_by_ids(
+            client.clone(),
+            ids(|x| match x {
+                RecordId::Target(id) => Some(*id),
+                _ => None,
+            }),
+            |x: &Target<TargetConfParam>| x.id,
+        ),
+        fetch_by_ids(
+            client.clone(),
+            ids(|x| match x {
+                RecordId::Volume(id) => Some(*id),
+                _ => None,
+            }),
+            |x: &Volume| x.id,
+        ),
+        fetch_by_ids(
+            client,
+            ids(|x| match x {
+                RecordId::ActiveAlert(id) => Some(*id),
+                _ => None,
+            }),
+            |x: &Alert| x.id,
+        ),
+    )
+    .await?;
+
+    Ok(Cache {
+        host,
+        filesystem,
+        target,
+        volume,
+        active_alert,
+        ..Cache::default()
+    })
+}
+
+fn converter<T, R>(
+    cache: &Cache,
+    fetched: &Cache,
     msg_type: MessageType,
-    x: impl ToApiRecord + NotDeleted,
+    x: R,
+    cached_fn: fn(&Cache) -> &HashMap<u32, T>,
     record_fn: fn(T) -> Record,
     record_id_fn: fn(u32) -> RecordId,
-) -> Result<RecordChange, ImlManagerClientError>
+) -> RecordChange
 where
-    T: std::fmt::Debug
-        + serde::de::DeserializeOwned
-        + 'static
-        + Send
-        + Sync
-        + EndpointName
-        + FlatQuery,
+    T: Clone,
+    R: Id + ApplyToApiRecord<T> + NotDeleted,
 {
     match (msg_type, &x) {
-        (MessageType::Delete, _) => Ok(RecordChange::Delete(record_id_fn(x.id()))),
-        (_, x) if x.deleted() => Ok(RecordChange::Delete(record_id_fn(x.id()))),
-        (MessageType::Insert, x) | (MessageType::Update, x) => {
-            let id = x.id();
-
-            let r = ToApiRecord::to_api_record(x, client).await;
-
-            match r {
-                Ok(x) => Ok(x).map(record_fn).map(RecordChange::Update),
-                Err(ImlManagerClientError::Reqwest(ref e))
-                    if e.status() == Some(iml_manager_client::StatusCode::NOT_FOUND) =>
-                {
-                    Ok(id).map(record_id_fn).map(RecordChange::Delete)
-                }
-                Err(e) => Err(e),
-            }
-        }
+        (MessageType::Delete, _) => RecordChange::Delete(record_id_fn(x.id())),
+        (_, x) if x.deleted() => RecordChange::Delete(record_id_fn(x.id())),
+        (MessageType::Insert, x) | (MessageType::Update, x) => from_cache(x, cache, cached_fn)
+            .or_else(|| cached_fn(fetched).get(&x.id()).cloned())
+            .map(|r| RecordChange::Update(record_fn(r)))
+            .unwrap_or_else(|| RecordChange::Delete(record_id_fn(x.id()))),
     }
 }
 
-impl ToApiRecord for ManagedHostRecord {}
-impl ToApiRecord for FsRecord {}
-impl ToApiRecord for ManagedTargetRecord {}
-impl ToApiRecord for VolumeRecord {}
-impl ToApiRecord for VolumeNodeRecord {}
-impl ToApiRecord for AlertStateRecord {}
-
-pub async fn db_record_to_change_record(
+/// Converts a db row into a `RecordChange`.
+///
+/// Rows for records already in `cache` are applied in place.
+/// Anything else is taken from `fetched`, and is treated as deleted if it isn't there.
+pub fn db_record_to_change_record(
     (msg_type, record): (MessageType, DbRecord),
-    client: Client,
-) -> Result<RecordChange, ImlManagerClientError> {
+    cache: &Cache,
+    fetched: &Cache,
+) -> RecordChange {
     match record {
-        DbRecord::ManagedHost(x) => {
-            converter(client, msg_type, x, Record::Host, RecordId::Host).await
-        }
-        DbRecord::ManagedFilesystem(x) => {
-            converter(
-                client,
-                msg_type,
-                x,
-                Record::Filesystem,
-                RecordId::Filesystem,
-            )
-            .await
-        }
-        DbRecord::ManagedTarget(x) => {
-            converter(client, msg_type, x, Record::Target, RecordId::Target).await
-        }
+        DbRecord::ManagedHost(x) => converter(
+            cache,
+            fetched,
+            msg_type,
+            x,
+            |c| &c.host,
+            Record::Host,
+            RecordId::Host,
+        ),
+        DbRecord::ManagedFilesystem(x) => converter(
+            cache,
+            fetched,
+            msg_type,
+            x,
+            |c| &c.filesystem,
+            Record::Filesystem,
+            RecordId::Filesystem,
+        ),
+        DbRecord::ManagedTarget(x) => converter(
+            cache,
+            fetched,
+            msg_type,
+            x,
+            |c| &c.target,
+            Record::Target,
+            RecordId::Target,
+        ),
         DbRecord::AlertState(x) => match (msg_type, &x) {
-            (MessageType::Delete, x) => Ok(RecordChange::Delete(RecordId::ActiveAlert(x.id()))),
-            (_, x) if !x.is_active() => Ok(RecordChange::Delete(RecordId::ActiveAlert(x.id()))),
+            (MessageType::Delete, x) => RecordChange::Delete(RecordId::ActiveAlert(x.id())),
+            (_, x) if !x.is_active() => RecordChange::Delete(RecordId::ActiveAlert(x.id())),
             (MessageType::Insert, x) | (MessageType::Update, x) => {
-                ToApiRecord::to_api_record(x, client)
-                    .map_ok(Record::ActiveAlert)
-                    .map_ok(RecordChange::Update)
-                    .await
+                from_cache(x, cache, |c| &c.active_alert)
+                    .or_else(|| fetched.active_alert.get(&x.id()).cloned())
+                    .map(|r| RecordChange::Update(Record::ActiveAlert(r)))
+                    .unwrap_or_else(|| RecordChange::Delete(RecordId::ActiveAlert(x.id())))
             }
         },
         DbRecord::OstPool(x) => match (msg_type, x) {
-            (MessageType::Delete, x) => Ok(RecordChange::Delete(RecordId::OstPool(x.id()))),
-            (_, ref x) if x.deleted() => Ok(RecordChange::Delete(RecordId::OstPool(x.id()))),
+            (MessageType::Delete, x) => RecordChange::Delete(RecordId::OstPool(x.id())),
+            (_, ref x) if x.deleted() => RecordChange::Delete(RecordId::OstPool(x.id())),
             (MessageType::Insert, x) | (MessageType::Update, x) => {
-                Ok(RecordChange::Update(Record::OstPool(x)))
+                RecordChange::Update(Record::OstPool(x))
             }
         },
         DbRecord::OstPoolOsts(x) => match (msg_type, x) {
-            (MessageType::Delete, x) => Ok(RecordChange::Delete(RecordId::OstPoolOsts(x.id()))),
+            (MessageType::Delete, x) => RecordChange::Delete(RecordId::OstPoolOsts(x.id())),
             (MessageType::Insert, x) | (MessageType::Update, x) => {
-                Ok(RecordChange::Update(Record::OstPoolOsts(x)))
+                RecordChange::Update(Record::OstPoolOsts(x))
             }
         },
         DbRecord::StratagemConfiguration(x) => match (msg_type, x) {
-            (MessageType::Delete, x) => Ok(RecordChange::Delete(RecordId::StratagemConfig(x.id()))),
-            (_, ref x) if x.deleted() => {
-                Ok(RecordChange::Delete(RecordId::StratagemConfig(x.id())))
-            }
+            (MessageType::Delete, x) => RecordChange::Delete(RecordId::StratagemConfig(x.id())),
+            (_, ref x) if x.deleted() => RecordChange::Delete(RecordId::StratagemConfig(x.id())),
             (MessageType::Insert, x) | (MessageType::Update, x) => {
-                Ok(RecordChange::Update(Record::StratagemConfig(x)))
+                RecordChange::Update(Record::StratagemConfig(x))
             }
         },
         DbRecord::LnetConfiguration(x) => match (msg_type, x) {
-            (MessageType::Delete, x) => {
-                Ok(RecordChange::Delete(RecordId::LnetConfiguration(x.id())))
+            (MessageType::Delete, x) => RecordChange::Delete(RecordId::LnetConfiguration(x.id())),
+            (_, ref x) if x.deleted() => RecordChange::Delete(RecordId::LnetConfiguration(x.id())),
+            (MessageType::Insert, x) | (MessageType::Update, x) => {
+                RecordChange::Update(Record::LnetConfiguration(x))
             }
-            (_, ref x) if x.deleted() => {
-                Ok(RecordChange::Delete(RecordId::LnetConfiguration(x.id())))
+        },
+        DbRecord::ManagedTargetMount(x) => match (msg_type, x) {
+            (MessageType::Delete, x) => RecordChange::Delete(RecordId::ManagedTargetMount(x.id())),
+            (_, ref x) if x.deleted() => RecordChange::Delete(RecordId::ManagedTargetMount(x.id())),
+            (MessageType::Insert, x) | (MessageType::Update, x) => {
+                RecordChange::Update(Record::ManagedTargetMount(x))
             }
+        },
+        DbRecord::Volume(x) => converter(
+            cache,
+            fetched,
+            msg_type,
+            x,
+            |c| &c.volume,
+            Record::Volume,
+            RecordId::Volume,
+        ),
+        DbRecord::Device(x) => match (msg_type, x) {
+            (MessageType::Delete, x) => RecordChange::Delete(RecordId::Device(x.id)),
             (MessageType::Insert, x) | (MessageType::Update, x) => {
-                Ok(RecordChange::Update(Record::LnetConfiguration(x)))
+                RecordChange::Update(Record::Device(x))
             }
         },
-        DbRecord::ManagedTargetMount(x) => match (msg_type, x) {
+        DbRecord::DeviceHost(x) => match (msg_type, x) {
             (MessageType::Delete, x) => {
-                Ok(RecordChange::Delete(RecordId::ManagedTargetMount(x.id())))
-            }
-            (_, ref x) if x.deleted() => {
-                Ok(RecordChange::Delete(RecordId::ManagedTargetMount(x.id())))
+                RecordChange::Delete(RecordId::DeviceHost(x.device_id, x.fqdn))
             }
             (MessageType::Insert, x) | (MessageType::Update, x) => {
-                Ok(RecordChange::Update(Record::ManagedTargetMount(x)))
+                RecordChange::Update(Record::DeviceHost(x))
             }
         },
-        DbRecord::Volume(x) => {
-            converter(client, msg_type, x, Record::Volume, RecordId::Volume).await
-        }
         DbRecord::VolumeNode(x) => match (msg_type, x) {
-            (MessageType::Delete, x) => Ok(RecordChange::Delete(RecordId::VolumeNode(x.id()))),
-            (_, ref x) if x.deleted() => Ok(RecordChange::Delete(RecordId::VolumeNode(x.id()))),
+            (MessageType::Delete, x) => RecordChange::Delete(RecordId::VolumeNode(x.id())),
+            (_, ref x) if x.deleted() => RecordChange::Delete(RecordId::VolumeNode(x.id())),
             (MessageType::Insert, x) | (MessageType::Update, x) => {
-                Ok(RecordChange::Update(Record::VolumeNode(x)))
+                RecordChange::Update(Record::VolumeNode(x))
             }
         },
     }
@@ -264,6 +389,8 @@ pub async fn populate_from_db(
             "select * from {}",
             OstPoolOstsRecord::table_name()
         )),
+        client.prepare(&format!("select * from {}", Device::table_name())),
+        client.prepare(&format!("select * from {}", DeviceHost::table_name())),
     ])
     .await?;
 
@@ -275,12 +402,35 @@ pub async fn populate_from_db(
         into_row(client.query_raw(&stmts[4], iter::empty()).await?),
     );
 
+    let device_fut = client
+        .query_raw(&stmts[6], iter::empty())
+        .await?
+        .map_ok(Device::from)
+        .map_ok(|x| (x.id.clone(), x))
+        .try_collect::<HashMap<_, _>>();
+
+    let device_host_fut = client
+        .query_raw(&stmts[7], iter::empty())
+        .await?
+        .map_ok(DeviceHost::from)
+        .try_fold(HashMap::new(), |mut hm: HashMap<_, HashMap<_, _>>, x| {
+            hm.entry(x.fqdn.clone())
+                .or_insert_with(HashMap::new)
+                .insert(x.device_id.clone(), x);
+
+            future::ok(hm)
+        });
+
     let (
         (managed_target_mount, stratagem_configuration, lnet_configuration, volume_node, ost_pool),
         ost_pool_osts,
-    ) = future::try_join(
+        device,
+        device_host,
+    ) = future::try_join4(
         fut,
         into_row(client.query_raw(&stmts[5], iter::empty()).await?),
+        device_fut,
+        device_host_fut,
     )
     .await?;
 
@@ -292,8 +442,217 @@ pub async fn populate_from_db(
     cache.volume_node = volume_node;
     cache.ost_pool = ost_pool;
     cache.ost_pool_osts = ost_pool_osts;
+    cache.device = device;
+    cache.device_host = device_host;
 
     tracing::debug!("Populated from db");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{db_record_to_change_record, missing_records};
+    use crate::{listen::MessageType, DbRecord};
+    use iml_wire_types::{
+        db::{Device, DeviceHost, DeviceId, DeviceIds, DeviceType, MountPath, Paths, Size},
+        warp_drive::{Cache, Record, RecordChange, RecordId},
+        Fqdn, Volume,
+    };
+    use serde_json::json;
+
+    fn device(id: &str) -> Device {
+        Device {
+            id: id.to_string().into(),
+            size: Size(1024),
+            usable_for_lustre: true,
+            device_type: DeviceType::ScsiDevice,
+            parents: DeviceIds::default(),
+            children: DeviceIds::default(),
+        }
+    }
+
+    fn device_host(id: &str, fqdn: &str) -> DeviceHost {
+        DeviceHost {
+            device_id: id.to_string().into(),
+            fqdn: Fqdn(fqdn.into()),
+            local: true,
+            paths: Paths(vec![format!("/dev/{}", id).into()].into_iter().collect()),
+            mount_path: MountPath(None),
+            fs_type: None,
+            fs_label: None,
+            fs_uuid: None,
+        }
+    }
+
+    fn volume(id: u32, label: &str, storage_resource_id: Option<u32>) -> Volume {
+        serde_json::from_value(json!({
+            "id": id,
+            "kind": "linux-scsi",
+            "label": label,
+            "resource_uri": format!("/api/volume/{}/", id),
+            "storage_resource": storage_resource_id
+                .map(|x| format!("/api/storage_resource/{}/", x)),
+            "usable_for_lustre": true,
+            "volume_nodes": []
+        }))
+        .unwrap()
+    }
+
+    fn volume_row(id: u32, label: &str, storage_resource_id: Option<u32>) -> DbRecord {
+        DbRecord::Volume(
+            serde_json::from_value(json!({
+                "id": id,
+                "storage_resource_id": storage_resource_id,
+                "size": 1024,
+                "label": label,
+                "filesystem_type": "ext4",
+                "not_deleted": true,
+                "usable_for_lustre": false
+            }))
+            .unwrap(),
+        )
+    }
+
+    /// Converts `x` and applies it to the `Cache`, as `handle_db_notifications` does.
+    fn apply(cache: &mut Cache, msg_type: MessageType, x: DbRecord) -> RecordChange {
+        let change = db_record_to_change_record((msg_type, x), cache, &Cache::default());
+
+        match &change {
+            RecordChange::Update(r) => cache.insert_record(r.clone()),
+            RecordChange::Delete(id) => {
+                cache.remove_record(id);
+            }
+        };
+
+        change
+    }
+
+    #[test]
+    fn test_device_cache() {
+        let mut cache = Cache::default();
+        let id: DeviceId = "a".to_string().into();
+
+        apply(
+            &mut cache,
+            MessageType::Insert,
+            DbRecord::Device(device("a")),
+        );
+        apply(
+            &mut cache,
+            MessageType::Insert,
+            DbRecord::Device(device("b")),
+        );
+
+        let updated = Device {
+            size: Size(2048),
+            ..device("a")
+        };
+
+        apply(
+            &mut cache,
+            MessageType::Update,
+            DbRecord::Device(updated.clone()),
+        );
+
+        assert_eq!(cache.device.get(&id), Some(&updated));
+
+        let change = apply(
+            &mut cache,
+            MessageType::Delete,
+            DbRecord::Device(device("a")),
+        );
+
+        match change {
+            RecordChange::Delete(RecordId::Device(x)) => assert_eq!(x, id),
+            x => panic!("Expected a Device delete, got {:?}", x),
+        }
+
+        assert!(!cache.device.contains_key(&id));
+        assert!(cache.device.contains_key(&"b".to_string().into()));
+    }
+
+    #[test]
+    fn test_device_host_cache() {
+        let mut cache = Cache::default();
+        let fqdn = Fqdn("oss1.local".into());
+
+        for id in &["a", "b"] {
+            apply(
+                &mut cache,
+                MessageType::Insert,
+                DbRecord::DeviceHost(device_host(id, "oss1.local")),
+            );
+        }
+
+        assert_eq!(cache.device_host[&fqdn].len(), 2);
+
+        let change = apply(
+            &mut cache,
+            MessageType::Delete,
+            DbRecord::DeviceHost(device_host("a", "oss1.local")),
+        );
+
+        match change {
+            RecordChange::Delete(RecordId::DeviceHost(id, x)) => {
+                assert_eq!((id, x), ("a".to_string().into(), fqdn.clone()))
+            }
+            x => panic!("Expected a DeviceHost delete, got {:?}", x),
+        }
+
+        assert_eq!(
+            cache.device_host[&fqdn].keys().collect::<Vec<_>>(),
+            vec![&DeviceId::from("b".to_string())]
+        );
+
+        apply(
+            &mut cache,
+            MessageType::Delete,
+            DbRecord::DeviceHost(device_host("b", "oss1.local")),
+        );
+
+        // Hosts with no devices left are dropped.
+        assert!(!cache.device_host.contains_key(&fqdn));
+    }
+
+    #[test]
+    fn test_missing_volumes_come_from_fetched() {
+        let mut cache = Cache::default();
+
+        cache.volume.insert(1, volume(1, "sdb", None));
+
+        let xs = vec![
+            (MessageType::Update, volume_row(1, "sdc", None)),
+            (MessageType::Update, volume_row(2, "sdd", Some(3))),
+            (MessageType::Insert, volume_row(3, "sde", None)),
+            (MessageType::Delete, volume_row(4, "sdf", None)),
+        ];
+
+        let missing = missing_records(&xs, &cache);
+
+        assert_eq!(
+            missing,
+            vec![RecordId::Volume(2), RecordId::Volume(3)]
+                .into_iter()
+                .collect()
+        );
+
+        let mut fetched = Cache::default();
+
+        fetched.volume.insert(2, volume(2, "sdd", Some(3)));
+
+        let changes: Vec<_> = xs
+            .into_iter()
+            .map(|x| db_record_to_change_record(x, &cache, &fetched))
+            .collect();
+
+        match &changes[..] {
+            [RecordChange::Update(Record::Volume(a)), RecordChange::Update(Record::Volume(b)), RecordChange::Delete(RecordId::Volume(3)), RecordChange::Delete(RecordId::Volume(4))] =>
+            {
+                assert_eq!((a.id, a.label.as_str()), (1, "sdc"));
+                assert_eq!((b.id, b.label.as_str()), (2, "sdd"));
+            }
+            xs => panic!("Unexpected changes {:?}", xs),
+        }
+    }
+}
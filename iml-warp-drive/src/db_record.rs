@@ -2,14 +2,18 @@
 // Use of this source code is governed by a MIT-style
 // license that can be found in the LICENSE file.
 
-use iml_wire_types::db::{
-    AlertStateRecord, FsRecord, LnetConfigurationRecord, ManagedHostRecord,
-    ManagedTargetMountRecord, ManagedTargetRecord, OstPoolOstsRecord, OstPoolRecord,
-    StratagemConfiguration, TableName, VolumeNodeRecord, VolumeRecord, ALERT_STATE_TABLE_NAME,
-    LNET_CONFIGURATION_TABLE_NAME, MANAGED_FILESYSTEM_TABLE_NAME, MANAGED_HOST_TABLE_NAME,
-    MANAGED_TARGET_MOUNT_TABLE_NAME, MANAGED_TARGET_TABLE_NAME, OSTPOOL_OSTS_TABLE_NAME,
-    OSTPOOL_TABLE_NAME, STRATAGEM_CONFIGURATION_TABLE_NAME, VOLUME_NODE_TABLE_NAME,
-    VOLUME_TABLE_NAME,
+use iml_wire_types::{
+    db::{
+        AlertStateRecord, Device, DeviceHost, FsRecord, Id, LnetConfigurationRecord,
+        ManagedHostRecord, ManagedTargetMountRecord, ManagedTargetRecord, OstPoolOstsRecord,
+        OstPoolRecord, StratagemConfiguration, TableName, VolumeNodeRecord, VolumeRecord,
+        ALERT_STATE_TABLE_NAME, DEVICE_HOST_TABLE_NAME, DEVICE_TABLE_NAME,
+        LNET_CONFIGURATION_TABLE_NAME, MANAGED_FILESYSTEM_TABLE_NAME, MANAGED_HOST_TABLE_NAME,
+        MANAGED_TARGET_MOUNT_TABLE_NAME, MANAGED_TARGET_TABLE_NAME, OSTPOOL_OSTS_TABLE_NAME,
+        OSTPOOL_TABLE_NAME, STRATAGEM_CONFIGURATION_TABLE_NAME, VOLUME_NODE_TABLE_NAME,
+        VOLUME_TABLE_NAME,
+    },
+    warp_drive::RecordId,
 };
 use serde::de::Error;
 use std::convert::TryFrom;
@@ -18,6 +22,8 @@ use std::convert::TryFrom;
 #[derive(Debug)]
 pub enum DbRecord {
     AlertState(AlertStateRecord),
+    Device(Device),
+    DeviceHost(DeviceHost),
     LnetConfiguration(LnetConfigurationRecord),
     ManagedFilesystem(FsRecord),
     ManagedHost(ManagedHostRecord),
@@ -30,6 +36,27 @@ pub enum DbRecord {
     VolumeNode(VolumeNodeRecord),
 }
 
+impl DbRecord {
+    /// Returns the `RecordId` this row maps to in the `Cache`.
+    pub fn record_id(&self) -> RecordId {
+        match self {
+            DbRecord::AlertState(x) => RecordId::ActiveAlert(x.id()),
+            DbRecord::Device(x) => RecordId::Device(x.id.clone()),
+            DbRecord::DeviceHost(x) => RecordId::DeviceHost(x.device_id.clone(), x.fqdn.clone()),
+            DbRecord::LnetConfiguration(x) => RecordId::LnetConfiguration(x.id()),
+            DbRecord::ManagedFilesystem(x) => RecordId::Filesystem(x.id()),
+            DbRecord::ManagedHost(x) => RecordId::Host(x.id()),
+            DbRecord::ManagedTarget(x) => RecordId::Target(x.id()),
+            DbRecord::ManagedTargetMount(x) => RecordId::ManagedTargetMount(x.id()),
+            DbRecord::OstPool(x) => RecordId::OstPool(x.id()),
+            DbRecord::OstPoolOsts(x) => RecordId::OstPoolOsts(x.id()),
+            DbRecord::StratagemConfiguration(x) => RecordId::StratagemConfig(x.id()),
+            DbRecord::Volume(x) => RecordId::Volume(x.id()),
+            DbRecord::VolumeNode(x) => RecordId::VolumeNode(x.id()),
+        }
+    }
+}
+
 impl TryFrom<(TableName<'_>, serde_json::Value)> for DbRecord {
     type Error = serde_json::Error;
 
@@ -56,6 +83,8 @@ impl TryFrom<(TableName<'_>, serde_json::Value)> for DbRecord {
             LNET_CONFIGURATION_TABLE_NAME => {
                 serde_json::from_value(x).map(DbRecord::LnetConfiguration)
             }
+            DEVICE_TABLE_NAME => serde_json::from_value(x).map(DbRecord::Device),
+            DEVICE_HOST_TABLE_NAME => serde_json::from_value(x).map(DbRecord::DeviceHost),
             x => Err(serde_json::Error::custom(format!(
                 "No matching table representation for {}",
                 x
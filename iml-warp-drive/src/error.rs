@@ -14,6 +14,10 @@ pub enum ImlWarpDriveError {
     TokioPostgresError(iml_postgres::Error),
     DbError(DbError),
     SerdeJsonError(serde_json::error::Error),
+    InvalidSubscription(String),
+    Unauthorized(String),
+    Unavailable(String),
+    NotFound(String),
 }
 
 impl reject::Reject for ImlWarpDriveError {}
@@ -26,6 +30,10 @@ impl std::fmt::Display for ImlWarpDriveError {
             ImlWarpDriveError::TokioPostgresError(ref err) => write!(f, "{}", err),
             ImlWarpDriveError::DbError(ref err) => write!(f, "{}", err),
             ImlWarpDriveError::SerdeJsonError(ref err) => write!(f, "{}", err),
+            ImlWarpDriveError::InvalidSubscription(ref err) => write!(f, "{}", err),
+            ImlWarpDriveError::Unauthorized(ref err) => write!(f, "{}", err),
+            ImlWarpDriveError::Unavailable(ref err) => write!(f, "{}", err),
+            ImlWarpDriveError::NotFound(ref err) => write!(f, "{}", err),
         }
     }
 }
@@ -38,6 +46,10 @@ impl std::error::Error for ImlWarpDriveError {
             ImlWarpDriveError::TokioPostgresError(ref err) => Some(err),
             ImlWarpDriveError::DbError(ref err) => Some(err),
             ImlWarpDriveError::SerdeJsonError(ref err) => Some(err),
+            ImlWarpDriveError::InvalidSubscription(_) => None,
+            ImlWarpDriveError::Unauthorized(_) => None,
+            ImlWarpDriveError::Unavailable(_) => None,
+            ImlWarpDriveError::NotFound(_) => None,
         }
     }
 }
@@ -71,3 +83,25 @@ impl From<serde_json::error::Error> for ImlWarpDriveError {
         ImlWarpDriveError::SerdeJsonError(err)
     }
 }
+
+pub async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    match err.find::<ImlWarpDriveError>() {
+        Some(ImlWarpDriveError::InvalidSubscription(x)) => Ok(warp::reply::with_status(
+            x.to_string(),
+            warp::http::StatusCode::BAD_REQUEST,
+        )),
+        Some(ImlWarpDriveError::Unauthorized(x)) => Ok(warp::reply::with_status(
+            x.to_string(),
+            warp::http::StatusCode::UNAUTHORIZED,
+        )),
+        Some(ImlWarpDriveError::Unavailable(x)) => Ok(warp::reply::with_status(
+            x.to_string(),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        )),
+        Some(ImlWarpDriveError::NotFound(x)) => Ok(warp::reply::with_status(
+            x.to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        )),
+        _ => Err(err),
+    }
+}
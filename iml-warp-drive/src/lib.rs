@@ -2,12 +2,18 @@
 // Use of this source code is governed by a MIT-style
 // license that can be found in the LICENSE file.
 
+pub mod auth;
 pub mod cache;
 pub mod db_record;
 pub mod error;
 pub mod listen;
 pub mod locks;
+pub mod outbox;
+pub mod reconnect;
+pub mod replay;
 pub mod request;
+pub mod snapshot;
+pub mod subscription;
 pub mod users;
 
 pub use db_record::*;
@@ -2,13 +2,14 @@
 // Use of this source code is governed by a MIT-style
 // license that can be found in the LICENSE file.
 
-use crate::{cache, db_record, error, users, DbRecord};
+use crate::{cache, db_record, error, replay, users, DbRecord};
 use futures::{Stream, TryStreamExt};
 use iml_wire_types::{
     db::TableName,
-    warp_drive::{Message, RecordChange},
+    warp_drive::{Cache, Message, RecordChange},
 };
-use std::{convert::TryFrom, sync::Arc};
+use std::{collections::HashMap, convert::TryFrom, sync::Arc, time::Duration};
+use tokio::time::{self, Instant};
 
 #[derive(serde::Deserialize, Debug)]
 #[serde(rename_all = "UPPERCASE")]
@@ -27,6 +28,87 @@ fn into_db_record(s: &str) -> serde_json::error::Result<(MessageType, DbRecord)>
     Ok((msg_type, r))
 }
 
+/// How long to wait for more notifications before processing a batch.
+pub const BATCH_WINDOW: Duration = Duration::from_millis(50);
+
+/// The most notifications processed in a single batch.
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+/// Keeps only the last notification for each record,
+/// ordered by when that notification arrived.
+fn coalesce(xs: Vec<(MessageType, DbRecord)>) -> Vec<(MessageType, DbRecord)> {
+    let mut last = HashMap::new();
+
+    for (idx, (_, x)) in xs.iter().enumerate() {
+        last.insert(x.record_id(), idx);
+    }
+
+    xs.into_iter()
+        .enumerate()
+        .filter(|(idx, (_, x))| last.get(&x.record_id()) == Some(idx))
+        .map(|(_, x)| x)
+        .collect()
+}
+
+/// Reads the next batch of `table_update` notifications.
+///
+/// Waits for a first notification, then keeps reading until `BATCH_WINDOW`
+/// has passed or the batch is full. Returns `None` once the stream ends.
+async fn next_batch(
+    stream: &mut (impl Stream<Item = Result<iml_postgres::AsyncMessage, iml_postgres::Error>>
+              + std::marker::Unpin),
+) -> Result<Option<Vec<(MessageType, DbRecord)>>, error::ImlWarpDriveError> {
+    let mut xs = vec![];
+
+    let mut msg = match stream.try_next().await? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let deadline = Instant::now() + BATCH_WINDOW;
+
+    loop {
+        match msg {
+            iml_postgres::AsyncMessage::Notification(n) => {
+                if n.channel() == "table_update" {
+                    // A bad payload only affects its own row,
+                    // so skip it rather than dropping the connection.
+                    match into_db_record(n.payload()) {
+                        Ok(x) => xs.push(x),
+                        Err(e) => tracing::error!(
+                            "Could not parse LISTEN / NOTIFY payload, skipping: {}: {}",
+                            e,
+                            n.payload()
+                        ),
+                    }
+                } else {
+                    tracing::warn!("unknown channel: {}", n.channel());
+                }
+            }
+            iml_postgres::AsyncMessage::Notice(err) => {
+                tracing::error!("Error from postgres {}", err);
+
+                return Err(error::ImlWarpDriveError::from(err));
+            }
+            _ => unreachable!(),
+        };
+
+        if xs.len() >= MAX_BATCH_SIZE {
+            break;
+        }
+
+        msg = match time::timeout_at(deadline, stream.try_next()).await {
+            Ok(x) => match x? {
+                Some(x) => x,
+                None => break,
+            },
+            Err(_) => break,
+        };
+    }
+
+    Ok(Some(xs))
+}
+
 pub async fn handle_db_notifications(
     mut stream: impl Stream<Item = Result<iml_postgres::AsyncMessage, iml_postgres::Error>>
         + std::marker::Unpin,
@@ -34,62 +116,231 @@ pub async fn handle_db_notifications(
     api_client: iml_manager_client::Client,
     api_cache_state: cache::SharedCache,
     user_state4: users::SharedUsers,
+    replay_log: replay::SharedReplayLog,
 ) -> Result<(), error::ImlWarpDriveError> {
     // Keep the client alive within the spawned future so the LISTEN/NOTIFY stream is not dropped
     let _keep_alive = &client;
 
-    while let Some(msg) = stream.try_next().await? {
-        let api_cache_state = Arc::clone(&api_cache_state);
-        let user_state4 = Arc::clone(&user_state4);
+    while let Some(xs) = next_batch(&mut stream).await? {
+        let xs = coalesce(xs);
 
-        match msg {
-            iml_postgres::AsyncMessage::Notification(n) => {
-                if n.channel() == "table_update" {
-                    let r = into_db_record(n.payload())?;
-
-                    let record_change =
-                        cache::db_record_to_change_record(r, api_client.clone()).await?;
-
-                    match record_change.clone() {
-                        RecordChange::Delete(r) => {
-                            tracing::debug!("LISTEN / NOTIFY Delete record: {:?}", r);
-
-                            let removed = api_cache_state.lock().await.remove_record(&r);
-
-                            if removed {
-                                users::send_message(
-                                    Message::RecordChange(record_change),
-                                    Arc::clone(&user_state4),
-                                )
-                                .await;
-                            }
-                        }
-                        RecordChange::Update(r) => {
-                            tracing::debug!("LISTEN / NOTIFY Update record: {:?}", r);
-
-                            api_cache_state.lock().await.insert_record(r);
-
-                            users::send_message(
-                                Message::RecordChange(record_change),
-                                Arc::clone(&user_state4),
-                            )
-                            .await;
-                        }
-                    };
-                } else {
-                    tracing::warn!("unknown channel: {}", n.channel());
-                }
+        tracing::debug!("LISTEN / NOTIFY batch of {} records", xs.len());
+
+        let missing = {
+            let cache = api_cache_state.lock().await;
+
+            cache::missing_records(&xs, &cache)
+        };
+
+        // Records that can't be built from the `Cache` are fetched together,
+        // with one API call per kind of record.
+        // If that fails, their rows are skipped so they can't hold back the rest of the batch.
+        let (xs, fetched) = match cache::fetch_missing(&missing, api_client.clone()).await {
+            Ok(fetched) => (xs, fetched),
+            Err(e) => {
+                tracing::error!(
+                    "Could not fetch {} LISTEN / NOTIFY records from the API, skipping them: {}",
+                    missing.len(),
+                    e
+                );
 
-                Ok(())
+                let xs = xs
+                    .into_iter()
+                    .filter(|(_, x)| !missing.contains(&x.record_id()))
+                    .collect();
+
+                (xs, Cache::default())
             }
-            iml_postgres::AsyncMessage::Notice(err) => {
-                tracing::error!("Error from postgres {}", err);
+        };
+
+        let mut cache = api_cache_state.lock().await;
+
+        // Skip deletes for records we never had.
+        let record_changes: Vec<RecordChange> = xs
+            .into_iter()
+            .map(|x| cache::db_record_to_change_record(x, &cache, &fetched))
+            .filter(|x| match x {
+                RecordChange::Delete(r) => cache.contains_record(r),
+                RecordChange::Update(_) => true,
+            })
+            .collect();
 
-                Err(error::ImlWarpDriveError::from(err))
+        for x in &record_changes {
+            if let RecordChange::Update(r) = x {
+                tracing::debug!("LISTEN / NOTIFY Update record: {:?}", r);
+
+                cache.insert_record(r.clone());
             }
-            _ => unreachable!(),
-        }?;
+        }
+
+        let msg = match record_changes.as_slice() {
+            [] => continue,
+            [x] => Message::RecordChange(x.clone()),
+            _ => Message::RecordChanges(record_changes.clone()),
+        };
+
+        // Route the batch before applying deletes,
+        // so subscriptions can still resolve the deleted records.
+        users::send_message(
+            msg,
+            &cache,
+            Arc::clone(&user_state4),
+            Arc::clone(&replay_log),
+        )
+        .await;
+
+        for x in record_changes {
+            if let RecordChange::Delete(r) = x {
+                tracing::debug!("LISTEN / NOTIFY Delete record: {:?}", r);
+
+                cache.remove_record(&r);
+            }
+        }
     }
 
     Ok(())
 }
+
+/// Repopulates the `Cache` from the API and the db,
+/// then sends every user a fresh snapshot.
+///
+/// Notifications sent while disconnected are lost,
+/// so this is called each time the LISTEN / NOTIFY connection is (re)established.
+pub async fn resync(
+    client: &iml_postgres::SharedClient,
+    api_cache_state: cache::SharedCache,
+    user_state: users::SharedUsers,
+    replay_log: replay::SharedReplayLog,
+) -> Result<(), error::ImlWarpDriveError> {
+    cache::populate_from_api(Arc::clone(&api_cache_state)).await?;
+
+    {
+        let mut c = client.lock().await;
+
+        cache::populate_from_db(Arc::clone(&api_cache_state), &mut c).await?;
+    }
+
+    let cache = api_cache_state.lock().await;
+
+    users::send_message(
+        Message::Records(cache.clone()),
+        &cache,
+        user_state,
+        replay_log,
+    )
+    .await;
+
+    tracing::info!("Resynced cache");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{coalesce, into_db_record, MessageType};
+    use crate::DbRecord;
+    use iml_wire_types::{
+        db::{Device, DeviceHost, DeviceIds, DeviceType, MountPath, Paths, Size},
+        Fqdn,
+    };
+
+    fn ost_pool(id: u32, name: &str) -> String {
+        format!(
+            r#"["UPDATE", "chroma_core_ostpool", {{"id": {}, "name": "{}", "filesystem_id": 1, "not_deleted": true, "content_type_id": null}}]"#,
+            id, name
+        )
+    }
+
+    #[test]
+    fn test_coalesce_keeps_last_per_record() {
+        let xs = vec![ost_pool(1, "a"), ost_pool(2, "b"), ost_pool(1, "c")]
+            .iter()
+            .map(|x| into_db_record(x).unwrap())
+            .collect();
+
+        let xs: Vec<_> = coalesce(xs)
+            .into_iter()
+            .map(|(_, x)| match x {
+                DbRecord::OstPool(x) => (x.id, x.name),
+                x => panic!("Unexpected record {:?}", x),
+            })
+            .collect();
+
+        assert_eq!(xs, vec![(2, "b".into()), (1, "c".into())]);
+    }
+
+    #[test]
+    fn test_device_row() {
+        let (msg_type, x) = into_db_record(
+            r#"["INSERT", "chroma_core_device", {"id": "a", "size": "12345678901234567890", "usable_for_lustre": false, "device_type": "mpath", "parents": ["b", "c"], "children": []}]"#,
+        )
+        .unwrap();
+
+        match msg_type {
+            MessageType::Insert => {}
+            x => panic!("Expected Insert, got {:?}", x),
+        }
+
+        match x {
+            DbRecord::Device(x) => assert_eq!(
+                x,
+                Device {
+                    id: "a".to_string().into(),
+                    size: Size(12_345_678_901_234_567_890),
+                    usable_for_lustre: false,
+                    device_type: DeviceType::Mpath,
+                    parents: DeviceIds(
+                        vec!["b".to_string().into(), "c".to_string().into()]
+                            .into_iter()
+                            .collect()
+                    ),
+                    children: DeviceIds::default(),
+                }
+            ),
+            x => panic!("Unexpected record {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_device_host_row() {
+        let (_, x) = into_db_record(
+            r#"["DELETE", "chroma_core_devicehost", {"id": 7, "device_id": "a", "fqdn": "oss1.local", "local": true, "paths": ["/dev/mapper/a", "/dev/sda"], "mount_path": null, "fs_type": "ext4", "fs_label": null, "fs_uuid": null}]"#,
+        )
+        .unwrap();
+
+        match x {
+            DbRecord::DeviceHost(x) => assert_eq!(
+                x,
+                DeviceHost {
+                    device_id: "a".to_string().into(),
+                    fqdn: Fqdn("oss1.local".into()),
+                    local: true,
+                    paths: Paths(
+                        vec!["/dev/mapper/a".into(), "/dev/sda".into()]
+                            .into_iter()
+                            .collect()
+                    ),
+                    mount_path: MountPath(None),
+                    fs_type: Some("ext4".into()),
+                    fs_label: None,
+                    fs_uuid: None,
+                }
+            ),
+            x => panic!("Unexpected record {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_size() {
+        let x: Size = serde_json::from_str(r#""18446744073709551615""#).unwrap();
+
+        assert_eq!(x, Size(std::u64::MAX));
+
+        let x: Size = serde_json::from_str("1024").unwrap();
+
+        assert_eq!(x, Size(1024));
+
+        assert!(serde_json::from_str::<Size>(r#""-1""#).is_err());
+        assert!(serde_json::from_str::<Size>(r#""1 GiB""#).is_err());
+    }
+}
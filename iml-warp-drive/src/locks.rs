@@ -2,15 +2,20 @@
 // Use of this source code is governed by a MIT-style
 // license that can be found in the LICENSE file.
 
-use crate::request::Request;
-use futures::Stream as Stream03;
+use crate::{
+    cache::SharedCache, error::ImlWarpDriveError, replay::SharedReplayLog, request::Request, users,
+};
+use futures::{lock::Mutex, Stream as Stream03, TryStreamExt};
 use iml_rabbit::{
     basic_consume, basic_publish, bind_queue, create_channel, declare_transient_exchange,
     declare_transient_queue, message::Delivery, purge_queue, BasicConsumeOptions, Channel, Client,
     ExchangeKind, ImlRabbitError, Queue,
 };
-use iml_wire_types::{LockAction, LockChange, ToCompositeId};
-use std::collections::{HashMap, HashSet};
+use iml_wire_types::{warp_drive::Message, LockAction, LockChange, ToCompositeId};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 /// Declares the exchange for rpc comms
 async fn declare_rpc_exchange(c: Channel) -> Result<Channel, ImlRabbitError> {
@@ -61,6 +66,61 @@ pub async fn create_locks_consumer(
     Ok(consumer)
 }
 
+/// Applies each change read from the locks queue to `lock_state`,
+/// and sends it on to users.
+///
+/// Returns once the consumer stream ends, or on the first error.
+pub async fn handle_lock_changes(
+    mut stream: impl Stream03<Item = Result<Delivery, ImlRabbitError>> + std::marker::Unpin,
+    lock_state: SharedLocks,
+    api_cache_state: SharedCache,
+    user_state: users::SharedUsers,
+    replay_log: SharedReplayLog,
+) -> Result<(), ImlWarpDriveError> {
+    while let Some(message) = stream.try_next().await? {
+        tracing::debug!("got message {:?}", std::str::from_utf8(&message.data));
+
+        let lock_change: Changes = serde_json::from_slice(&message.data)?;
+
+        tracing::debug!("decoded message: {:?}", lock_change);
+
+        match lock_change {
+            Changes::Locks(l) => {
+                let data = {
+                    let mut hm = lock_state.lock().await;
+                    hm.clear();
+                    hm.extend(l.result);
+                    hm.clone()
+                };
+
+                users::send_message(
+                    Message::Locks(data),
+                    &*api_cache_state.lock().await,
+                    Arc::clone(&user_state),
+                    Arc::clone(&replay_log),
+                )
+                .await;
+            }
+            Changes::LockChange(l) => {
+                {
+                    let mut lock = lock_state.lock().await;
+                    update_locks(&mut lock, l.clone());
+                }
+
+                users::send_message(
+                    Message::LockChange(l),
+                    &*api_cache_state.lock().await,
+                    Arc::clone(&user_state),
+                    Arc::clone(&replay_log),
+                )
+                .await;
+            }
+        };
+    }
+
+    Ok(())
+}
+
 /// Need to wrap `LockChange` with this, because it's how
 /// the RPC layer in IML returns RPC calls.
 #[derive(serde::Deserialize, serde::Serialize, Debug, Eq, PartialEq)]
@@ -83,6 +143,8 @@ pub enum Changes {
 /// The current state of locks based on data from the locks queue
 pub type Locks = HashMap<String, HashSet<LockChange>>;
 
+pub type SharedLocks = Arc<Mutex<Locks>>;
+
 /// Add a new lock to `Locks`
 pub fn add_lock(locks: &mut Locks, lock_change: LockChange) {
     locks
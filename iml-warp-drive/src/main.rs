@@ -2,22 +2,30 @@
 // Use of this source code is governed by a MIT-style
 // license that can be found in the LICENSE file.
 
-use futures::{lock::Mutex, FutureExt, TryFutureExt, TryStreamExt};
+use futures::{future, lock::Mutex, FutureExt, TryFutureExt};
 use iml_manager_client::get_client;
 use iml_manager_env;
 use iml_warp_drive::{
-    cache::{populate_from_api, populate_from_db, SharedCache},
+    auth::{self, Session, SharedDbClient},
+    cache::SharedCache,
     error, listen,
-    locks::{self, create_locks_consumer, Locks},
+    locks::{self, create_locks_consumer, SharedLocks},
+    reconnect::Reconnect,
+    replay::{ReplayLog, SharedReplayLog},
+    snapshot::{self, Snapshot},
+    subscription::{Subscription, SubscriptionQuery},
     users,
 };
-use iml_wire_types::warp_drive::{Cache, Message};
-use std::{collections::HashMap, sync::Arc};
+use iml_wire_types::warp_drive::Cache;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tracing_subscriber::{fmt::Subscriber, EnvFilter};
 use warp::Filter;
 
-type SharedLocks = Arc<Mutex<Locks>>;
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let subscriber = Subscriber::builder()
@@ -35,126 +43,305 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let api_cache_state: SharedCache = Arc::new(Mutex::new(Cache::default()));
 
+    let replay_log_state: SharedReplayLog = Arc::new(Mutex::new(ReplayLog::default()));
+
+    let db_client_state: SharedDbClient = Arc::new(Mutex::new(None));
+
     // Clone here to allow SSE route to get a ref.
     let user_state2 = Arc::clone(&user_state);
     let lock_state2 = Arc::clone(&lock_state);
     let api_cache_state2 = Arc::clone(&api_cache_state);
+    let replay_log_state2 = Arc::clone(&replay_log_state);
+    let db_client_state2 = Arc::clone(&db_client_state);
 
-    // Handle an error in locks by shutting down
+    // Clone here to allow snapshot routes to get a ref.
+    let lock_state3 = Arc::clone(&lock_state);
+    let api_cache_state5 = Arc::clone(&api_cache_state);
+    let api_cache_state6 = Arc::clone(&api_cache_state);
+    let replay_log_state4 = Arc::clone(&replay_log_state);
+    let replay_log_state5 = Arc::clone(&replay_log_state);
+
+    // Shut down once LISTEN / NOTIFY or the locks queue can't be reconnected
     let (exit, valve) = tokio_runtime_shutdown::shared_shutdown();
 
     let user_state3 = Arc::clone(&user_state);
+    let user_state5 = Arc::clone(&user_state);
 
     let api_cache_state3 = Arc::clone(&api_cache_state);
+    let api_cache_state4 = Arc::clone(&api_cache_state);
 
     let api_client = get_client()?;
 
     tracing::info!("IML warp drive starting");
 
-    populate_from_api(Arc::clone(&api_cache_state)).await?;
+    // Keep the cache current from LISTEN / NOTIFY, reconnecting and resyncing on failure.
+    // Only give up once the connection can't be re-established.
+    let user_state4 = Arc::clone(&user_state);
+    let replay_log_state3 = Arc::clone(&replay_log_state);
+    let valve2 = valve.clone();
 
-    let (db_client, conn) = iml_postgres::connect().await?;
-    let shared_client = iml_postgres::shared_client(db_client);
+    let db_listener = async move {
+        let mut reconnect = Reconnect::default();
 
-    let c2 = Arc::clone(&shared_client);
+        loop {
+            let started = Instant::now();
 
-    let notify_stream = iml_postgres::NotifyStream(conn);
+            let r = async {
+                let (db_client, conn) = iml_postgres::connect().await?;
 
-    let notify_stream = valve.wrap(notify_stream);
+                let shared_client = iml_postgres::shared_client(db_client);
 
-    let notify_stream = exit.wrap_fut(listen::handle_db_notifications(
-        notify_stream,
-        Arc::clone(&c2),
-        api_client,
-        api_cache_state,
-        Arc::clone(&user_state),
-    ));
+                // Share the connection for auth lookups.
+                *db_client_state.lock().await = Some(Arc::clone(&shared_client));
 
-    tokio::spawn(
-        notify_stream.map(|r: Result<(), error::ImlWarpDriveError>| match r {
-            Ok(_) => tracing::info!("LISTEN / NOTIFY loop exited"),
-            Err(e) => tracing::error!("Unhandled error {}", e),
-        }),
-    );
-
-    {
-        let c = shared_client.lock().await;
+                let notify_stream = valve2.wrap(iml_postgres::NotifyStream(conn));
 
-        c.simple_query("LISTEN table_update").await?;
-    }
+                future::try_join(
+                    listen::handle_db_notifications(
+                        notify_stream,
+                        Arc::clone(&shared_client),
+                        api_client.clone(),
+                        Arc::clone(&api_cache_state),
+                        Arc::clone(&user_state4),
+                        Arc::clone(&replay_log_state3),
+                    ),
+                    async {
+                        {
+                            let c = shared_client.lock().await;
 
-    tracing::info!("Started listening to NOTIFY events");
+                            c.simple_query("LISTEN table_update").await?;
+                        }
 
-    {
-        let mut c = shared_client.lock().await;
+                        tracing::info!("Started listening to NOTIFY events");
+
+                        listen::resync(
+                            &shared_client,
+                            Arc::clone(&api_cache_state3),
+                            Arc::clone(&user_state4),
+                            Arc::clone(&replay_log_state3),
+                        )
+                        .await
+                    },
+                )
+                .await
+                .map(drop)
+            }
+            .await;
 
-        populate_from_db(Arc::clone(&api_cache_state3), &mut c).await?;
-    }
+            db_client_state.lock().await.take();
 
-    let rabbit_client = iml_rabbit::connect_to_rabbit().await?;
+            // The stream also ends on shutdown, only reconnect if that's not the case.
+            let shutting_down = tokio_runtime_shutdown::when_finished(&valve2)
+                .now_or_never()
+                .is_some();
 
-    let locks_consumer_stream = create_locks_consumer(rabbit_client).await?;
+            match &r {
+                Ok(_) if shutting_down => return Ok::<_, error::ImlWarpDriveError>(()),
+                Ok(_) => tracing::warn!("LISTEN / NOTIFY connection closed, reconnecting"),
+                Err(e) => tracing::error!("LISTEN / NOTIFY failed, reconnecting: {}", e),
+            }
 
-    let mut s = valve.wrap(locks_consumer_stream);
+            if !reconnect.wait(started).await {
+                tracing::error!("Could not keep LISTEN / NOTIFY connected, giving up");
 
-    tokio::spawn(
-        async move {
-            while let Some(message) = s.try_next().map_err(error::ImlWarpDriveError::from).await? {
-                tracing::debug!("got message {:?}", std::str::from_utf8(&message.data));
+                return r;
+            }
+        }
+    };
 
-                let lock_change: locks::Changes = serde_json::from_slice(&message.data)?;
+    tokio::spawn(exit.wrap_fut(db_listener).map(
+        |r: Result<(), error::ImlWarpDriveError>| match r {
+            Ok(_) => tracing::info!("LISTEN / NOTIFY loop exited"),
+            Err(e) => tracing::error!("Unhandled error {}", e),
+        },
+    ));
 
-                tracing::debug!("decoded message: {:?}", lock_change);
+    // Keep locks current from the locks queue, reconnecting on failure.
+    // A new consumer asks for all locks up front, so users are sent a fresh `Message::Locks`.
+    let user_state6 = Arc::clone(&user_state);
+    let valve3 = valve.clone();
+
+    let locks_listener = async move {
+        let mut reconnect = Reconnect::default();
+
+        loop {
+            let started = Instant::now();
+
+            let r = async {
+                let locks_consumer_stream = iml_rabbit::connect_to_rabbit()
+                    .and_then(create_locks_consumer)
+                    .await?;
+
+                locks::handle_lock_changes(
+                    valve3.wrap(locks_consumer_stream),
+                    Arc::clone(&lock_state),
+                    Arc::clone(&api_cache_state4),
+                    Arc::clone(&user_state6),
+                    Arc::clone(&replay_log_state),
+                )
+                .await
+            }
+            .await;
 
-                match lock_change {
-                    locks::Changes::Locks(l) => {
-                        let mut hm = lock_state.lock().await;
-                        hm.clear();
-                        hm.extend(l.result);
+            let shutting_down = tokio_runtime_shutdown::when_finished(&valve3)
+                .now_or_never()
+                .is_some();
 
-                        users::send_message(Message::Locks(hm.clone()), Arc::clone(&user_state))
-                            .await;
-                    }
-                    locks::Changes::LockChange(l) => {
-                        {
-                            let mut lock = lock_state.lock().await;
-                            locks::update_locks(&mut lock, l);
-                        }
+            match &r {
+                Ok(_) if shutting_down => return Ok::<_, error::ImlWarpDriveError>(()),
+                Ok(_) => tracing::warn!("Locks consumer closed, reconnecting"),
+                Err(e) => tracing::error!("Locks consumer failed, reconnecting: {}", e),
+            }
 
-                        let data = {
-                            let locks = lock_state.lock().await;
-                            locks.clone()
-                        };
+            if !reconnect.wait(started).await {
+                tracing::error!("Could not keep the locks consumer connected, giving up");
 
-                        users::send_message(Message::Locks(data), Arc::clone(&user_state)).await;
-                    }
-                };
+                return r;
             }
-
-            Ok(())
         }
-        .map_err(exit.trigger_fn())
-        .map(|r: Result<(), error::ImlWarpDriveError>| match r {
+    };
+
+    tokio::spawn(exit.wrap_fut(locks_listener).map(
+        |r: Result<(), error::ImlWarpDriveError>| match r {
             Ok(_) => tracing::info!("Rabbit client stream exited"),
             Err(e) => tracing::error!("Unhandled error {}", e),
-        }),
-    );
+        },
+    ));
+
+    // Periodically report how many users are connected, and how many are falling behind.
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+        loop {
+            interval.tick().await;
+
+            let stats = users::stats(&user_state5).await;
+
+            tracing::info!(
+                "{} users connected, {} lagging",
+                stats.connected,
+                stats.lagging
+            );
+        }
+    });
+
+    // Users must be logged in, see `iml_warp_drive::auth`.
+    let authenticated = warp::any()
+        .map(move || Arc::clone(&db_client_state2))
+        .and(warp::cookie::optional(auth::SESSION_COOKIE_NAME))
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(
+            |db_client: SharedDbClient,
+             session_key: Option<String>,
+             authorization: Option<String>| {
+                auth::authenticate(db_client, session_key, authorization)
+                    .map_err(warp::reject::custom)
+            },
+        );
+
+    // GET /messaging/cache -> JSON snapshot of the cache, see `iml_warp_drive::snapshot`.
+    let cache_route = warp::get()
+        .and(warp::path("messaging"))
+        .and(warp::path("cache"))
+        .and(warp::path::tail())
+        .and(authenticated.clone())
+        .and(warp::any().map(move || Arc::clone(&api_cache_state5)))
+        .and(warp::any().map(move || Arc::clone(&replay_log_state4)))
+        .and(warp::query::<SubscriptionQuery>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and_then(
+            |tail: warp::path::Tail,
+             session: Session,
+             api_cache: SharedCache,
+             replay_log: SharedReplayLog,
+             query: SubscriptionQuery,
+             if_none_match: Option<String>| {
+                async move {
+                    let snapshot: Snapshot = tail.as_str().parse()?;
+
+                    let subscription = Subscription::try_from(query)?;
+
+                    snapshot::cache_reply(
+                        snapshot,
+                        subscription,
+                        session,
+                        api_cache,
+                        replay_log,
+                        if_none_match,
+                    )
+                    .await
+                }
+                .map_err(warp::reject::custom)
+            },
+        );
+
+    // GET /messaging/locks -> JSON snapshot of the locks
+    let locks_route = warp::get()
+        .and(warp::path("messaging"))
+        .and(warp::path("locks"))
+        .and(warp::path::end())
+        .and(authenticated.clone())
+        .and(warp::any().map(move || Arc::clone(&lock_state3)))
+        .and(warp::any().map(move || Arc::clone(&api_cache_state6)))
+        .and(warp::any().map(move || Arc::clone(&replay_log_state5)))
+        .and(warp::query::<SubscriptionQuery>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and_then(
+            |session: Session,
+             locks: SharedLocks,
+             api_cache: SharedCache,
+             replay_log: SharedReplayLog,
+             query: SubscriptionQuery,
+             if_none_match: Option<String>| {
+                async move {
+                    let subscription = Subscription::try_from(query)?;
+
+                    snapshot::locks_reply(
+                        subscription,
+                        session,
+                        locks,
+                        api_cache,
+                        replay_log,
+                        if_none_match,
+                    )
+                    .await
+                }
+                .map_err(warp::reject::custom)
+            },
+        );
 
     // GET -> messages stream
-    let routes = warp::get()
+    // Query parameters narrow the stream down, see `iml_warp_drive::subscription`.
+    let stream_route = warp::get()
         .and(warp::any().map(move || Arc::clone(&user_state2)))
         .and(warp::any().map(move || Arc::clone(&lock_state2)))
         .and(warp::any().map(move || Arc::clone(&api_cache_state2)))
+        .and(warp::any().map(move || Arc::clone(&replay_log_state2)))
+        .and(authenticated)
+        .and(warp::query::<SubscriptionQuery>())
+        .and(warp::sse::last_event_id::<u64>())
         .and_then(
-            |users: users::SharedUsers, locks: SharedLocks, api_cache: SharedCache| {
+            |users: users::SharedUsers,
+             locks: SharedLocks,
+             api_cache: SharedCache,
+             replay_log: SharedReplayLog,
+             session: Session,
+             query: SubscriptionQuery,
+             last_event_id: Option<u64>| {
                 tracing::debug!("Inside user route");
 
                 async move {
+                    let subscription = Subscription::try_from(query)?;
+
                     // reply using server-sent events
                     let stream = users::user_connected(
                         users,
-                        locks.lock().await.clone(),
-                        api_cache.lock().await.clone(),
+                        replay_log,
+                        locks,
+                        api_cache,
+                        subscription,
+                        session,
+                        last_event_id,
                     )
                     .await;
 
@@ -164,7 +351,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
                 .map_err(warp::reject::custom)
             },
-        )
+        );
+
+    // The stream matches any path, so snapshot errors are handled
+    // before falling through to it.
+    let routes = cache_route
+        .or(locks_route)
+        .recover(error::handle_rejection)
+        .or(stream_route)
+        .recover(error::handle_rejection)
         .with(warp::log("iml-warp-drive::api"));
 
     let (_, fut) = warp::serve(routes).bind_with_graceful_shutdown(
@@ -0,0 +1,195 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! # Outbox
+//!
+//! A bounded queue of messages waiting to be sent to a single user.
+//!
+//! Pending messages superseded by a newer one are coalesced away.
+//! If a user still falls too far behind, everything pending is dropped
+//! and the user is resynced from a fresh snapshot instead.
+
+use crate::subscription;
+use iml_wire_types::warp_drive::{Message, RecordId};
+use std::{
+    collections::VecDeque,
+    task::{Context, Poll, Waker},
+};
+
+/// The number of messages that may be pending for a user before they are resynced.
+pub const OUTBOX_SIZE: usize = 1024;
+
+/// An item to be sent to a user.
+#[derive(Debug)]
+pub enum Outgoing {
+    Message(u64, Message),
+    /// The user fell behind, and needs a fresh snapshot.
+    Resync,
+}
+
+fn change_id(msg: &Message) -> Option<RecordId> {
+    match msg {
+        Message::RecordChange(x) => Some(subscription::change_id(x)),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct Outbox {
+    capacity: usize,
+    pending: VecDeque<(u64, Message)>,
+    resync: bool,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Self {
+        Outbox {
+            capacity: std::cmp::max(capacity, 1),
+            pending: VecDeque::new(),
+            resync: false,
+            closed: false,
+            waker: None,
+        }
+    }
+    /// Queues a message, dropping any pending messages it supersedes.
+    ///
+    /// Returns `false` if the user had to be marked for a resync.
+    pub fn push(&mut self, event_id: u64, msg: Message) -> bool {
+        match &msg {
+            Message::RecordChange(_) => {
+                let id = change_id(&msg);
+
+                self.pending.retain(|(_, x)| change_id(x) != id);
+            }
+            Message::Locks(_) => self.pending.retain(|(_, x)| match x {
+                Message::Locks(_) | Message::LockChange(_) => false,
+                _ => true,
+            }),
+            Message::Records(_) => self.pending.retain(|(_, x)| match x {
+                Message::Records(_) | Message::RecordChange(_) | Message::RecordChanges(_) => false,
+                _ => true,
+            }),
+            Message::LockChange(_) | Message::RecordChanges(_) => {}
+        };
+
+        let ok = if self.pending.len() < self.capacity {
+            self.pending.push_back((event_id, msg));
+
+            true
+        } else {
+            self.pending.clear();
+            self.resync = true;
+
+            false
+        };
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+
+        ok
+    }
+    /// Is this user at risk of being resynced, or waiting on one?
+    pub fn is_lagging(&self) -> bool {
+        self.resync || self.pending.len() >= self.capacity / 2
+    }
+    /// Ends the stream once everything pending has been sent.
+    pub fn close(&mut self) {
+        self.closed = true;
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Outgoing>> {
+        if self.resync {
+            self.resync = false;
+
+            return Poll::Ready(Some(Outgoing::Resync));
+        }
+
+        if let Some((id, msg)) = self.pending.pop_front() {
+            return Poll::Ready(Some(Outgoing::Message(id, msg)));
+        }
+
+        if self.closed {
+            return Poll::Ready(None);
+        }
+
+        self.waker = Some(cx.waker().clone());
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Outbox, Outgoing};
+    use futures::{
+        stream::{self, StreamExt},
+        FutureExt,
+    };
+    use iml_wire_types::warp_drive::{Message, RecordChange, RecordId};
+    use std::collections::HashMap;
+
+    fn delete(id: u32) -> Message {
+        Message::RecordChange(RecordChange::Delete(RecordId::Host(id)))
+    }
+
+    fn drain(outbox: &mut Outbox) -> Vec<Outgoing> {
+        outbox.close();
+
+        stream::poll_fn(|cx| outbox.poll_next(cx))
+            .collect()
+            .now_or_never()
+            .unwrap()
+    }
+
+    fn ids(xs: Vec<Outgoing>) -> Vec<Option<u64>> {
+        xs.into_iter()
+            .map(|x| match x {
+                Outgoing::Message(id, _) => Some(id),
+                Outgoing::Resync => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_coalesces_record_changes() {
+        let mut outbox = Outbox::new(10);
+
+        outbox.push(1, delete(1));
+        outbox.push(2, delete(2));
+        outbox.push(3, delete(1));
+
+        assert_eq!(ids(drain(&mut outbox)), vec![Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn test_locks_supersede_pending_locks() {
+        let mut outbox = Outbox::new(10);
+
+        outbox.push(1, Message::Locks(HashMap::new()));
+        outbox.push(2, delete(1));
+        outbox.push(3, Message::Locks(HashMap::new()));
+
+        assert_eq!(ids(drain(&mut outbox)), vec![Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn test_overflow_resyncs() {
+        let mut outbox = Outbox::new(2);
+
+        assert!(outbox.push(1, delete(1)));
+        assert!(outbox.push(2, delete(2)));
+        assert!(outbox.is_lagging());
+        assert!(!outbox.push(3, delete(3)));
+        assert!(outbox.push(4, delete(4)));
+
+        assert_eq!(ids(drain(&mut outbox)), vec![None, Some(4)]);
+        assert!(!outbox.is_lagging());
+    }
+}
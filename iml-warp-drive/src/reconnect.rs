@@ -0,0 +1,126 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! # Reconnect
+//!
+//! Backoff used when (re)connecting to Postgres and RabbitMQ.
+
+use iml_request_retry::{
+    policy::{ExponentialBackoffPolicy, ExponentialBackoffPolicyBuilder},
+    RetryAction, RetryPolicy,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+use tokio::time::delay_for;
+
+/// The number of failed connection attempts in a row before giving up.
+pub const MAX_RECONNECT_ATTEMPTS: u32 = 16;
+
+/// How long a connection has to stay up before the backoff starts over.
+pub const HEALTHY_AFTER: Duration = Duration::from_secs(60);
+
+pub type ReconnectPolicy<E> = ExponentialBackoffPolicy<E, StdRng, fn(&E) -> bool>;
+
+/// Builds a fresh policy for a round of connection attempts.
+/// Every error is treated as transient.
+pub fn reconnect_policy<E: Debug>() -> ReconnectPolicy<E> {
+    let is_fatal_f: fn(&E) -> bool = |_| false;
+
+    ExponentialBackoffPolicyBuilder::with_f_rng(is_fatal_f, StdRng::from_entropy())
+        .max_count(MAX_RECONNECT_ATTEMPTS)
+        .build()
+        .expect("Could not build reconnect policy")
+}
+
+/// Backoff between runs of a connection, covering connecting, resyncing and consuming.
+///
+/// A connection that is accepted but fails straight away backs off like one
+/// that could not be made. The backoff only starts over once a run has stayed
+/// up for `HEALTHY_AFTER`.
+pub struct Reconnect {
+    policy: ReconnectPolicy<()>,
+    attempt: u32,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            policy: reconnect_policy(),
+            attempt: 0,
+        }
+    }
+}
+
+impl Reconnect {
+    /// The delay before the next run, given whether the last one was healthy.
+    ///
+    /// Returns `None` once there have been too many failed runs in a row.
+    fn next_delay(&mut self, healthy: bool) -> Option<Duration> {
+        if healthy {
+            *self = Self::default();
+        }
+
+        let action = self.policy.on_err(self.attempt, ());
+
+        self.attempt += 1;
+
+        match action {
+            RetryAction::RetryNow => Some(Duration::from_secs(0)),
+            RetryAction::WaitFor(x) => Some(x),
+            RetryAction::ReturnError(_) => None,
+        }
+    }
+
+    /// Waits before the next run, given when the last one started.
+    ///
+    /// Returns `false` once there have been too many failed runs in a row.
+    pub async fn wait(&mut self, started: Instant) -> bool {
+        match self.next_delay(started.elapsed() >= HEALTHY_AFTER) {
+            Some(x) => {
+                delay_for(x).await;
+
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reconnect, MAX_RECONNECT_ATTEMPTS};
+
+    #[test]
+    fn test_backoff_grows_until_giving_up() {
+        let mut reconnect = Reconnect::default();
+
+        let delays: Vec<_> = (0..MAX_RECONNECT_ATTEMPTS)
+            .map(|_| reconnect.next_delay(false).unwrap())
+            .collect();
+
+        assert!(delays.first() < delays.last());
+
+        assert_eq!(reconnect.next_delay(false), None);
+    }
+
+    #[test]
+    fn test_backoff_resets_after_healthy_run() {
+        let mut reconnect = Reconnect::default();
+
+        let first = reconnect.next_delay(false).unwrap();
+
+        for _ in 1..MAX_RECONNECT_ATTEMPTS {
+            reconnect.next_delay(false).unwrap();
+        }
+
+        let x = reconnect.next_delay(true).unwrap();
+
+        // The first delay is randomized by up to half either way.
+        assert!(x < first * 3);
+        assert_eq!(reconnect.attempt, 1);
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! # Replay
+//!
+//! Every `Message` sent to users is tagged with a monotonically increasing event id
+//! and kept in a bounded in-memory log.
+//!
+//! A client reconnecting with a `Last-Event-ID` header is replayed the messages it missed.
+//! If the gap is no longer covered by the log, it falls back to a full snapshot.
+
+use futures::lock::Mutex;
+use iml_wire_types::warp_drive::Message;
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The number of messages kept for replay.
+pub const REPLAY_LOG_SIZE: usize = 1000;
+
+pub type SharedReplayLog = Arc<Mutex<ReplayLog>>;
+
+#[derive(Debug)]
+pub struct ReplayLog {
+    last_id: u64,
+    capacity: usize,
+    entries: VecDeque<(u64, Message)>,
+}
+
+impl Default for ReplayLog {
+    fn default() -> Self {
+        // Start ids from the current time, so ids handed out by a
+        // previous run are never mistaken for ones from this run.
+        let last_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_micros() as u64)
+            .unwrap_or(0);
+
+        ReplayLog::new(last_id, REPLAY_LOG_SIZE)
+    }
+}
+
+impl ReplayLog {
+    pub fn new(last_id: u64, capacity: usize) -> Self {
+        ReplayLog {
+            last_id,
+            capacity: std::cmp::max(capacity, 1),
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+    /// The id of the most recent message.
+    pub fn last_id(&self) -> u64 {
+        self.last_id
+    }
+    /// Adds a message to the log, returning its id.
+    /// The oldest message is dropped once the log is full.
+    pub fn push(&mut self, msg: Message) -> u64 {
+        self.last_id += 1;
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back((self.last_id, msg));
+
+        self.last_id
+    }
+    /// Returns every message after `last_id`.
+    ///
+    /// Returns `None` if any of them have already been dropped from the log,
+    /// or if `last_id` was not handed out by this log.
+    pub fn since(&self, last_id: u64) -> Option<Vec<(u64, Message)>> {
+        if last_id > self.last_id {
+            return None;
+        }
+
+        let oldest = self
+            .entries
+            .front()
+            .map(|(id, _)| *id)
+            .unwrap_or(self.last_id + 1);
+
+        if last_id + 1 < oldest {
+            return None;
+        }
+
+        Some(
+            self.entries
+                .iter()
+                .filter(|(id, _)| *id > last_id)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplayLog;
+    use iml_wire_types::warp_drive::{Message, RecordChange, RecordId};
+
+    fn msg(id: u32) -> Message {
+        Message::RecordChange(RecordChange::Delete(RecordId::Host(id)))
+    }
+
+    #[test]
+    fn test_since() {
+        let mut log = ReplayLog::new(10, 5);
+
+        for x in 0..3 {
+            log.push(msg(x));
+        }
+
+        assert_eq!(log.last_id(), 13);
+
+        let ids: Vec<_> = log
+            .since(11)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+
+        assert_eq!(ids, vec![12, 13]);
+        assert_eq!(log.since(13).unwrap().len(), 0);
+        assert_eq!(log.since(10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_since_gap_too_large() {
+        let mut log = ReplayLog::new(0, 2);
+
+        for x in 0..5 {
+            log.push(msg(x));
+        }
+
+        assert!(log.since(2).is_none());
+        assert_eq!(log.since(3).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_since_unknown_id() {
+        let log = ReplayLog::new(10, 2);
+
+        assert!(log.since(11).is_none());
+        assert_eq!(log.since(10).unwrap().len(), 0);
+        assert!(log.since(9).is_none());
+    }
+}
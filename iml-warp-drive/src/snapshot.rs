@@ -0,0 +1,295 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! # Snapshot
+//!
+//! Plain JSON views of the current `Cache` and `Locks`,
+//! for tools that don't want to hold an SSE stream open.
+//!
+//! Responses are tagged with the id of the last event sent to users,
+//! so polling with `If-None-Match` is only a lookup until something changes.
+
+use crate::{
+    auth::Session,
+    cache::SharedCache,
+    error::ImlWarpDriveError,
+    locks::SharedLocks,
+    replay::SharedReplayLog,
+    subscription::{RecordKind, Subscription},
+};
+use iml_wire_types::{
+    db::DeviceId,
+    warp_drive::{Cache, RecordId},
+    Fqdn,
+};
+use percent_encoding::percent_decode_str;
+use std::{iter, str::FromStr};
+use warp::{http::StatusCode, Reply};
+
+/// The part of the `Cache` a request is for, parsed from the path after `/cache`:
+///
+/// - `/cache` is the whole `Cache`.
+/// - `/cache/host` is every record of a kind.
+/// - `/cache/host/1` is a single record.
+/// - `/cache/device_host/<fqdn>/<device id>` is a single `DeviceHost`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Snapshot {
+    Cache,
+    Kind(RecordKind),
+    Record(RecordId),
+}
+
+fn decode(x: &str) -> Option<String> {
+    percent_decode_str(x)
+        .decode_utf8()
+        .ok()
+        .map(|x| x.into_owned())
+}
+
+fn record_id(kind: RecordKind, id: &str) -> Option<RecordId> {
+    let x = match kind {
+        RecordKind::ActiveAlert => RecordId::ActiveAlert(id.parse().ok()?),
+        RecordKind::Device => RecordId::Device(DeviceId::from(decode(id)?)),
+        RecordKind::DeviceHost => {
+            let mut xs = id.splitn(2, '/');
+
+            let fqdn = Fqdn(decode(xs.next()?)?);
+
+            RecordId::DeviceHost(DeviceId::from(decode(xs.next()?)?), fqdn)
+        }
+        RecordKind::Filesystem => RecordId::Filesystem(id.parse().ok()?),
+        RecordKind::Host => RecordId::Host(id.parse().ok()?),
+        RecordKind::LnetConfiguration => RecordId::LnetConfiguration(id.parse().ok()?),
+        RecordKind::ManagedTargetMount => RecordId::ManagedTargetMount(id.parse().ok()?),
+        RecordKind::OstPool => RecordId::OstPool(id.parse().ok()?),
+        RecordKind::OstPoolOsts => RecordId::OstPoolOsts(id.parse().ok()?),
+        RecordKind::StratagemConfig => RecordId::StratagemConfig(id.parse().ok()?),
+        RecordKind::Target => RecordId::Target(id.parse().ok()?),
+        RecordKind::Volume => RecordId::Volume(id.parse().ok()?),
+        RecordKind::VolumeNode => RecordId::VolumeNode(id.parse().ok()?),
+    };
+
+    Some(x)
+}
+
+impl FromStr for Snapshot {
+    type Err = ImlWarpDriveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_matches('/');
+
+        if s.is_empty() {
+            return Ok(Snapshot::Cache);
+        }
+
+        let mut xs = s.splitn(2, '/');
+
+        let kind = xs.next().and_then(|x| x.parse().ok());
+
+        let snapshot = match (kind, xs.next()) {
+            (Some(kind), None) => Some(Snapshot::Kind(kind)),
+            (Some(kind), Some(id)) => record_id(kind, id).map(Snapshot::Record),
+            (None, _) => None,
+        };
+
+        snapshot.ok_or_else(|| ImlWarpDriveError::NotFound(format!("Unknown path {}", s)))
+    }
+}
+
+impl Snapshot {
+    /// Looks this snapshot up in `cache`, narrowed down by `subscription`.
+    ///
+    /// A single kind is returned as a map of id to record,
+    /// and a single record as a tagged `Record`.
+    pub fn to_value(
+        &self,
+        cache: &Cache,
+        subscription: &Subscription,
+    ) -> Result<serde_json::Value, ImlWarpDriveError> {
+        match self {
+            Snapshot::Cache => Ok(serde_json::to_value(subscription.subset(cache))?),
+            Snapshot::Kind(kind) => {
+                let wanted = subscription
+                    .kinds
+                    .as_ref()
+                    .map(|xs| xs.contains(kind))
+                    .unwrap_or(true);
+
+                let subscription = Subscription {
+                    kinds: Some(iter::once(*kind).filter(|_| wanted).collect()),
+                    ..subscription.clone()
+                };
+
+                let x = serde_json::to_value(subscription.subset(cache))?
+                    .as_object_mut()
+                    .and_then(|xs| xs.remove(&kind.to_string()))
+                    .unwrap_or_default();
+
+                Ok(x)
+            }
+            Snapshot::Record(id) => {
+                let x = Some(id)
+                    .filter(|id| {
+                        subscription.matches(cache, subscription.scope(cache).as_ref(), id)
+                    })
+                    .and_then(|id| cache.get_record(id))
+                    .ok_or_else(|| {
+                        ImlWarpDriveError::NotFound(format!("Record {:?} not found", id))
+                    })?;
+
+                Ok(serde_json::to_value(x)?)
+            }
+        }
+    }
+}
+
+/// Tags a response with the id of the last event sent to users.
+///
+/// The tag is weak, as records may serialize in a different order.
+/// Redacted responses are tagged separately.
+pub fn etag(event_id: u64, session: &Session) -> String {
+    if session.is_read_only() {
+        format!("W/\"{}-r\"", event_id)
+    } else {
+        format!("W/\"{}\"", event_id)
+    }
+}
+
+/// Does an `If-None-Match` header match `etag`?
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|x| x == "*" || x.trim_start_matches("W/") == etag)
+}
+
+fn reply(etag: String, body: Option<serde_json::Value>) -> Box<dyn Reply> {
+    match body {
+        Some(x) => Box::new(warp::reply::with_header(
+            warp::reply::json(&x),
+            "etag",
+            etag,
+        )),
+        None => Box::new(warp::reply::with_header(
+            warp::reply::with_status(warp::reply(), StatusCode::NOT_MODIFIED),
+            "etag",
+            etag,
+        )),
+    }
+}
+
+/// Replies with part of the `Cache`,
+/// or `304 Not Modified` if `if_none_match` is still current.
+pub async fn cache_reply(
+    snapshot: Snapshot,
+    subscription: Subscription,
+    session: Session,
+    api_cache: SharedCache,
+    replay_log: SharedReplayLog,
+    if_none_match: Option<String>,
+) -> Result<impl Reply, ImlWarpDriveError> {
+    let api_cache = api_cache.lock().await;
+
+    // Messages are sent while holding the cache,
+    // so the tag can't move on while we do.
+    let etag = etag(replay_log.lock().await.last_id(), &session);
+
+    if if_none_match.map(|x| etag_matches(&x, &etag)) == Some(true) {
+        return Ok(reply(etag, None));
+    }
+
+    let body = snapshot.to_value(&api_cache, &subscription)?;
+
+    Ok(reply(etag, Some(body)))
+}
+
+/// Replies with the current `Locks`,
+/// or `304 Not Modified` if `if_none_match` is still current.
+pub async fn locks_reply(
+    subscription: Subscription,
+    session: Session,
+    locks: SharedLocks,
+    api_cache: SharedCache,
+    replay_log: SharedReplayLog,
+    if_none_match: Option<String>,
+) -> Result<impl Reply, ImlWarpDriveError> {
+    let locks = locks.lock().await;
+    let api_cache = api_cache.lock().await;
+
+    let etag = etag(replay_log.lock().await.last_id(), &session);
+
+    if if_none_match.map(|x| etag_matches(&x, &etag)) == Some(true) {
+        return Ok(reply(etag, None));
+    }
+
+    let locks = subscription.filter_locks(&api_cache, &locks);
+
+    let body = serde_json::to_value(session.redact_locks(locks))?;
+
+    Ok(reply(etag, Some(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{etag_matches, Snapshot};
+    use crate::subscription::{RecordKind, Subscription};
+    use iml_wire_types::{
+        db::DeviceId,
+        warp_drive::{Cache, RecordId},
+        Fqdn,
+    };
+
+    #[test]
+    fn test_parse_snapshot() {
+        assert_eq!("".parse::<Snapshot>().unwrap(), Snapshot::Cache);
+        assert_eq!(
+            "/host/".parse::<Snapshot>().unwrap(),
+            Snapshot::Kind(RecordKind::Host)
+        );
+        assert_eq!(
+            "host/3".parse::<Snapshot>().unwrap(),
+            Snapshot::Record(RecordId::Host(3))
+        );
+        assert_eq!(
+            "device_host/mds1.local/lvm%2Fvg1"
+                .parse::<Snapshot>()
+                .unwrap(),
+            Snapshot::Record(RecordId::DeviceHost(
+                DeviceId::from("lvm/vg1".to_string()),
+                Fqdn("mds1.local".into())
+            ))
+        );
+
+        assert!("hosts".parse::<Snapshot>().is_err());
+        assert!("host/abc".parse::<Snapshot>().is_err());
+        assert!("device_host/mds1.local".parse::<Snapshot>().is_err());
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches("W/\"5\"", "W/\"5\""));
+        assert!(etag_matches("\"4\", \"5\"", "W/\"5\""));
+        assert!(etag_matches("*", "W/\"5\""));
+        assert!(!etag_matches("W/\"4\"", "W/\"5\""));
+        assert!(!etag_matches("W/\"5-r\"", "W/\"5\""));
+    }
+
+    #[test]
+    fn test_missing_records() {
+        let cache = Cache::default();
+        let subscription = Subscription::default();
+
+        let x = Snapshot::Kind(RecordKind::Host)
+            .to_value(&cache, &subscription)
+            .unwrap();
+
+        assert_eq!(x, serde_json::json!({}));
+
+        assert!(Snapshot::Record(RecordId::Host(1))
+            .to_value(&cache, &subscription)
+            .is_err());
+    }
+}
@@ -0,0 +1,1005 @@
+// Copyright (c) 2019 DDN. All rights reserved.
+// Use of this source code is governed by a MIT-style
+// license that can be found in the LICENSE file.
+
+//! # Subscription
+//!
+//! Lets a client narrow the warp-drive stream down to the records it cares about.
+//!
+//! A `Subscription` is built from the query parameters of the SSE route:
+//!
+//! - `kinds`: comma separated record kinds, i.e. `filesystem,target,ost_pool`
+//! - `fs_id`: only records belonging to the given filesystem
+//! - `host_ids`: comma separated host ids, only records belonging to the given hosts
+//!
+//! Filesystem and host filters pull in dependent records, so subscribing to a filesystem
+//! also yields its targets, the hosts serving them, their mounts, volumes and pools.
+//! Relations are resolved against the `Cache` at the time each message is sent,
+//! once per message rather than once per record.
+
+use crate::{error::ImlWarpDriveError, locks::Locks};
+use iml_wire_types::{
+    db::DeviceId,
+    warp_drive::{Cache, Message, Record, RecordChange, RecordId},
+    Fqdn, Target, TargetConfParam, ToCompositeId,
+};
+use std::{
+    collections::HashSet, convert::TryFrom, fmt, hash::Hash, iter::FromIterator, str::FromStr,
+};
+
+/// The kinds of records held in the `Cache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordKind {
+    ActiveAlert,
+    Device,
+    DeviceHost,
+    Filesystem,
+    Host,
+    LnetConfiguration,
+    ManagedTargetMount,
+    OstPool,
+    OstPoolOsts,
+    StratagemConfig,
+    Target,
+    Volume,
+    VolumeNode,
+}
+
+impl FromStr for RecordKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active_alert" => Ok(RecordKind::ActiveAlert),
+            "device" => Ok(RecordKind::Device),
+            "device_host" => Ok(RecordKind::DeviceHost),
+            "filesystem" => Ok(RecordKind::Filesystem),
+            "host" => Ok(RecordKind::Host),
+            "lnet_configuration" => Ok(RecordKind::LnetConfiguration),
+            "managed_target_mount" => Ok(RecordKind::ManagedTargetMount),
+            "ost_pool" => Ok(RecordKind::OstPool),
+            "ost_pool_osts" => Ok(RecordKind::OstPoolOsts),
+            "stratagem_config" => Ok(RecordKind::StratagemConfig),
+            "target" => Ok(RecordKind::Target),
+            "volume" => Ok(RecordKind::Volume),
+            "volume_node" => Ok(RecordKind::VolumeNode),
+            _ => Err("unknown record kind".into()),
+        }
+    }
+}
+
+impl fmt::Display for RecordKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let x = match self {
+            RecordKind::ActiveAlert => "active_alert",
+            RecordKind::Device => "device",
+            RecordKind::DeviceHost => "device_host",
+            RecordKind::Filesystem => "filesystem",
+            RecordKind::Host => "host",
+            RecordKind::LnetConfiguration => "lnet_configuration",
+            RecordKind::ManagedTargetMount => "managed_target_mount",
+            RecordKind::OstPool => "ost_pool",
+            RecordKind::OstPoolOsts => "ost_pool_osts",
+            RecordKind::StratagemConfig => "stratagem_config",
+            RecordKind::Target => "target",
+            RecordKind::Volume => "volume",
+            RecordKind::VolumeNode => "volume_node",
+        };
+
+        write!(f, "{}", x)
+    }
+}
+
+impl From<&RecordId> for RecordKind {
+    fn from(x: &RecordId) -> Self {
+        match x {
+            RecordId::ActiveAlert(_) => RecordKind::ActiveAlert,
+            RecordId::Device(_) => RecordKind::Device,
+            RecordId::DeviceHost(_, _) => RecordKind::DeviceHost,
+            RecordId::Filesystem(_) => RecordKind::Filesystem,
+            RecordId::Host(_) => RecordKind::Host,
+            RecordId::LnetConfiguration(_) => RecordKind::LnetConfiguration,
+            RecordId::ManagedTargetMount(_) => RecordKind::ManagedTargetMount,
+            RecordId::OstPool(_) => RecordKind::OstPool,
+            RecordId::OstPoolOsts(_) => RecordKind::OstPoolOsts,
+            RecordId::StratagemConfig(_) => RecordKind::StratagemConfig,
+            RecordId::Target(_) => RecordKind::Target,
+            RecordId::Volume(_) => RecordKind::Volume,
+            RecordId::VolumeNode(_) => RecordKind::VolumeNode,
+        }
+    }
+}
+
+/// Returns the `RecordId` of a `Record`.
+pub fn record_id(x: &Record) -> RecordId {
+    match x {
+        Record::ActiveAlert(x) => RecordId::ActiveAlert(x.id),
+        Record::Device(x) => RecordId::Device(x.id.clone()),
+        Record::DeviceHost(x) => RecordId::DeviceHost(x.device_id.clone(), x.fqdn.clone()),
+        Record::Filesystem(x) => RecordId::Filesystem(x.id),
+        Record::Host(x) => RecordId::Host(x.id),
+        Record::LnetConfiguration(x) => RecordId::LnetConfiguration(x.id),
+        Record::ManagedTargetMount(x) => RecordId::ManagedTargetMount(x.id),
+        Record::OstPool(x) => RecordId::OstPool(x.id),
+        Record::OstPoolOsts(x) => RecordId::OstPoolOsts(x.id),
+        Record::StratagemConfig(x) => RecordId::StratagemConfig(x.id),
+        Record::Target(x) => RecordId::Target(x.id),
+        Record::Volume(x) => RecordId::Volume(x.id),
+        Record::VolumeNode(x) => RecordId::VolumeNode(x.id),
+    }
+}
+
+/// Returns the `RecordId` a `RecordChange` applies to.
+pub fn change_id(x: &RecordChange) -> RecordId {
+    match x {
+        RecordChange::Update(r) => record_id(r),
+        RecordChange::Delete(id) => id.clone(),
+    }
+}
+
+/// The raw query parameters accepted by the SSE route.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+pub struct SubscriptionQuery {
+    pub kinds: Option<String>,
+    pub fs_id: Option<u32>,
+    pub host_ids: Option<String>,
+}
+
+fn parse_list<T, C>(s: &str) -> Result<C, ImlWarpDriveError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+    C: FromIterator<T>,
+{
+    s.split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            x.parse().map_err(|e| {
+                ImlWarpDriveError::InvalidSubscription(format!("Invalid value {}: {}", x, e))
+            })
+        })
+        .collect()
+}
+
+/// What a connected user wants to receive.
+///
+/// The default `Subscription` matches everything.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Subscription {
+    pub kinds: Option<HashSet<RecordKind>>,
+    pub filesystem_id: Option<u32>,
+    pub host_ids: Option<HashSet<u32>>,
+}
+
+impl TryFrom<SubscriptionQuery> for Subscription {
+    type Error = ImlWarpDriveError;
+
+    fn try_from(q: SubscriptionQuery) -> Result<Self, Self::Error> {
+        Ok(Subscription {
+            kinds: q.kinds.as_ref().map(|x| parse_list(x)).transpose()?,
+            filesystem_id: q.fs_id,
+            host_ids: q.host_ids.as_ref().map(|x| parse_list(x)).transpose()?,
+        })
+    }
+}
+
+impl Subscription {
+    /// Resolves the records this `Subscription` is narrowed down to.
+    ///
+    /// This walks the whole `Cache`, so it should be done once per message
+    /// and handed to `matches`. Returns `None` when there is no filesystem or host filter.
+    pub fn scope(&self, cache: &Cache) -> Option<Scope> {
+        let fs_scope = self.filesystem_id.map(|x| Scope::for_filesystem(cache, x));
+        let host_scope = self.host_ids.as_ref().map(|x| Scope::for_hosts(cache, x));
+
+        let scope = match (fs_scope, host_scope) {
+            (Some(a), Some(b)) => a.intersect(b),
+            (Some(a), None) => a,
+            (None, Some(b)) => b,
+            (None, None) => return None,
+        };
+
+        Some(scope.resolve(cache))
+    }
+    fn wants_kind(&self, kind: RecordKind) -> bool {
+        self.kinds.as_ref().map_or(true, |xs| xs.contains(&kind))
+    }
+    /// Computes the subset of the `Cache` matching this `Subscription`.
+    pub fn subset(&self, cache: &Cache) -> Cache {
+        let scope = self.scope(cache);
+
+        let mut out = Cache::default();
+
+        macro_rules! copy {
+            ($field:ident, $kind:ident, $scope_field:ident) => {
+                if self.wants_kind(RecordKind::$kind) {
+                    out.$field = cache
+                        .$field
+                        .iter()
+                        .filter(|(k, _)| {
+                            scope.as_ref().map_or(true, |s| s.$scope_field.contains(k))
+                        })
+                        .map(|(k, v)| (*k, v.clone()))
+                        .collect();
+                }
+            };
+        }
+
+        copy!(filesystem, Filesystem, filesystems);
+        copy!(host, Host, hosts);
+        copy!(lnet_configuration, LnetConfiguration, lnet_configurations);
+        copy!(managed_target_mount, ManagedTargetMount, mounts);
+        copy!(ost_pool, OstPool, ost_pools);
+        copy!(ost_pool_osts, OstPoolOsts, ost_pool_osts);
+        copy!(stratagem_config, StratagemConfig, stratagem_configs);
+        copy!(target, Target, targets);
+        copy!(volume, Volume, volumes);
+        copy!(volume_node, VolumeNode, volume_nodes);
+
+        if self.wants_kind(RecordKind::ActiveAlert) {
+            out.active_alert = cache
+                .active_alert
+                .iter()
+                .filter(|(k, _)| {
+                    scope
+                        .as_ref()
+                        .map_or(true, |s| s.contains_id(cache, &RecordId::ActiveAlert(**k)))
+                })
+                .map(|(k, v)| (*k, v.clone()))
+                .collect();
+        }
+
+        if self.wants_kind(RecordKind::Device) {
+            out.device = cache
+                .device
+                .iter()
+                .filter(|(k, _)| scope.as_ref().map_or(true, |s| s.devices.contains(k)))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+        }
+
+        if self.wants_kind(RecordKind::DeviceHost) {
+            out.device_host = cache
+                .device_host
+                .iter()
+                .filter(|(k, _)| scope.as_ref().map_or(true, |s| s.fqdns.contains(k)))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+        }
+
+        out
+    }
+    /// Does the record with the given id match this `Subscription`?
+    ///
+    /// `scope` is the `Subscription::scope` of `cache`.
+    /// The record is expected to be in the `Cache`, so deletes must be
+    /// checked before they are applied.
+    pub fn matches(&self, cache: &Cache, scope: Option<&Scope>, id: &RecordId) -> bool {
+        if !self.wants_kind(RecordKind::from(id)) {
+            return false;
+        }
+
+        scope.map_or(true, |scope| scope.contains_id(cache, id))
+    }
+    /// Computes the subset of `Locks` held on records matching this `Subscription`.
+    pub fn filter_locks(&self, cache: &Cache, locks: &Locks) -> Locks {
+        let scope = match self.scope(cache) {
+            Some(x) => x,
+            None => return locks.clone(),
+        };
+
+        let ids = scope.composite_ids(cache);
+
+        locks
+            .iter()
+            .filter(|(k, _)| ids.contains(*k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+    fn matches_change(&self, cache: &Cache, scope: Option<&Scope>, x: &RecordChange) -> bool {
+        self.matches(cache, scope, &change_id(x))
+    }
+    /// Narrows a `Message` down to this `Subscription`.
+    ///
+    /// Returns `None` if nothing in the message is of interest.
+    pub fn filter_message(&self, cache: &Cache, msg: &Message) -> Option<Message> {
+        if *self == Subscription::default() {
+            return Some(msg.clone());
+        }
+
+        match msg {
+            Message::Records(x) => Some(Message::Records(self.subset(x))),
+            Message::Locks(x) => Some(Message::Locks(self.filter_locks(cache, x))),
+            Message::LockChange(x) => {
+                let key = x.composite_id().to_string();
+
+                match self.scope(cache) {
+                    Some(scope) if !scope.composite_ids(cache).contains(&key) => None,
+                    _ => Some(msg.clone()),
+                }
+            }
+            Message::RecordChange(x) => {
+                if self.matches_change(cache, self.scope(cache).as_ref(), x) {
+                    Some(msg.clone())
+                } else {
+                    None
+                }
+            }
+            Message::RecordChanges(xs) => {
+                let scope = self.scope(cache);
+
+                let xs: Vec<_> = xs
+                    .iter()
+                    .filter(|x| self.matches_change(cache, scope.as_ref(), x))
+                    .cloned()
+                    .collect();
+
+                if xs.is_empty() {
+                    None
+                } else {
+                    Some(Message::RecordChanges(xs))
+                }
+            }
+        }
+    }
+    /// Narrows a replayed `Message` down to this `Subscription`.
+    ///
+    /// Replayed deletes may refer to records that are no longer in the `Cache`,
+    /// so they are forwarded whenever their kind is wanted.
+    pub fn filter_replayed(&self, cache: &Cache, msg: &Message) -> Option<Message> {
+        match msg {
+            Message::RecordChange(RecordChange::Delete(id)) => {
+                if self.wants_kind(RecordKind::from(id)) {
+                    Some(msg.clone())
+                } else {
+                    None
+                }
+            }
+            Message::RecordChanges(xs) if *self != Subscription::default() => {
+                let scope = self.scope(cache);
+
+                let xs: Vec<_> = xs
+                    .iter()
+                    .filter(|x| match x {
+                        RecordChange::Delete(id) => self.wants_kind(RecordKind::from(id)),
+                        RecordChange::Update(_) => self.matches_change(cache, scope.as_ref(), x),
+                    })
+                    .cloned()
+                    .collect();
+
+                if xs.is_empty() {
+                    None
+                } else {
+                    Some(Message::RecordChanges(xs))
+                }
+            }
+            _ => self.filter_message(cache, msg),
+        }
+    }
+}
+
+fn target_filesystems(x: &Target<TargetConfParam>) -> impl Iterator<Item = u32> + '_ {
+    x.filesystem_id
+        .into_iter()
+        .chain(x.filesystems.iter().flatten().map(|fs| fs.id))
+}
+
+fn intersect<T: Eq + Hash>(a: HashSet<T>, b: &HashSet<T>) -> HashSet<T> {
+    a.into_iter().filter(|x| b.contains(x)).collect()
+}
+
+/// The ids of every record related to a filesystem and / or set of hosts.
+#[derive(Debug, Default)]
+pub struct Scope {
+    filesystems: HashSet<u32>,
+    hosts: HashSet<u32>,
+    lnet_configurations: HashSet<u32>,
+    mounts: HashSet<u32>,
+    ost_pools: HashSet<u32>,
+    ost_pool_osts: HashSet<u32>,
+    stratagem_configs: HashSet<u32>,
+    targets: HashSet<u32>,
+    volumes: HashSet<u32>,
+    volume_nodes: HashSet<u32>,
+    fqdns: HashSet<Fqdn>,
+    devices: HashSet<DeviceId>,
+    resource_uris: HashSet<String>,
+}
+
+impl Scope {
+    fn for_filesystem(cache: &Cache, fs_id: u32) -> Self {
+        let targets: HashSet<u32> = cache
+            .target
+            .values()
+            .filter(|x| target_filesystems(x).any(|id| id == fs_id))
+            .map(|x| x.id)
+            .collect();
+
+        let mut scope = Scope::from_targets(cache, targets);
+
+        scope.filesystems = cache
+            .filesystem
+            .keys()
+            .filter(|id| **id == fs_id)
+            .copied()
+            .collect();
+
+        scope.with_filesystem_records(cache)
+    }
+    fn for_hosts(cache: &Cache, host_ids: &HashSet<u32>) -> Self {
+        let targets: HashSet<u32> = cache
+            .managed_target_mount
+            .values()
+            .filter(|x| host_ids.contains(&x.host_id))
+            .map(|x| x.target_id)
+            .collect();
+
+        let mut scope = Scope::from_targets(cache, targets);
+
+        scope.hosts = host_ids.clone();
+        scope.mounts = cache
+            .managed_target_mount
+            .values()
+            .filter(|x| host_ids.contains(&x.host_id))
+            .map(|x| x.id)
+            .collect();
+        scope.volume_nodes = cache
+            .volume_node
+            .values()
+            .filter(|x| host_ids.contains(&x.host_id))
+            .map(|x| x.id)
+            .collect();
+        scope.volumes = cache
+            .volume_node
+            .values()
+            .filter(|x| scope.volume_nodes.contains(&x.id))
+            .map(|x| x.volume_id)
+            .collect();
+        scope.filesystems = cache
+            .target
+            .values()
+            .filter(|x| scope.targets.contains(&x.id))
+            .flat_map(target_filesystems)
+            .collect();
+
+        scope.with_filesystem_records(cache)
+    }
+    /// Fills in the mounts, hosts, volume nodes and volumes of the given targets.
+    fn from_targets(cache: &Cache, targets: HashSet<u32>) -> Self {
+        let mounts: Vec<_> = cache
+            .managed_target_mount
+            .values()
+            .filter(|x| targets.contains(&x.target_id))
+            .collect();
+
+        let volume_nodes: HashSet<u32> = mounts.iter().map(|x| x.volume_node_id).collect();
+
+        Scope {
+            hosts: mounts.iter().map(|x| x.host_id).collect(),
+            mounts: mounts.iter().map(|x| x.id).collect(),
+            volumes: cache
+                .volume_node
+                .values()
+                .filter(|x| volume_nodes.contains(&x.id))
+                .map(|x| x.volume_id)
+                .collect(),
+            volume_nodes,
+            targets,
+            ..Scope::default()
+        }
+    }
+    /// Fills in the pools and stratagem configurations of the scoped filesystems.
+    fn with_filesystem_records(mut self, cache: &Cache) -> Self {
+        self.ost_pools = cache
+            .ost_pool
+            .values()
+            .filter(|x| self.filesystems.contains(&x.filesystem_id))
+            .map(|x| x.id)
+            .collect();
+        self.ost_pool_osts = cache
+            .ost_pool_osts
+            .values()
+            .filter(|x| self.ost_pools.contains(&x.ostpool_id))
+            .map(|x| x.id)
+            .collect();
+        self.stratagem_configs = cache
+            .stratagem_config
+            .values()
+            .filter(|x| self.filesystems.contains(&x.filesystem_id))
+            .map(|x| x.id)
+            .collect();
+
+        self
+    }
+    /// Fills in the lnet configurations and devices of the scoped hosts,
+    /// and the resource uris used to match alerts.
+    fn resolve(mut self, cache: &Cache) -> Self {
+        self.lnet_configurations = cache
+            .lnet_configuration
+            .values()
+            .filter(|x| self.hosts.contains(&x.host_id))
+            .map(|x| x.id)
+            .collect();
+
+        self.fqdns = self
+            .hosts
+            .iter()
+            .filter_map(|x| cache.host.get(x))
+            .map(|x| Fqdn(x.fqdn.clone()))
+            .collect();
+
+        self.devices = self
+            .fqdns
+            .iter()
+            .filter_map(|x| cache.device_host.get(x))
+            .flat_map(|xs| xs.keys().cloned())
+            .collect();
+
+        self.resource_uris = self
+            .filesystems
+            .iter()
+            .filter_map(|x| cache.filesystem.get(x))
+            .map(|x| x.resource_uri.clone())
+            .chain(
+                self.targets
+                    .iter()
+                    .filter_map(|x| cache.target.get(x))
+                    .map(|x| x.resource_uri.clone()),
+            )
+            .chain(
+                self.hosts
+                    .iter()
+                    .filter_map(|x| cache.host.get(x))
+                    .map(|x| x.resource_uri.clone()),
+            )
+            .collect();
+
+        self
+    }
+    fn intersect(self, other: Scope) -> Self {
+        Scope {
+            filesystems: intersect(self.filesystems, &other.filesystems),
+            hosts: intersect(self.hosts, &other.hosts),
+            mounts: intersect(self.mounts, &other.mounts),
+            ost_pools: intersect(self.ost_pools, &other.ost_pools),
+            ost_pool_osts: intersect(self.ost_pool_osts, &other.ost_pool_osts),
+            stratagem_configs: intersect(self.stratagem_configs, &other.stratagem_configs),
+            targets: intersect(self.targets, &other.targets),
+            volumes: intersect(self.volumes, &other.volumes),
+            volume_nodes: intersect(self.volume_nodes, &other.volume_nodes),
+            ..Scope::default()
+        }
+    }
+    fn contains_id(&self, cache: &Cache, id: &RecordId) -> bool {
+        match id {
+            RecordId::ActiveAlert(id) => cache
+                .active_alert
+                .get(id)
+                .and_then(|x| x.affected.as_ref())
+                .map_or(false, |xs| {
+                    xs.iter().any(|x| self.resource_uris.contains(x))
+                }),
+            RecordId::Device(id) => self.devices.contains(id),
+            RecordId::DeviceHost(_, fqdn) => self.fqdns.contains(fqdn),
+            RecordId::Filesystem(id) => self.filesystems.contains(id),
+            RecordId::Host(id) => self.hosts.contains(id),
+            RecordId::LnetConfiguration(id) => self.lnet_configurations.contains(id),
+            RecordId::ManagedTargetMount(id) => self.mounts.contains(id),
+            RecordId::OstPool(id) => self.ost_pools.contains(id),
+            RecordId::OstPoolOsts(id) => self.ost_pool_osts.contains(id),
+            RecordId::StratagemConfig(id) => self.stratagem_configs.contains(id),
+            RecordId::Target(id) => self.targets.contains(id),
+            RecordId::Volume(id) => self.volumes.contains(id),
+            RecordId::VolumeNode(id) => self.volume_nodes.contains(id),
+        }
+    }
+    /// The keys used in `Locks` for scoped records.
+    fn composite_ids(&self, cache: &Cache) -> HashSet<String> {
+        let fs = self
+            .filesystems
+            .iter()
+            .filter_map(|x| cache.filesystem.get(x))
+            .map(|x| (x.content_type_id, x.id));
+
+        let targets = self
+            .targets
+            .iter()
+            .filter_map(|x| cache.target.get(x))
+            .map(|x| (x.content_type_id, x.id));
+
+        let hosts = self
+            .hosts
+            .iter()
+            .filter_map(|x| cache.host.get(x))
+            .map(|x| (x.content_type_id, x.id));
+
+        let ost_pools = self
+            .ost_pools
+            .iter()
+            .filter_map(|x| cache.ost_pool.get(x))
+            .filter_map(|x| x.content_type_id.map(|ct| (ct, x.id)));
+
+        let lnet = self
+            .lnet_configurations
+            .iter()
+            .filter_map(|x| cache.lnet_configuration.get(x))
+            .filter_map(|x| x.content_type_id.map(|ct| (ct, x.id)));
+
+        fs.chain(targets)
+            .chain(hosts)
+            .chain(ost_pools)
+            .chain(lnet)
+            .map(|(ct, id)| format!("{}:{}", ct, id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecordKind, Subscription, SubscriptionQuery};
+    use iml_wire_types::{
+        db::{ManagedTargetMountRecord, OstPoolOstsRecord, OstPoolRecord, VolumeNodeRecord},
+        warp_drive::{Cache, Message, RecordChange, RecordId},
+        Filesystem, Target, TargetConfParam,
+    };
+    use std::convert::TryFrom;
+
+    fn filesystem(id: u32) -> Filesystem {
+        serde_json::from_value(serde_json::json!({
+            "conf_params": {},
+            "content_type_id": 1,
+            "id": id,
+            "immutable_state": false,
+            "label": format!("fs{}", id),
+            "mdts": [],
+            "mgt": "",
+            "mount_command": "",
+            "mount_path": "",
+            "name": format!("fs{}", id),
+            "osts": [],
+            "resource_uri": format!("/api/filesystem/{}/", id),
+            "state": "available",
+            "state_modified_at": ""
+        }))
+        .unwrap()
+    }
+
+    fn target(id: u32, fs_id: u32) -> Target<TargetConfParam> {
+        serde_json::from_value(serde_json::json!({
+            "active_host_name": "",
+            "content_type_id": 2,
+            "failover_server_name": "",
+            "failover_servers": [],
+            "filesystem_id": fs_id,
+            "id": id,
+            "immutable_state": false,
+            "kind": "OST",
+            "label": format!("target{}", id),
+            "name": format!("target{}", id),
+            "primary_server": "",
+            "primary_server_name": "",
+            "resource_uri": format!("/api/target/{}/", id),
+            "state": "mounted",
+            "state_modified_at": "",
+            "volume": "/api/volume/1/",
+            "volume_name": ""
+        }))
+        .unwrap()
+    }
+
+    fn mount(
+        id: u32,
+        host_id: u32,
+        target_id: u32,
+        volume_node_id: u32,
+    ) -> ManagedTargetMountRecord {
+        ManagedTargetMountRecord {
+            id,
+            host_id,
+            mount_point: None,
+            volume_node_id,
+            primary: true,
+            target_id,
+            not_deleted: Some(true),
+        }
+    }
+
+    fn volume_node(id: u32, volume_id: u32, host_id: u32) -> VolumeNodeRecord {
+        VolumeNodeRecord {
+            id,
+            volume_id,
+            host_id,
+            path: format!("/dev/sd{}", id),
+            storage_resource_id: None,
+            primary: true,
+            _use: true,
+            not_deleted: Some(true),
+        }
+    }
+
+    /// Two filesystems:
+    ///
+    /// - fs 1 has targets 10 and 11, mounted on host 100. Target 11 fails over to host 200.
+    /// - fs 2 has target 20, mounted on host 200.
+    ///
+    /// Pool 30 of fs 1 holds target 11, and pool 31 of fs 2 holds target 20.
+    fn cache() -> Cache {
+        let mut cache = Cache::default();
+
+        for id in &[1, 2] {
+            cache.filesystem.insert(*id, filesystem(*id));
+        }
+
+        for (id, fs_id) in &[(10, 1), (11, 1), (20, 2)] {
+            cache.target.insert(*id, target(*id, *fs_id));
+        }
+
+        for (id, host_id, target_id, volume_node_id) in &[
+            (1000, 100, 10, 500),
+            (1001, 100, 11, 501),
+            (1002, 200, 20, 502),
+            (1003, 200, 11, 503),
+        ] {
+            cache
+                .managed_target_mount
+                .insert(*id, mount(*id, *host_id, *target_id, *volume_node_id));
+        }
+
+        for (id, volume_id, host_id) in &[
+            (500, 50, 100),
+            (501, 51, 100),
+            (502, 52, 200),
+            (503, 51, 200),
+        ] {
+            cache
+                .volume_node
+                .insert(*id, volume_node(*id, *volume_id, *host_id));
+        }
+
+        for (id, fs_id) in &[(30, 1), (31, 2)] {
+            cache.ost_pool.insert(
+                *id,
+                OstPoolRecord {
+                    id: *id,
+                    name: format!("pool{}", id),
+                    filesystem_id: *fs_id,
+                    not_deleted: Some(true),
+                    content_type_id: None,
+                },
+            );
+        }
+
+        for (id, ostpool_id, managedost_id) in &[(40, 30, 11), (41, 31, 20)] {
+            cache.ost_pool_osts.insert(
+                *id,
+                OstPoolOstsRecord {
+                    id: *id,
+                    ostpool_id: *ostpool_id,
+                    managedost_id: *managedost_id,
+                },
+            );
+        }
+
+        cache
+    }
+
+    fn matching(sub: &Subscription, cache: &Cache, ids: Vec<RecordId>) -> Vec<RecordId> {
+        let scope = sub.scope(cache);
+
+        ids.into_iter()
+            .filter(|id| sub.matches(cache, scope.as_ref(), id))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let sub = Subscription::try_from(SubscriptionQuery {
+            kinds: Some("filesystem, target,,ost_pool".into()),
+            fs_id: Some(1),
+            host_ids: Some("2,3".into()),
+        })
+        .unwrap();
+
+        assert_eq!(
+            sub.kinds,
+            Some(
+                vec![
+                    RecordKind::Filesystem,
+                    RecordKind::Target,
+                    RecordKind::OstPool
+                ]
+                .into_iter()
+                .collect()
+            )
+        );
+        assert_eq!(sub.filesystem_id, Some(1));
+        assert_eq!(sub.host_ids, Some(vec![2, 3].into_iter().collect()));
+    }
+
+    #[test]
+    fn test_parse_query_bad_kind() {
+        let sub = Subscription::try_from(SubscriptionQuery {
+            kinds: Some("filesystem,nope".into()),
+            ..SubscriptionQuery::default()
+        });
+
+        assert!(sub.is_err());
+    }
+
+    #[test]
+    fn test_default_matches_everything() {
+        let sub = Subscription::try_from(SubscriptionQuery::default()).unwrap();
+
+        assert_eq!(sub, Subscription::default());
+        assert!(sub.matches(&Cache::default(), None, &RecordId::Host(1)));
+    }
+
+    #[test]
+    fn test_kinds_filter() {
+        let sub = Subscription {
+            kinds: Some(vec![RecordKind::Filesystem].into_iter().collect()),
+            ..Subscription::default()
+        };
+
+        assert!(sub.matches(&Cache::default(), None, &RecordId::Filesystem(1)));
+        assert!(!sub.matches(&Cache::default(), None, &RecordId::Host(1)));
+    }
+
+    #[test]
+    fn test_filesystem_scope() {
+        let cache = cache();
+
+        let sub = Subscription {
+            filesystem_id: Some(1),
+            ..Subscription::default()
+        };
+
+        assert_eq!(
+            matching(
+                &sub,
+                &cache,
+                vec![
+                    RecordId::Filesystem(1),
+                    RecordId::Filesystem(2),
+                    RecordId::Target(10),
+                    RecordId::Target(11),
+                    RecordId::Target(20),
+                    RecordId::ManagedTargetMount(1000),
+                    RecordId::ManagedTargetMount(1002),
+                    RecordId::ManagedTargetMount(1003),
+                    RecordId::Host(100),
+                    RecordId::Host(200),
+                    RecordId::VolumeNode(503),
+                    RecordId::VolumeNode(502),
+                    RecordId::Volume(51),
+                    RecordId::Volume(52),
+                    RecordId::OstPool(30),
+                    RecordId::OstPool(31),
+                    RecordId::OstPoolOsts(40),
+                    RecordId::OstPoolOsts(41),
+                ]
+            ),
+            vec![
+                RecordId::Filesystem(1),
+                RecordId::Target(10),
+                RecordId::Target(11),
+                RecordId::ManagedTargetMount(1000),
+                RecordId::ManagedTargetMount(1003),
+                RecordId::Host(100),
+                RecordId::Host(200),
+                RecordId::VolumeNode(503),
+                RecordId::Volume(51),
+                RecordId::OstPool(30),
+                RecordId::OstPoolOsts(40),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_host_scope() {
+        let cache = cache();
+
+        let sub = Subscription {
+            host_ids: Some(vec![100].into_iter().collect()),
+            ..Subscription::default()
+        };
+
+        assert_eq!(
+            matching(
+                &sub,
+                &cache,
+                vec![
+                    RecordId::Host(100),
+                    RecordId::Host(200),
+                    RecordId::Target(10),
+                    RecordId::Target(11),
+                    RecordId::Target(20),
+                    RecordId::ManagedTargetMount(1001),
+                    RecordId::ManagedTargetMount(1003),
+                    RecordId::VolumeNode(500),
+                    RecordId::VolumeNode(503),
+                    RecordId::Volume(50),
+                    RecordId::Volume(52),
+                    RecordId::Filesystem(1),
+                    RecordId::Filesystem(2),
+                    RecordId::OstPool(30),
+                    RecordId::OstPool(31),
+                ]
+            ),
+            vec![
+                RecordId::Host(100),
+                RecordId::Target(10),
+                RecordId::Target(11),
+                RecordId::ManagedTargetMount(1001),
+                RecordId::VolumeNode(500),
+                RecordId::Volume(50),
+                RecordId::Filesystem(1),
+                RecordId::OstPool(30),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filesystem_and_host_scope() {
+        let cache = cache();
+
+        let sub = Subscription {
+            filesystem_id: Some(1),
+            host_ids: Some(vec![200].into_iter().collect()),
+            ..Subscription::default()
+        };
+
+        assert_eq!(
+            matching(
+                &sub,
+                &cache,
+                vec![
+                    RecordId::Host(100),
+                    RecordId::Host(200),
+                    RecordId::Target(10),
+                    RecordId::Target(11),
+                    RecordId::Target(20),
+                    RecordId::ManagedTargetMount(1001),
+                    RecordId::ManagedTargetMount(1003),
+                    RecordId::Filesystem(1),
+                    RecordId::Filesystem(2),
+                ]
+            ),
+            vec![
+                RecordId::Host(200),
+                RecordId::Target(11),
+                RecordId::ManagedTargetMount(1003),
+                RecordId::Filesystem(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filter_record_changes() {
+        let cache = cache();
+
+        let sub = Subscription {
+            filesystem_id: Some(2),
+            ..Subscription::default()
+        };
+
+        let msg = Message::RecordChanges(vec![
+            RecordChange::Delete(RecordId::Target(10)),
+            RecordChange::Delete(RecordId::Target(20)),
+            RecordChange::Delete(RecordId::OstPool(31)),
+        ]);
+
+        match sub.filter_message(&cache, &msg) {
+            Some(Message::RecordChanges(xs)) => assert_eq!(
+                xs.iter().map(super::change_id).collect::<Vec<_>>(),
+                vec![RecordId::Target(20), RecordId::OstPool(31)]
+            ),
+            x => panic!("Expected RecordChanges, got {:?}", x),
+        }
+
+        let msg = Message::RecordChange(RecordChange::Delete(RecordId::Target(10)));
+
+        assert!(sub.filter_message(&cache, &msg).is_none());
+    }
+}
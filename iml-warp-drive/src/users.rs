@@ -2,14 +2,17 @@
 // Use of this source code is governed by a MIT-style
 // license that can be found in the LICENSE file.
 
-use crate::locks::Locks;
-use futures::{
-    channel::{mpsc, oneshot},
-    future::poll_fn,
-    lock::Mutex,
-    Stream, StreamExt,
+use crate::{
+    auth::Session,
+    cache::SharedCache,
+    locks::{Locks, SharedLocks},
+    outbox::{Outbox, Outgoing, OUTBOX_SIZE},
+    replay::SharedReplayLog,
+    subscription::Subscription,
 };
+use futures::{channel::oneshot, future::poll_fn, lock::Mutex, stream, Stream, StreamExt};
 use iml_wire_types::warp_drive::{Cache, Message};
+use parking_lot::Mutex as SyncMutex;
 use std::{
     collections::HashMap,
     sync::{
@@ -22,60 +25,224 @@ use warp::sse::ServerSentEvent;
 /// Global unique user id counter.
 static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
 
-pub type SharedUsers = Arc<Mutex<HashMap<usize, mpsc::UnboundedSender<Message>>>>;
+/// A connected user and the records they have subscribed to.
+///
+/// The outbox is behind a blocking lock, as it is also polled from the user's stream.
+/// It is only ever held to push or pop, never across an `.await`,
+/// so it is safe to take while the async locks on users, caches and logs are held.
+#[derive(Debug)]
+pub struct User {
+    outbox: Arc<SyncMutex<Outbox>>,
+    subscription: Subscription,
+}
+
+impl Drop for User {
+    fn drop(&mut self) {
+        self.outbox.lock().close();
+    }
+}
+
+pub type SharedUsers = Arc<Mutex<HashMap<usize, User>>>;
+
+/// Counts of connected users.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct UserStats {
+    pub connected: usize,
+    /// Users close to, or waiting on, a resync.
+    pub lagging: usize,
+}
+
+pub async fn stats(state: &SharedUsers) -> UserStats {
+    let lock = state.lock().await;
 
+    UserStats {
+        connected: lock.len(),
+        lagging: lock
+            .values()
+            .filter(|x| x.outbox.lock().is_lagging())
+            .count(),
+    }
+}
+
+fn snapshot(
+    subscription: &Subscription,
+    locks: &Locks,
+    api_cache: &Cache,
+    event_id: u64,
+) -> Vec<(u64, Message)> {
+    vec![
+        (event_id, Message::Records(subscription.subset(api_cache))),
+        (
+            event_id,
+            Message::Locks(subscription.filter_locks(api_cache, locks)),
+        ),
+    ]
+}
+
+/// Connects a new user.
+///
+/// If `last_event_id` is still covered by the replay log, only the messages
+/// after it are sent. Otherwise the user starts from a full snapshot.
+///
+/// Everything sent is first redacted for the user's `Session`.
 pub async fn user_connected(
     state: SharedUsers,
-    locks: Locks,
-    api_cache: Cache,
+    replay_log: SharedReplayLog,
+    locks: SharedLocks,
+    api_cache: SharedCache,
+    subscription: Subscription,
+    session: Session,
+    last_event_id: Option<u64>,
 ) -> impl Stream<Item = Result<impl ServerSentEvent, warp::Error>> {
     // Use a counter to assign a new unique ID for this user.
     let id = NEXT_USER_ID.fetch_add(1, Ordering::Relaxed);
 
-    tracing::debug!("User connected {}", id);
+    tracing::debug!(
+        "User connected {} ({}) with subscription {:?}",
+        id,
+        session.username,
+        subscription
+    );
 
-    // Use an unbounded channel to handle buffering and flushing of messages
+    // Use a bounded outbox to handle buffering and flushing of messages
     // to the event source...
-    let (tx, rx) = mpsc::unbounded();
+    let outbox = Arc::new(SyncMutex::new(Outbox::new(OUTBOX_SIZE)));
+
+    {
+        let locks = locks.lock().await;
+        let api_cache = api_cache.lock().await;
+
+        // Hold the replay log until the user is saved,
+        // so no message is missed or sent twice.
+        let replay_log = replay_log.lock().await;
+
+        let xs = match last_event_id.and_then(|x| replay_log.since(x)) {
+            Some(xs) => {
+                tracing::debug!("Replaying {} messages to user {}", xs.len(), id);
+
+                xs.into_iter()
+                    .filter_map(|(event_id, msg)| {
+                        subscription
+                            .filter_replayed(&api_cache, &msg)
+                            .map(|msg| (event_id, msg))
+                    })
+                    .collect()
+            }
+            None => snapshot(&subscription, &locks, &api_cache, replay_log.last_id()),
+        };
 
-    let _ = tx.unbounded_send(Message::Records(api_cache));
-    let _ = tx.unbounded_send(Message::Locks(locks));
+        {
+            let mut outbox = outbox.lock();
 
-    // Save the sender in our list of connected users.
-    state.lock().await.insert(id, tx);
+            for (event_id, msg) in xs {
+                outbox.push(event_id, msg);
+            }
+        }
+
+        // Save the outbox in our list of connected users.
+        state.lock().await.insert(
+            id,
+            User {
+                outbox: Arc::clone(&outbox),
+                subscription: subscription.clone(),
+            },
+        );
+    }
 
     // Make an extra clone of users list to give to our disconnection handler...
     let state2 = Arc::clone(&state);
 
     // Create channel to track disconnecting the receiver side of events.
     // This is little bit tricky.
-    let (mut dtx, mut drx) = oneshot::channel::<()>();
+    let (mut dtx, drx) = oneshot::channel::<()>();
 
     // When `drx` is dropped then `dtx` will be canceled.
     // We can track it to make sure when the user disconnects.
     tokio::spawn(async move {
         poll_fn(move |cx| dtx.poll_canceled(cx)).await;
-        drx.close();
         user_disconnected(id, &state2).await;
     });
 
     // Convert messages into Server-Sent Events and return resulting stream.
-    rx.map(|msg| Ok(warp::sse::data(serde_json::to_string(&msg).unwrap())))
+    // A user that fell behind is sent a fresh snapshot in place of what it missed.
+    stream::poll_fn(move |cx| outbox.lock().poll_next(cx))
+        .then(move |x| {
+            let subscription = subscription.clone();
+            let locks = Arc::clone(&locks);
+            let api_cache = Arc::clone(&api_cache);
+            let replay_log = Arc::clone(&replay_log);
+
+            async move {
+                match x {
+                    Outgoing::Message(event_id, msg) => vec![(event_id, msg)],
+                    Outgoing::Resync => {
+                        tracing::info!("Resyncing user {}", id);
+
+                        snapshot(
+                            &subscription,
+                            &*locks.lock().await,
+                            &*api_cache.lock().await,
+                            replay_log.lock().await.last_id(),
+                        )
+                    }
+                }
+            }
+        })
+        .flat_map(stream::iter)
+        .map(move |(event_id, msg)| {
+            // The stream owns `drx`, so dropping it disconnects the user.
+            let _ = &drx;
+
+            let msg = session.redact(msg);
+
+            Ok((
+                warp::sse::id(event_id.to_string()),
+                warp::sse::data(serde_json::to_string(&msg).unwrap()),
+            ))
+        })
 }
 
-pub async fn send_message(msg: Message, state: SharedUsers) {
+/// Records a message in the replay log and sends it
+/// to every user whose `Subscription` matches it.
+///
+/// `api_cache` is used to resolve the relations between records,
+/// so for deletes it must still contain the deleted record.
+pub async fn send_message(
+    msg: Message,
+    api_cache: &Cache,
+    state: SharedUsers,
+    replay_log: SharedReplayLog,
+) {
     tracing::debug!("Sending message {:?} to users {:?}", msg, state);
 
+    let mut replay_log = replay_log.lock().await;
+
+    let event_id = replay_log.push(msg.clone());
+
     let lock = state.lock().await;
 
-    for (_, tx) in lock.iter() {
-        match tx.unbounded_send(msg.clone()) {
-            Ok(()) => (),
-            Err(_disconnected) => {
-                // The tx is disconnected, our `user_disconnected` code
-                // should be happening in another task, nothing more to
-                // do here.
+    // Users often share a subscription, so each distinct one is only filtered once.
+    let mut filtered: Vec<(&Subscription, Option<Message>)> = vec![];
+
+    for (id, user) in lock.iter() {
+        let x = match filtered.iter().find(|(s, _)| **s == user.subscription) {
+            Some((_, x)) => x.clone(),
+            None => {
+                let x = user.subscription.filter_message(api_cache, &msg);
+
+                filtered.push((&user.subscription, x.clone()));
+
+                x
             }
+        };
+
+        let msg = match x {
+            Some(x) => x,
+            None => continue,
+        };
+
+        if !user.outbox.lock().push(event_id, msg) {
+            tracing::warn!("User {} fell too far behind, dropping pending messages", id);
         }
     }
 }
@@ -45,6 +45,102 @@ impl fmt::Display for Fqdn {
     }
 }
 
+/// The max length of a `MailboxAddress`.
+pub const MAILBOX_ADDRESS_MAX_LEN: usize = 255;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MailboxAddressError {
+    Empty,
+    TooLong(usize),
+    InvalidChar(char),
+    LeadingDot,
+}
+
+impl fmt::Display for MailboxAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MailboxAddressError::Empty => write!(f, "Mailbox address cannot be empty"),
+            MailboxAddressError::TooLong(x) => write!(
+                f,
+                "Mailbox address is {} characters, max is {}",
+                x, MAILBOX_ADDRESS_MAX_LEN
+            ),
+            MailboxAddressError::InvalidChar(c) => {
+                write!(f, "Mailbox address contains invalid character {:?}", c)
+            }
+            MailboxAddressError::LeadingDot => {
+                write!(f, "Mailbox address cannot start with a '.'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MailboxAddressError {}
+
+/// The name of a file-backed mailbox address.
+///
+/// Addresses may only contain ASCII alphanumerics, `-`, `_` and `.`,
+/// and may not start with a `.`. This means they never contain path separators
+/// and can be safely joined onto the mailbox path.
+#[derive(
+    Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Clone, serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "String", into = "String")]
+pub struct MailboxAddress(String);
+
+impl MailboxAddress {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::convert::TryFrom<String> for MailboxAddress {
+    type Error = MailboxAddressError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.is_empty() {
+            return Err(MailboxAddressError::Empty);
+        }
+
+        if s.len() > MAILBOX_ADDRESS_MAX_LEN {
+            return Err(MailboxAddressError::TooLong(s.len()));
+        }
+
+        if let Some(c) = s
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_' || *c == '.'))
+        {
+            return Err(MailboxAddressError::InvalidChar(c));
+        }
+
+        if s.starts_with('.') {
+            return Err(MailboxAddressError::LeadingDot);
+        }
+
+        Ok(Self(s))
+    }
+}
+
+impl std::str::FromStr for MailboxAddress {
+    type Err = MailboxAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        std::convert::TryFrom::try_from(s.to_string())
+    }
+}
+
+impl From<MailboxAddress> for String {
+    fn from(MailboxAddress(s): MailboxAddress) -> Self {
+        s
+    }
+}
+
+impl fmt::Display for MailboxAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 #[serde(transparent)]
 pub struct Id(pub String);
@@ -246,6 +342,21 @@ pub struct ActionResult {
 
 pub type AgentResult = std::result::Result<serde_json::Value, String>;
 
+/// A point in time view of an agent's action queue,
+/// reported through the `action_queue` session.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ActionQueueStats {
+    /// The number of actions currently running.
+    pub running: usize,
+    /// The number of actions waiting to run.
+    pub queued: usize,
+    /// The number of waiting actions, per `ActionName`.
+    pub queued_by_action: HashMap<String, usize>,
+    /// How long, in seconds, the oldest waiting action has been queued.
+    #[serde(default)]
+    pub oldest_queued_secs: Option<u64>,
+}
+
 pub trait ToJsonValue {
     fn to_json_value(&self) -> Result<serde_json::Value, String>;
 }
@@ -445,6 +556,33 @@ impl EndpointName for Host {
     }
 }
 
+/// An LNet configuration record from `/api/lnet_configuration/`
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct LnetConfiguration {
+    pub content_type_id: u32,
+    pub id: u32,
+    pub immutable_state: bool,
+    pub label: String,
+    pub nids: Option<Vec<String>>,
+    pub resource_uri: String,
+    pub state: String,
+    pub state_modified_at: String,
+}
+
+impl FlatQuery for LnetConfiguration {}
+
+impl ToCompositeId for LnetConfiguration {
+    fn composite_id(&self) -> CompositeId {
+        CompositeId(self.content_type_id, self.id)
+    }
+}
+
+impl EndpointName for LnetConfiguration {
+    fn endpoint_name() -> &'static str {
+        "lnet_configuration"
+    }
+}
+
 /// A server profile record from api/server_profile/
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct ServerProfile {
@@ -576,6 +714,33 @@ impl<T> EndpointName for Job<T> {
     }
 }
 
+/// A Step record from `/api/step/`
+///
+/// A `Job` runs its steps in order. A retried step has more than one record.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Step {
+    pub args: HashMap<String, serde_json::Value>,
+    pub backtrace: String,
+    pub class_name: String,
+    pub console: String,
+    pub created_at: String,
+    pub description: String,
+    pub id: u32,
+    pub log: String,
+    pub modified_at: String,
+    pub resource_uri: String,
+    pub result: Option<String>,
+    pub state: String,
+    pub step_count: u32,
+    pub step_index: u32,
+}
+
+impl EndpointName for Step {
+    fn endpoint_name() -> &'static str {
+        "step"
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct FilesystemConfParams {
     #[serde(rename = "llite.max_cached_mb")]
@@ -919,6 +1084,27 @@ pub enum AlertSeverity {
     CRITICAL,
 }
 
+impl fmt::Display for AlertSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::str::FromStr for AlertSeverity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "DEBUG" => Ok(AlertSeverity::DEBUG),
+            "INFO" => Ok(AlertSeverity::INFO),
+            "WARNING" => Ok(AlertSeverity::WARNING),
+            "ERROR" => Ok(AlertSeverity::ERROR),
+            "CRITICAL" => Ok(AlertSeverity::CRITICAL),
+            _ => Err(format!("Unknown alert severity {}", s)),
+        }
+    }
+}
+
 /// An Alert record from /api/alert/
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct Alert {
@@ -976,8 +1162,13 @@ impl EndpointName for StratagemConfiguration {
     }
 }
 
+impl FlatQuery for StratagemConfiguration {}
+
 pub mod db {
-    use crate::{Fqdn, Label};
+    use crate::{
+        warp_drive::Cache, Alert, AlertSeverity, Filesystem, Fqdn, Host, Label, Target,
+        TargetConfParam, Volume, VolumeOrResourceUri,
+    };
     use std::{collections::BTreeSet, fmt, ops::Deref, path::PathBuf};
 
     #[cfg(feature = "postgres-interop")]
@@ -1547,6 +1738,12 @@ pub mod db {
         }
     }
 
+    impl From<String> for DeviceId {
+        fn from(x: String) -> Self {
+            DeviceId(x)
+        }
+    }
+
     impl Deref for DeviceId {
         type Target = String;
 
@@ -1555,7 +1752,7 @@ pub mod db {
         }
     }
 
-    #[derive(Debug, Default, PartialEq, Eq)]
+    #[derive(Debug, Default, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
     pub struct DeviceIds(pub BTreeSet<DeviceId>);
 
     impl Deref for DeviceIds {
@@ -1599,9 +1796,39 @@ pub mod db {
         }
     }
 
-    #[derive(Debug, PartialEq, Eq)]
+    /// The size of a `Device` in bytes.
+    /// This is stored as text, as it may not fit in a `bigint`.
+    #[derive(Debug, PartialEq, Eq, Clone)]
     pub struct Size(pub u64);
 
+    impl serde::Serialize for Size {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            serializer.serialize_str(&self.0.to_string())
+        }
+    }
+
+    impl<'de> serde::Deserialize<'de> for Size {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            #[derive(serde::Deserialize)]
+            #[serde(untagged)]
+            enum Repr {
+                Text(String),
+                Number(u64),
+            }
+
+            match Repr::deserialize(deserializer)? {
+                Repr::Text(x) => x.parse().map(Size).map_err(serde::de::Error::custom),
+                Repr::Number(x) => Ok(Size(x)),
+            }
+        }
+    }
+
     #[cfg(feature = "postgres-interop")]
     impl ToSql for Size {
         fn to_sql(
@@ -1638,15 +1865,34 @@ pub mod db {
     }
 
     /// The current type of Devices we support
-    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize)]
+    #[derive(
+        Debug,
+        PartialEq,
+        Eq,
+        PartialOrd,
+        Ord,
+        Hash,
+        Clone,
+        Copy,
+        serde::Serialize,
+        serde::Deserialize,
+    )]
     pub enum DeviceType {
+        #[serde(rename = "scsi")]
         ScsiDevice,
+        #[serde(rename = "partition")]
         Partition,
+        #[serde(rename = "mdraid")]
         MdRaid,
+        #[serde(rename = "mpath")]
         Mpath,
+        #[serde(rename = "vg")]
         VolumeGroup,
+        #[serde(rename = "lv")]
         LogicalVolume,
+        #[serde(rename = "zpool")]
         Zpool,
+        #[serde(rename = "dataset")]
         Dataset,
     }
 
@@ -1715,7 +1961,7 @@ pub mod db {
 
     /// A device (Block or Virtual).
     /// These should be unique per cluster
-    #[derive(Debug, PartialEq, Eq)]
+    #[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
     pub struct Device {
         pub id: DeviceId,
         pub size: Size,
@@ -1747,7 +1993,7 @@ pub mod db {
         }
     }
 
-    #[derive(Debug, PartialEq, Eq)]
+    #[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
     pub struct Paths(pub BTreeSet<PathBuf>);
 
     impl Deref for Paths {
@@ -1791,7 +2037,7 @@ pub mod db {
         }
     }
 
-    #[derive(Debug, PartialEq, Eq)]
+    #[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
     pub struct MountPath(pub Option<PathBuf>);
 
     #[cfg(feature = "postgres-interop")]
@@ -1826,7 +2072,7 @@ pub mod db {
 
     /// A pointer to a `Device` present on a host.
     /// Stores mount_path and paths to reach the pointed to `Device`.
-    #[derive(Debug, PartialEq, Eq)]
+    #[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
     pub struct DeviceHost {
         pub device_id: DeviceId,
         pub fqdn: Fqdn,
@@ -1864,6 +2110,520 @@ pub mod db {
             }
         }
     }
+
+    /// The id at the end of an API resource uri, i.e. `1` for `/api/host/1/`.
+    fn resource_uri_id(uri: &str) -> Option<u32> {
+        uri.trim_end_matches('/').rsplit('/').next()?.parse().ok()
+    }
+
+    /// The API label of a host.
+    fn host_label(fqdn: &str) -> String {
+        fqdn.trim_end_matches(".localdomain").to_string()
+    }
+
+    /// Maps a python `logging` level to an `AlertSeverity`.
+    fn alert_severity(level: u32) -> AlertSeverity {
+        match level {
+            x if x >= 50 => AlertSeverity::CRITICAL,
+            x if x >= 40 => AlertSeverity::ERROR,
+            x if x >= 30 => AlertSeverity::WARNING,
+            x if x >= 20 => AlertSeverity::INFO,
+            _ => AlertSeverity::DEBUG,
+        }
+    }
+
+    /// Applies the columns of a db row to the matching API record.
+    ///
+    /// Used to keep records that are already in the `Cache` current
+    /// without a round-trip to the API.
+    pub trait ApplyToApiRecord<T> {
+        /// Applies this row to `x`, resolving related records from `cache`.
+        ///
+        /// Returns `false` without touching `x` if a foreign key changed,
+        /// as the API record then has to be fetched again.
+        fn apply_to(&self, x: &mut T, cache: &Cache) -> bool;
+    }
+
+    impl ApplyToApiRecord<Host> for ManagedHostRecord {
+        fn apply_to(&self, x: &mut Host, _: &Cache) -> bool {
+            if self.server_profile_id.as_deref() != Some(x.server_profile.name.as_str()) {
+                return false;
+            }
+
+            x.address = self.address.clone();
+            x.boot_time = self.boot_time.clone();
+            x.corosync_ring0 = self.corosync_ring0.clone();
+            x.fqdn = self.fqdn.clone();
+            x.immutable_state = self.immutable_state;
+            x.install_method = self.install_method.clone();
+            x.label = host_label(&self.fqdn);
+            x.needs_update = self.needs_update;
+            x.nodename = self.nodename.clone();
+            x.properties = self.properties.clone();
+            x.state = self.state.clone();
+            x.state_modified_at = self.state_modified_at.clone();
+
+            true
+        }
+    }
+
+    impl ApplyToApiRecord<Filesystem> for FsRecord {
+        fn apply_to(&self, x: &mut Filesystem, _: &Cache) -> bool {
+            if resource_uri_id(&x.mgt) != Some(self.mgs_id) {
+                return false;
+            }
+
+            x.immutable_state = self.immutable_state;
+            x.label = self.name.clone();
+            x.name = self.name.clone();
+            x.state = self.state.clone();
+            x.state_modified_at = self.state_modified_at.clone();
+
+            true
+        }
+    }
+
+    impl ApplyToApiRecord<Target<TargetConfParam>> for ManagedTargetRecord {
+        fn apply_to(&self, x: &mut Target<TargetConfParam>, cache: &Cache) -> bool {
+            let volume_id = match &x.volume {
+                VolumeOrResourceUri::Volume(v) => Some(v.id),
+                VolumeOrResourceUri::ResourceUri(uri) => resource_uri_id(uri),
+            };
+
+            if volume_id != Some(self.volume_id) {
+                return false;
+            }
+
+            x.ha_label = self.ha_label.clone();
+            x.immutable_state = self.immutable_state;
+            x.inode_count = self.inode_count;
+            x.inode_size = self.inode_size;
+            x.state = self.state.clone();
+            x.state_modified_at = self.state_modified_at.clone();
+            x.uuid = self.uuid.clone();
+
+            if let Some(name) = &self.name {
+                x.label = name.clone();
+                x.name = name.clone();
+            }
+
+            match self.active_mount_id {
+                Some(id) => {
+                    let host = cache
+                        .managed_target_mount
+                        .get(&id)
+                        .and_then(|m| cache.host.get(&m.host_id));
+
+                    // Leave the active host alone if the mount hasn't reached the cache yet.
+                    if let Some(host) = host {
+                        x.active_host = Some(host.resource_uri.clone());
+                        x.active_host_name = host.label.clone();
+                    }
+                }
+                None => {
+                    x.active_host = None;
+                    x.active_host_name = "---".into();
+                }
+            };
+
+            // Names of related records are copied into the target,
+            // so pick up any that changed since it was fetched.
+            if let Some(v) = cache.volume.get(&self.volume_id) {
+                x.volume_name = v.label.clone();
+
+                if let VolumeOrResourceUri::Volume(x) = &mut x.volume {
+                    *x = v.clone();
+                }
+            }
+
+            if let Some(fs) = x.filesystem_id.and_then(|id| cache.filesystem.get(&id)) {
+                x.filesystem_name = Some(fs.name.clone());
+            }
+
+            for fs in x.filesystems.iter_mut().flatten() {
+                if let Some(y) = cache.filesystem.get(&fs.id) {
+                    fs.name = y.name.clone();
+                }
+            }
+
+            true
+        }
+    }
+
+    impl ApplyToApiRecord<Volume> for VolumeRecord {
+        fn apply_to(&self, x: &mut Volume, _: &Cache) -> bool {
+            if x.storage_resource.as_deref().and_then(resource_uri_id) != self.storage_resource_id {
+                return false;
+            }
+
+            x.filesystem_type = self.filesystem_type.clone();
+            x.label = self.label.clone();
+            x.size = self.size.map(|x| x as i64);
+            x.usable_for_lustre = self.usable_for_lustre;
+
+            true
+        }
+    }
+
+    impl ApplyToApiRecord<Alert> for AlertStateRecord {
+        fn apply_to(&self, x: &mut Alert, _: &Cache) -> bool {
+            if x.alert_item_id.map(|x| x as u32) != self.alert_item_id {
+                return false;
+            }
+
+            x._message = self.message.clone();
+            x.active = self.active;
+            x.begin = self.begin.clone();
+            x.dismissed = self.dismissed;
+            x.end = self.end.clone();
+            x.lustre_pid = self.lustre_pid.map(|x| x as i32);
+            x.severity = alert_severity(self.severity);
+
+            if let Some(variant) = &self.variant {
+                x.variant = variant.clone();
+            }
+
+            true
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{
+            AlertStateRecord, ApplyToApiRecord, FsRecord, ManagedHostRecord,
+            ManagedTargetMountRecord, ManagedTargetRecord, VolumeRecord,
+        };
+        use crate::{
+            warp_drive::Cache, Alert, AlertSeverity, Filesystem, Host, Target, TargetConfParam,
+            Volume, VolumeOrResourceUri,
+        };
+        use serde_json::json;
+
+        fn host(id: u32, profile: &str) -> Host {
+            serde_json::from_value(json!({
+                "address": format!("oss{}.local", id),
+                "content_type_id": 1,
+                "corosync_ring0": "",
+                "fqdn": format!("oss{}.localdomain", id),
+                "id": id,
+                "immutable_state": false,
+                "install_method": "existing_keys_choice",
+                "label": format!("oss{}", id),
+                "lnet_configuration": "",
+                "member_of_active_filesystem": false,
+                "needs_update": false,
+                "nodename": format!("oss{}.localdomain", id),
+                "properties": "{}",
+                "resource_uri": format!("/api/host/{}/", id),
+                "server_profile": {
+                    "corosync": false,
+                    "corosync2": true,
+                    "default": false,
+                    "initial_state": "managed",
+                    "managed": true,
+                    "name": profile,
+                    "ntp": true,
+                    "pacemaker": true,
+                    "repolist": [],
+                    "resource_uri": format!("/api/server_profile/{}/", profile),
+                    "ui_description": "",
+                    "ui_name": "",
+                    "user_selectable": true,
+                    "worker": false
+                },
+                "state": "managed",
+                "state_modified_at": ""
+            }))
+            .unwrap()
+        }
+
+        fn host_record(id: u32, profile: &str, state: &str) -> ManagedHostRecord {
+            serde_json::from_value(json!({
+                "id": id,
+                "state_modified_at": "2019-12-01T00:00:00Z",
+                "state": state,
+                "immutable_state": false,
+                "not_deleted": true,
+                "content_type_id": 1,
+                "address": format!("oss{}.local", id),
+                "fqdn": format!("oss{}.localdomain", id),
+                "nodename": format!("oss{}.localdomain", id),
+                "server_profile_id": profile,
+                "needs_update": true,
+                "install_method": "existing_keys_choice",
+                "properties": "{}",
+                "corosync_ring0": ""
+            }))
+            .unwrap()
+        }
+
+        fn filesystem(id: u32, name: &str, mgt_id: u32) -> Filesystem {
+            serde_json::from_value(json!({
+                "conf_params": {},
+                "content_type_id": 2,
+                "id": id,
+                "immutable_state": false,
+                "label": name,
+                "mdts": [],
+                "mgt": format!("/api/target/{}/", mgt_id),
+                "mount_command": "",
+                "mount_path": "",
+                "name": name,
+                "osts": [],
+                "resource_uri": format!("/api/filesystem/{}/", id),
+                "state": "available",
+                "state_modified_at": ""
+            }))
+            .unwrap()
+        }
+
+        fn fs_record(id: u32, name: &str, mgs_id: u32) -> FsRecord {
+            serde_json::from_value(json!({
+                "id": id,
+                "state_modified_at": "2019-12-01T00:00:00Z",
+                "state": "stopped",
+                "immutable_state": false,
+                "name": name,
+                "mgs_id": mgs_id,
+                "mdt_next_index": 1,
+                "ost_next_index": 1,
+                "not_deleted": true,
+                "content_type_id": 2
+            }))
+            .unwrap()
+        }
+
+        fn volume(id: u32, label: &str, storage_resource_id: Option<u32>) -> Volume {
+            serde_json::from_value(json!({
+                "id": id,
+                "kind": "linux-scsi",
+                "label": label,
+                "resource_uri": format!("/api/volume/{}/", id),
+                "storage_resource": storage_resource_id
+                    .map(|x| format!("/api/storage_resource/{}/", x)),
+                "usable_for_lustre": true,
+                "volume_nodes": []
+            }))
+            .unwrap()
+        }
+
+        fn volume_record(id: u32, label: &str, storage_resource_id: Option<u32>) -> VolumeRecord {
+            serde_json::from_value(json!({
+                "id": id,
+                "storage_resource_id": storage_resource_id,
+                "size": 1024,
+                "label": label,
+                "filesystem_type": "ext4",
+                "not_deleted": true,
+                "usable_for_lustre": false
+            }))
+            .unwrap()
+        }
+
+        fn target(id: u32, fs_id: u32, volume_id: u32) -> Target<TargetConfParam> {
+            serde_json::from_value(json!({
+                "active_host_name": "---",
+                "content_type_id": 3,
+                "failover_server_name": "",
+                "failover_servers": [],
+                "filesystem_id": fs_id,
+                "filesystem_name": "fs",
+                "filesystems": [{ "id": fs_id, "name": "fs" }],
+                "id": id,
+                "immutable_state": false,
+                "kind": "OST",
+                "label": "fs-OST0000",
+                "name": "fs-OST0000",
+                "primary_server": "",
+                "primary_server_name": "",
+                "resource_uri": format!("/api/target/{}/", id),
+                "state": "unmounted",
+                "state_modified_at": "",
+                "volume": format!("/api/volume/{}/", volume_id),
+                "volume_name": ""
+            }))
+            .unwrap()
+        }
+
+        fn target_record(
+            id: u32,
+            volume_id: u32,
+            active_mount_id: Option<u32>,
+        ) -> ManagedTargetRecord {
+            serde_json::from_value(json!({
+                "id": id,
+                "state_modified_at": "2019-12-01T00:00:00Z",
+                "state": "mounted",
+                "immutable_state": false,
+                "name": "fs2-OST0000",
+                "uuid": "abc",
+                "ha_label": "fs2-OST0000_a1",
+                "volume_id": volume_id,
+                "inode_size": 512,
+                "bytes_per_inode": null,
+                "inode_count": 100,
+                "reformat": false,
+                "active_mount_id": active_mount_id,
+                "not_deleted": true,
+                "content_type_id": 3
+            }))
+            .unwrap()
+        }
+
+        fn alert(id: u32, alert_item_id: i32) -> Alert {
+            serde_json::from_value(json!({
+                "_message": null,
+                "active": true,
+                "alert_item": format!("/api/host/{}/", alert_item_id),
+                "alert_item_id": alert_item_id,
+                "alert_item_str": "",
+                "alert_type": "HostOfflineAlert",
+                "begin": "2019-12-01T00:00:00Z",
+                "dismissed": false,
+                "id": id,
+                "message": "",
+                "record_type": "AlertState",
+                "resource_uri": format!("/api/alert/{}/", id),
+                "severity": "WARNING",
+                "variant": "{}"
+            }))
+            .unwrap()
+        }
+
+        fn alert_record(id: u32, alert_item_id: u32) -> AlertStateRecord {
+            serde_json::from_value(json!({
+                "id": id,
+                "alert_item_type_id": 1,
+                "alert_item_id": alert_item_id,
+                "alert_type": "HostOfflineAlert",
+                "begin": "2019-12-01T00:00:00Z",
+                "end": "2019-12-02T00:00:00Z",
+                "active": null,
+                "dismissed": false,
+                "severity": 40,
+                "record_type": "HostOfflineAlert",
+                "variant": "{}",
+                "lustre_pid": null,
+                "message": "Host is offline"
+            }))
+            .unwrap()
+        }
+
+        #[test]
+        fn test_apply_host() {
+            let cache = Cache::default();
+
+            let mut x = host(1, "default");
+
+            assert!(host_record(1, "default", "lnet_up").apply_to(&mut x, &cache));
+            assert_eq!(x.state, "lnet_up");
+            assert_eq!(x.label, "oss1");
+            assert!(x.needs_update);
+
+            assert!(!host_record(1, "stratagem_server", "lnet_down").apply_to(&mut x, &cache));
+            assert_eq!(x.state, "lnet_up");
+        }
+
+        #[test]
+        fn test_apply_filesystem() {
+            let cache = Cache::default();
+
+            let mut x = filesystem(1, "fs", 5);
+
+            assert!(fs_record(1, "fs2", 5).apply_to(&mut x, &cache));
+            assert_eq!(x.name, "fs2");
+            assert_eq!(x.label, "fs2");
+            assert_eq!(x.state, "stopped");
+
+            assert!(!fs_record(1, "fs3", 6).apply_to(&mut x, &cache));
+            assert_eq!(x.name, "fs2");
+        }
+
+        #[test]
+        fn test_apply_target() {
+            let mut cache = Cache::default();
+
+            cache.host.insert(1, host(1, "default"));
+            cache.filesystem.insert(1, filesystem(1, "fs2", 5));
+            cache.volume.insert(10, volume(10, "sdb", None));
+            cache.managed_target_mount.insert(
+                20,
+                ManagedTargetMountRecord {
+                    id: 20,
+                    host_id: 1,
+                    mount_point: None,
+                    volume_node_id: 30,
+                    primary: true,
+                    target_id: 7,
+                    not_deleted: Some(true),
+                },
+            );
+
+            let mut x = target(7, 1, 10);
+
+            assert!(target_record(7, 10, Some(20)).apply_to(&mut x, &cache));
+            assert_eq!(x.state, "mounted");
+            assert_eq!(x.name, "fs2-OST0000");
+            assert_eq!(x.active_host, Some("/api/host/1/".into()));
+            assert_eq!(x.active_host_name, "oss1");
+            assert_eq!(x.volume_name, "sdb");
+            assert_eq!(x.filesystem_name, Some("fs2".into()));
+            assert_eq!(x.filesystems.as_ref().unwrap()[0].name, "fs2");
+
+            assert!(target_record(7, 10, None).apply_to(&mut x, &cache));
+            assert_eq!(x.active_host, None);
+            assert_eq!(x.active_host_name, "---");
+
+            let mut x = target(7, 1, 10);
+            x.volume = VolumeOrResourceUri::Volume(volume(10, "old", None));
+
+            assert!(target_record(7, 10, None).apply_to(&mut x, &cache));
+            match x.volume {
+                VolumeOrResourceUri::Volume(v) => assert_eq!(v.label, "sdb"),
+                VolumeOrResourceUri::ResourceUri(_) => panic!("Expected an embedded volume"),
+            };
+
+            let mut x = target(7, 1, 10);
+
+            assert!(!target_record(7, 11, None).apply_to(&mut x, &cache));
+            assert_eq!(x.state, "unmounted");
+        }
+
+        #[test]
+        fn test_apply_volume() {
+            let cache = Cache::default();
+
+            let mut x = volume(1, "sdb", Some(3));
+
+            assert!(volume_record(1, "sdc", Some(3)).apply_to(&mut x, &cache));
+            assert_eq!(x.label, "sdc");
+            assert_eq!(x.size, Some(1024));
+            assert!(!x.usable_for_lustre);
+
+            assert!(!volume_record(1, "sdd", None).apply_to(&mut x, &cache));
+            assert_eq!(x.label, "sdc");
+
+            let mut x = volume(1, "sdb", None);
+
+            assert!(volume_record(1, "sdc", None).apply_to(&mut x, &cache));
+            assert!(!volume_record(1, "sdd", Some(3)).apply_to(&mut x, &cache));
+        }
+
+        #[test]
+        fn test_apply_alert() {
+            let cache = Cache::default();
+
+            let mut x = alert(1, 2);
+
+            assert!(alert_record(1, 2).apply_to(&mut x, &cache));
+            assert_eq!(x.active, None);
+            assert_eq!(x.end, Some("2019-12-02T00:00:00Z".into()));
+            assert_eq!(x.severity, AlertSeverity::ERROR);
+            assert_eq!(x._message, Some("Host is offline".into()));
+
+            assert!(!alert_record(1, 3).apply_to(&mut x, &cache));
+        }
+    }
 }
 
 /// Types used for component checks
@@ -1967,10 +2727,10 @@ impl FlatQuery for OstPool {}
 pub mod warp_drive {
     use crate::{
         db::{
-            Id, LnetConfigurationRecord, ManagedTargetMountRecord, OstPoolOstsRecord,
-            OstPoolRecord, StratagemConfiguration, VolumeNodeRecord,
+            Device, DeviceHost, DeviceId, Id, LnetConfigurationRecord, ManagedTargetMountRecord,
+            OstPoolOstsRecord, OstPoolRecord, StratagemConfiguration, VolumeNodeRecord,
         },
-        Alert, Filesystem, Host, LockChange, Target, TargetConfParam, Volume,
+        Alert, Filesystem, Fqdn, Host, LockChange, Target, TargetConfParam, Volume,
     };
     use std::collections::{HashMap, HashSet};
 
@@ -1980,6 +2740,9 @@ pub mod warp_drive {
     #[derive(Default, serde::Serialize, serde::Deserialize, Debug, Clone)]
     pub struct Cache {
         pub active_alert: HashMap<u32, Alert>,
+        pub device: HashMap<DeviceId, Device>,
+        /// `DeviceHost`s, keyed by host and then by device.
+        pub device_host: HashMap<Fqdn, HashMap<DeviceId, DeviceHost>>,
         pub filesystem: HashMap<u32, Filesystem>,
         pub host: HashMap<u32, Host>,
         pub lnet_configuration: HashMap<u32, LnetConfigurationRecord>,
@@ -1997,6 +2760,20 @@ pub mod warp_drive {
         pub fn remove_record(&mut self, x: &RecordId) -> bool {
             match x {
                 RecordId::ActiveAlert(id) => self.active_alert.remove(&id).is_some(),
+                RecordId::Device(id) => self.device.remove(id).is_some(),
+                RecordId::DeviceHost(id, fqdn) => {
+                    let removed = self
+                        .device_host
+                        .get_mut(fqdn)
+                        .and_then(|xs| xs.remove(id))
+                        .is_some();
+
+                    if self.device_host.get(fqdn).map(HashMap::is_empty) == Some(true) {
+                        self.device_host.remove(fqdn);
+                    }
+
+                    removed
+                }
                 RecordId::Filesystem(id) => self.filesystem.remove(&id).is_some(),
                 RecordId::Host(id) => self.host.remove(&id).is_some(),
                 RecordId::LnetConfiguration(id) => self.lnet_configuration.remove(&id).is_some(),
@@ -2009,12 +2786,86 @@ pub mod warp_drive {
                 RecordId::VolumeNode(id) => self.volume_node.remove(&id).is_some(),
             }
         }
+        /// Returns whether the record is in the cache
+        pub fn contains_record(&self, x: &RecordId) -> bool {
+            match x {
+                RecordId::ActiveAlert(id) => self.active_alert.contains_key(&id),
+                RecordId::Device(id) => self.device.contains_key(id),
+                RecordId::DeviceHost(id, fqdn) => self
+                    .device_host
+                    .get(fqdn)
+                    .map(|xs| xs.contains_key(id))
+                    .unwrap_or(false),
+                RecordId::Filesystem(id) => self.filesystem.contains_key(&id),
+                RecordId::Host(id) => self.host.contains_key(&id),
+                RecordId::LnetConfiguration(id) => self.lnet_configuration.contains_key(&id),
+                RecordId::ManagedTargetMount(id) => self.managed_target_mount.contains_key(&id),
+                RecordId::OstPool(id) => self.ost_pool.contains_key(&id),
+                RecordId::OstPoolOsts(id) => self.ost_pool_osts.contains_key(&id),
+                RecordId::StratagemConfig(id) => self.stratagem_config.contains_key(&id),
+                RecordId::Target(id) => self.target.contains_key(&id),
+                RecordId::Volume(id) => self.volume.contains_key(&id),
+                RecordId::VolumeNode(id) => self.volume_node.contains_key(&id),
+            }
+        }
+        /// Returns a copy of the record, if it is in the cache
+        pub fn get_record(&self, x: &RecordId) -> Option<Record> {
+            match x {
+                RecordId::ActiveAlert(id) => {
+                    self.active_alert.get(id).cloned().map(Record::ActiveAlert)
+                }
+                RecordId::Device(id) => self.device.get(id).cloned().map(Record::Device),
+                RecordId::DeviceHost(id, fqdn) => self
+                    .device_host
+                    .get(fqdn)
+                    .and_then(|xs| xs.get(id))
+                    .cloned()
+                    .map(Record::DeviceHost),
+                RecordId::Filesystem(id) => {
+                    self.filesystem.get(id).cloned().map(Record::Filesystem)
+                }
+                RecordId::Host(id) => self.host.get(id).cloned().map(Record::Host),
+                RecordId::LnetConfiguration(id) => self
+                    .lnet_configuration
+                    .get(id)
+                    .cloned()
+                    .map(Record::LnetConfiguration),
+                RecordId::ManagedTargetMount(id) => self
+                    .managed_target_mount
+                    .get(id)
+                    .cloned()
+                    .map(Record::ManagedTargetMount),
+                RecordId::OstPool(id) => self.ost_pool.get(id).cloned().map(Record::OstPool),
+                RecordId::OstPoolOsts(id) => {
+                    self.ost_pool_osts.get(id).cloned().map(Record::OstPoolOsts)
+                }
+                RecordId::StratagemConfig(id) => self
+                    .stratagem_config
+                    .get(id)
+                    .cloned()
+                    .map(Record::StratagemConfig),
+                RecordId::Target(id) => self.target.get(id).cloned().map(Record::Target),
+                RecordId::Volume(id) => self.volume.get(id).cloned().map(Record::Volume),
+                RecordId::VolumeNode(id) => {
+                    self.volume_node.get(id).cloned().map(Record::VolumeNode)
+                }
+            }
+        }
         /// Inserts the record into the cache
         pub fn insert_record(&mut self, x: Record) {
             match x {
                 Record::ActiveAlert(x) => {
                     self.active_alert.insert(x.id, x);
                 }
+                Record::Device(x) => {
+                    self.device.insert(x.id.clone(), x);
+                }
+                Record::DeviceHost(x) => {
+                    self.device_host
+                        .entry(x.fqdn.clone())
+                        .or_insert_with(HashMap::new)
+                        .insert(x.device_id.clone(), x);
+                }
                 Record::Filesystem(x) => {
                     self.filesystem.insert(x.id, x);
                 }
@@ -2053,6 +2904,8 @@ pub mod warp_drive {
     #[serde(tag = "tag", content = "payload")]
     pub enum Record {
         ActiveAlert(Alert),
+        Device(Device),
+        DeviceHost(DeviceHost),
         Filesystem(Filesystem),
         Host(Host),
         ManagedTargetMount(ManagedTargetMountRecord),
@@ -2065,10 +2918,12 @@ pub mod warp_drive {
         LnetConfiguration(LnetConfigurationRecord),
     }
 
-    #[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+    #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Hash)]
     #[serde(tag = "tag", content = "payload")]
     pub enum RecordId {
         ActiveAlert(u32),
+        Device(DeviceId),
+        DeviceHost(DeviceId, Fqdn),
         Filesystem(u32),
         Host(u32),
         ManagedTargetMount(u32),
@@ -2093,7 +2948,83 @@ pub mod warp_drive {
     #[serde(tag = "tag", content = "payload")]
     pub enum Message {
         Locks(Locks),
+        LockChange(LockChange),
         Records(Cache),
         RecordChange(RecordChange),
+        /// A batch of `RecordChange`s, in the order they were made.
+        RecordChanges(Vec<RecordChange>),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MailboxAddress, MailboxAddressError, MAILBOX_ADDRESS_MAX_LEN};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_valid_mailbox_addresses() {
+        let mailbox_path = PathBuf::from("/var/spool/iml/mailbox");
+
+        for x in &[
+            "foo",
+            "1234-warn_purge_times-fids_expired",
+            "report.txt",
+            "a..b",
+        ] {
+            let address: MailboxAddress = x.parse().unwrap();
+
+            assert_eq!(
+                mailbox_path.join(address.as_str()).parent(),
+                Some(mailbox_path.as_path())
+            );
+        }
+    }
+
+    #[test]
+    fn test_mailbox_address_traversal() {
+        for x in &[
+            "../../etc/foo",
+            "..",
+            ".",
+            "/etc/passwd",
+            "foo/../../bar",
+            "foo/bar",
+            "foo\\bar",
+            ".hidden",
+            "foo\0bar",
+            "foo bar",
+            "",
+        ] {
+            assert!(
+                x.parse::<MailboxAddress>().is_err(),
+                "{:?} should not be a valid address",
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn test_mailbox_address_max_len() {
+        let x = "a".repeat(MAILBOX_ADDRESS_MAX_LEN);
+
+        assert!(x.parse::<MailboxAddress>().is_ok());
+
+        let x = "a".repeat(MAILBOX_ADDRESS_MAX_LEN + 1);
+
+        assert_eq!(
+            x.parse::<MailboxAddress>(),
+            Err(MailboxAddressError::TooLong(MAILBOX_ADDRESS_MAX_LEN + 1))
+        );
+    }
+
+    #[test]
+    fn test_mailbox_address_deserialize() {
+        let x: Result<MailboxAddress, _> = serde_json::from_str("\"../foo\"");
+
+        assert!(x.is_err());
+
+        let x: MailboxAddress = serde_json::from_str("\"foo\"").unwrap();
+
+        assert_eq!(x.as_str(), "foo");
     }
 }
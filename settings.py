@@ -24,6 +24,15 @@ REPO_PATH = "/var/lib/chroma/repo"
 
 MAILBOX_PATH = "/var/spool/iml/mailbox"
 
+# Max bytes a single mailbox address may hold (1 GiB)
+MAILBOX_ADDRESS_QUOTA = 1024 ** 3
+
+# Max bytes the whole mailbox may hold (10 GiB)
+MAILBOX_TOTAL_QUOTA = 10 * 1024 ** 3
+
+# Seconds a mailbox address is kept after its last write (15 days)
+MAILBOX_TTL = 15 * 24 * 60 * 60
+
 HTTP_FRONTEND_PORT = 80
 
 HTTPS_FRONTEND_PORT = os.getenv("HTTPS_FRONTEND_PORT", 443)